@@ -0,0 +1,212 @@
+use std::borrow::Cow;
+
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::graphics::{AmbientOcclusion, GraphicsContext};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub(crate) struct AmbientOcclusionUniforms {
+    pub projection: [f32; 16],
+    pub inverse_projection: [f32; 16],
+    pub radius: f32,
+    pub bias: f32,
+    pub sample_count: u32,
+    pub _padding: u32,
+    pub kernel: [[f32; 4]; GraphicsContext::MAX_AMBIENT_OCCLUSION_SAMPLES],
+}
+
+impl AmbientOcclusionUniforms {
+    pub fn new(settings: &AmbientOcclusion, projection: Mat4) -> Self {
+        let sample_count =
+            (settings.samples as usize).clamp(1, GraphicsContext::MAX_AMBIENT_OCCLUSION_SAMPLES);
+
+        // a fixed sequence, so frames don't flicker
+        let mut state = 0x9e37_79b9_u32;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32
+        };
+
+        // points in the hemisphere around +z, more of them close to the center
+        let mut kernel = [[0.0; 4]; GraphicsContext::MAX_AMBIENT_OCCLUSION_SAMPLES];
+        for (i, point) in kernel.iter_mut().take(sample_count).enumerate() {
+            let direction =
+                Vec3::new(random() * 2.0 - 1.0, random() * 2.0 - 1.0, random()).normalize_or_zero();
+            let t = i as f32 / sample_count as f32;
+            let scale = 0.1 + 0.9 * t * t;
+            *point = (direction * random() * scale).extend(0.0).into();
+        }
+
+        Self {
+            projection: projection.to_cols_array(),
+            inverse_projection: projection.inverse().to_cols_array(),
+            radius: settings.radius,
+            bias: settings.bias,
+            sample_count: sample_count as u32,
+            _padding: 0,
+            kernel,
+        }
+    }
+}
+
+/// The prepass that renders depth and normals, and the passes that compute the
+/// ambient occlusion from them.
+pub(crate) struct AmbientOcclusionPipelines {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub blur_bind_group_layout: wgpu::BindGroupLayout,
+    pub buffer: wgpu::Buffer,
+    pub prepass: wgpu::RenderPipeline,
+    pub occlusion: wgpu::RenderPipeline,
+    pub blur: wgpu::RenderPipeline,
+}
+
+impl AmbientOcclusionPipelines {
+    pub fn new(device: &wgpu::Device, uniform_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        // the blur has its own binding in the shader they share
+        let blur_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                }],
+            });
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ambient Occlusion Buffer"),
+            contents: bytemuck::cast_slice(&[AmbientOcclusionUniforms::new(
+                &AmbientOcclusion::default(),
+                Mat4::IDENTITY,
+            )]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let prepass_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[uniform_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let prepass_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Owned(format!(
+                "{}{}",
+                GraphicsContext::INSTANCE_SHADER_PRELUDE,
+                include_str!("shader/prepass.wgsl")
+            ))),
+        });
+        let prepass = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Prepass Pipeline"),
+            layout: Some(&prepass_layout),
+            vertex: wgpu::VertexState {
+                module: &prepass_shader,
+                entry_point: "vs_main",
+                buffers: &GraphicsContext::VERTEX_BUFFERS,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &prepass_shader,
+                entry_point: "fs_main",
+                targets: &[GraphicsContext::NORMAL_FORMAT.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: GraphicsContext::PREPASS_DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader/ssao.wgsl"))),
+        });
+        let create_pipeline = |label, layout: &wgpu::BindGroupLayout, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets: &[GraphicsContext::AMBIENT_OCCLUSION_FORMAT.into()],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+
+        Self {
+            occlusion: create_pipeline("Ambient Occlusion Pipeline", &bind_group_layout, "fs_main"),
+            blur: create_pipeline(
+                "Ambient Occlusion Blur Pipeline",
+                &blur_bind_group_layout,
+                "fs_blur",
+            ),
+            bind_group_layout,
+            blur_bind_group_layout,
+            buffer,
+            prepass,
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::catalog::{Catalog, Id};
+use crate::graphics::{Material, Mesh, Texture};
+
+/// Identifies an imported asset across imports.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AssetKey {
+    /// Item at `index` of the glTF document loaded from the given path.
+    Gltf(PathBuf, usize),
+    /// Item read from a file outside of any document, so that it can be shared
+    /// between documents (e.g. two files that reference the same image file).
+    /// The packed sampler tells apart different uses of the same file.
+    File(PathBuf, u64),
+}
+
+impl AssetKey {
+    pub fn gltf(path: &Path, index: usize) -> Self {
+        Self::Gltf(path.to_path_buf(), index)
+    }
+}
+
+/// Persistent store of the GPU resources created by the importers, so that
+/// importing the same file twice (or two files sharing a texture) doesn't
+/// upload everything again.
+pub struct AssetCache {
+    textures: Catalog<Texture>,
+    materials: Catalog<Material>,
+    meshes: Catalog<Vec<(Mesh, Material)>>,
+
+    texture_ids: HashMap<AssetKey, Id<Texture>>,
+    material_ids: HashMap<AssetKey, Id<Material>>,
+    mesh_ids: HashMap<AssetKey, Id<Vec<(Mesh, Material)>>>,
+
+    dependencies: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl Default for AssetCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AssetCache {
+    pub fn new() -> Self {
+        Self {
+            textures: Catalog::new(),
+            materials: Catalog::new(),
+            meshes: Catalog::new(),
+            texture_ids: HashMap::new(),
+            material_ids: HashMap::new(),
+            mesh_ids: HashMap::new(),
+            dependencies: HashMap::new(),
+        }
+    }
+
+    pub fn texture(&self, key: &AssetKey) -> Option<Texture> {
+        self.textures
+            .get_opt(self.texture_ids.get(key).copied())
+            .cloned()
+    }
+
+    pub fn material(&self, key: &AssetKey) -> Option<Material> {
+        self.materials
+            .get_opt(self.material_ids.get(key).copied())
+            .cloned()
+    }
+
+    pub fn mesh(&self, key: &AssetKey) -> Option<Vec<(Mesh, Material)>> {
+        self.meshes
+            .get_opt(self.mesh_ids.get(key).copied())
+            .cloned()
+    }
+
+    /// Replaces the texture that was inserted with `key` before, if any.
+    pub fn insert_texture(&mut self, key: AssetKey, texture: Texture) -> Id<Texture> {
+        let id = self.textures.add(texture);
+        if let Some(previous) = self.texture_ids.insert(key, id) {
+            self.textures.remove(previous);
+        }
+        id
+    }
+
+    /// Replaces the material that was inserted with `key` before, if any.
+    pub fn insert_material(&mut self, key: AssetKey, material: Material) -> Id<Material> {
+        let id = self.materials.add(material);
+        if let Some(previous) = self.material_ids.insert(key, id) {
+            self.materials.remove(previous);
+        }
+        id
+    }
+
+    /// Replaces the primitives that were inserted with `key` before, if any.
+    pub fn insert_mesh(
+        &mut self,
+        key: AssetKey,
+        primitives: Vec<(Mesh, Material)>,
+    ) -> Id<Vec<(Mesh, Material)>> {
+        let id = self.meshes.add(primitives);
+        if let Some(previous) = self.mesh_ids.insert(key, id) {
+            self.meshes.remove(previous);
+        }
+        id
+    }
+
+    /// Files that were read when importing `source`, including `source` itself.
+    pub fn dependencies(&self, source: &Path) -> &[PathBuf] {
+        self.dependencies
+            .get(source)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    pub fn add_dependencies(&mut self, source: &Path, files: Vec<PathBuf>) {
+        let dependencies = self.dependencies.entry(source.to_path_buf()).or_default();
+        for file in files {
+            if !dependencies.contains(&file) {
+                dependencies.push(file);
+            }
+        }
+    }
+
+    /// Forgets every asset that was imported from `source` or from one of its
+    /// dependencies, so that importing it again reads everything from disk.
+    /// Handles that were already handed out stay valid.
+    pub fn evict_source(&mut self, source: &Path) {
+        let files = self.dependencies.remove(source).unwrap_or_default();
+        let evicted = |key: &AssetKey| match key {
+            AssetKey::Gltf(path, _) => path == source,
+            AssetKey::File(path, _) => files.contains(path),
+        };
+
+        let textures = &mut self.textures;
+        self.texture_ids.retain(|key, id| {
+            let keep = !evicted(key);
+            if !keep {
+                textures.remove(*id);
+            }
+            keep
+        });
+        let materials = &mut self.materials;
+        self.material_ids.retain(|key, id| {
+            let keep = !evicted(key);
+            if !keep {
+                materials.remove(*id);
+            }
+            keep
+        });
+        let meshes = &mut self.meshes;
+        self.mesh_ids.retain(|key, id| {
+            let keep = !evicted(key);
+            if !keep {
+                meshes.remove(*id);
+            }
+            keep
+        });
+    }
+}
@@ -1,13 +1,18 @@
 use ayude::{
     camera::Camera,
-    graphics::{self, GraphicsContext, Material, TextureDescription},
-    imgui, import_gltf,
-    transform::Transform,
-    Scene,
+    cooked,
+    debug_draw::DebugDraw,
+    graphics::{
+        self, DebugView, Environment, GraphicsContext, Material, PostEffect, TextureDescription,
+        ToneMapping,
+    },
+    hot_reload::AssetReloader,
+    AssetCache, Scene,
 };
 use glam::{Mat4, Vec2, Vec3};
 use rusttype::{Font, Scale};
 use std::{
+    path::Path,
     sync::Arc,
     time::{Duration, Instant},
 };
@@ -24,16 +29,20 @@ pub struct World {
     movement: [f32; 2], // stores WASD input
 
     the_scene: Scene,
-    _the_sphere: Scene,
 
-    the_scene_skin_visualization: Vec<(graphics::UniformBuffer, Material, Scene, usize)>,
+    /// material, transform and depth in the hierarchy of each joint
+    the_scene_skin_visualization: Vec<(Material, Mat4, usize)>,
     visualization_depth: usize,
+    debug: DebugDraw,
 
     test_font_texture: graphics::Texture,
-    test_font_uniform_buffer: graphics::UniformBuffer,
 
     rendering_skin: bool,
 
+    environment: Environment,
+
+    assets: AssetCache,
+    reloader: AssetReloader,
     graphics: GraphicsContext,
 }
 
@@ -58,7 +67,7 @@ fn create_texture_for_text(
     let width = glyphs
         .iter()
         .rev()
-        .map(|g| g.position().x as f32 + g.unpositioned().h_metrics().advance_width)
+        .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
         .next()
         .unwrap_or(0.0)
         .ceil() as usize;
@@ -93,21 +102,23 @@ impl World {
     fn new(graphics: GraphicsContext) -> Self {
         let gltf_file_name = "samples/knight/knight.gltf";
         // let gltf_file_name = "samples/principito_y_el_aviador/scene.gltf";
-        let the_scene = import_gltf::import_default_scene(gltf_file_name, &graphics).unwrap();
-
-        let the_sphere =
-            import_gltf::import_default_scene("samples/sphere.gltf", &graphics).unwrap();
+        let mut assets = AssetCache::new();
+        let the_scene = cooked::import_default_scene_cooked(
+            gltf_file_name,
+            &format!("{}.cooked", gltf_file_name),
+            &graphics,
+            &mut assets,
+        )
+        .unwrap();
 
         let camera = Camera::new(Vec3::from([0.0, 0.0, 37.0]), std::f32::consts::PI, 0.0);
 
         let font = {
             let data = std::fs::read("data/Cousine.ttf").expect("font file should exist");
-            let font = Font::try_from_vec(data).expect("font should load");
-            font
+            Font::try_from_vec(data).expect("font should load")
         };
 
         let test_font_texture = create_texture_for_text(&font, &graphics, "RIGHT NOW.");
-        let test_font_uniform_buffer = graphics.create_uniform_buffer();
 
         let the_scene_skin_visualization = {
             let mut res = vec![];
@@ -118,12 +129,12 @@ impl World {
                     None => continue,
                 };
 
-                for (joint_index, &node_index) in skin.joints.iter().enumerate() {
+                for (joint_index, node_index) in skin.joints.iter().enumerate() {
                     let joint = &scene.nodes[usize::from(node_index)];
 
                     let mut depth = 0;
 
-                    let mut transform = joint.transform.mat4().clone();
+                    let mut transform = joint.transform.mat4();
                     let mut current = joint;
                     'transform: loop {
                         match current.parent {
@@ -133,15 +144,12 @@ impl World {
                             }
                             None => break 'transform,
                         }
-                        transform = transform * current.transform.mat4();
+                        transform *= current.transform.mat4();
                     }
 
                     let ibm = skin.inverse_bind_matrices[joint_index].mat4();
 
-                    let mut joint_scene = the_sphere.duplicate(&graphics);
-                    joint_scene.transform = Transform::from(
-                        transform * ibm.inverse() * Mat4::from_scale(Vec3::new(0.25, 0.25, 0.25)),
-                    );
+                    let joint_transform = transform * ibm.inverse();
 
                     let name = joint.name.clone().unwrap_or(format!("{}", node_index));
                     let name_tex = create_texture_for_text(&font, &graphics, &name);
@@ -151,47 +159,78 @@ impl World {
                         diffuse: Some(name_tex),
                         normal: None,
                         shaded: false,
+                        diffuse_tex_coord: 0,
+                        normal_tex_coord: 0,
+                        cast_shadows: false,
+                        receive_shadows: false,
+                        transparent: false,
+                        shader: None,
                     };
 
-                    let ub = graphics.create_uniform_buffer();
-
-                    res.push((ub, mat, joint_scene, depth));
+                    res.push((mat, joint_transform, depth));
                 }
             }
             res
         };
 
+        let mut reloader = AssetReloader::new(Duration::from_millis(500));
+        reloader.watch(&the_scene, &assets);
+
         // imgui::init();
 
-        let world = World {
+        // lit by the sky when there is one to load
+        let mut environment = Environment::default();
+        let environment_map_path = Path::new("samples/environment.hdr");
+        if environment_map_path.exists() {
+            match graphics.load_environment_map(environment_map_path) {
+                Ok(map) => environment.environment_map = Some(map),
+                Err(e) => log::error!("{}", e),
+            }
+        }
+
+        World {
             camera,
 
             movement: [0.0, 0.0],
 
             the_scene,
-            _the_sphere: the_sphere,
 
             the_scene_skin_visualization,
             visualization_depth: 0,
+            debug: DebugDraw::new(),
 
             test_font_texture,
-            test_font_uniform_buffer,
 
             rendering_skin: false,
 
-            graphics,
-        };
+            environment,
 
-        world
+            assets,
+            reloader,
+            graphics,
+        }
     }
 
     fn update(&mut self, delta: Duration) {
+        // the shapes were drawn by the last render
+        self.debug.advance(delta);
+
         let mov = Vec2::from(self.movement) * delta.as_secs_f32();
         self.camera.drive(mov);
+
+        let errors = self.reloader.reload_changed(
+            &mut [&mut self.the_scene],
+            &self.graphics,
+            &mut self.assets,
+        );
+        for (source, e) in errors {
+            log::error!("Failed to reload '{}': {}", source.display(), e);
+        }
     }
 
     fn render(&mut self, window_dimensions: (i32, i32)) {
         let mut frame = self.graphics.get_current_frame();
+        frame.set_environment(&self.environment);
 
         let perspective = glam::Mat4::perspective_rh_gl(
             std::f32::consts::PI / 3.0,
@@ -207,41 +246,45 @@ impl World {
             diffuse: Some(self.test_font_texture.clone()),
             normal: None,
             shaded: false,
+            diffuse_tex_coord: 0,
+            normal_tex_coord: 0,
+            cast_shadows: false,
+            receive_shadows: false,
+            transparent: false,
+            shader: None,
         };
 
+        for cascade in 0..frame.fit_shadow_cascades(perspective, view) {
+            let mut pass = frame.begin_shadow_pass(cascade);
+            if !self.rendering_skin {
+                self.the_scene.render_shadows(&mut pass);
+            }
+        }
+
         {
-            let mut pass = frame.begin_render_pass();
+            let mut pass = frame.begin_render_pass(perspective, view);
 
             if !self.rendering_skin {
-                self.the_scene.render(&mut pass, perspective, view);
+                self.the_scene.render(&mut pass);
                 let translation = Vec3::new(-1.0, -1.0, 0.0);
-                pass.render_billboard(
-                    &self.test_font_uniform_buffer,
-                    &text_material,
-                    perspective,
-                    view,
-                    translation,
-                    self.camera.transform().position(),
-                );
+                pass.render_billboard(&text_material, translation);
             } else {
-                for (ub, name, scene, depth) in &self.the_scene_skin_visualization {
+                for (name, transform, depth) in &self.the_scene_skin_visualization {
                     if self.visualization_depth >= *depth {
-                        scene.render(&mut pass, perspective, view);
-
-                        let s = scene.transform.scale().y;
-                        let pos = scene.transform.position() + Vec3::new(0.0, s * 2.0, 0.0);
-
-                        pass.render_billboard(
-                            ub,
-                            &name,
-                            perspective,
-                            view,
-                            pos,
-                            self.camera.transform().position(),
-                        );
+                        let position = transform.transform_point3(Vec3::ZERO);
+                        let radius = transform.x_axis.truncate().length() * 0.25;
+                        self.debug
+                            .sphere(position, radius, [1.0, 1.0, 1.0, 1.0]);
+                        self.debug.axes(*transform, radius * 2.0, [1.0; 4]);
+
+                        let pos = position + Vec3::new(0.0, radius * 2.0, 0.0);
+
+                        pass.render_billboard(name, pos);
                     }
                 }
             };
+
+            pass.render_debug(&self.debug);
         }
         frame.submit();
     }
@@ -287,7 +330,7 @@ async fn async_main(event_loop: EventLoop<()>, window: Arc<Window>) {
                 WindowEvent::CloseRequested => {
                     *control_flow = ControlFlow::Exit;
                 }
-                _ => return,
+                _ => {}
             },
             Event::DeviceEvent { event, .. } => match event {
                 DeviceEvent::MouseMotion { delta } => {
@@ -330,13 +373,70 @@ async fn async_main(event_loop: EventLoop<()>, window: Arc<Window>) {
                         game.visualization_depth += 1;
                     }
                     Some(VirtualKeyCode::Left) if input.state == ElementState::Pressed => {
-                        if game.visualization_depth > 0 {
-                            game.visualization_depth -= 1;
+                        game.visualization_depth = game.visualization_depth.saturating_sub(1);
+                    }
+                    Some(VirtualKeyCode::T) if input.state == ElementState::Pressed => {
+                        let environment = &mut game.environment;
+                        environment.tone_mapping = match environment.tone_mapping {
+                            ToneMapping::None => ToneMapping::Reinhard,
+                            ToneMapping::Reinhard => ToneMapping::Aces,
+                            ToneMapping::Aces => ToneMapping::Agx,
+                            ToneMapping::Agx => ToneMapping::None,
+                        };
+                        log::info!("tone mapping: {:?}", environment.tone_mapping);
+                    }
+                    Some(VirtualKeyCode::B) if input.state == ElementState::Pressed => {
+                        let bloom = &mut game.environment.bloom;
+                        bloom.enabled = !bloom.enabled;
+                        log::info!("bloom: {}", bloom.enabled);
+                    }
+                    Some(VirtualKeyCode::O) if input.state == ElementState::Pressed => {
+                        let ambient_occlusion = &mut game.environment.ambient_occlusion;
+                        ambient_occlusion.enabled = !ambient_occlusion.enabled;
+                        log::info!("ambient occlusion: {}", ambient_occlusion.enabled);
+                    }
+                    Some(VirtualKeyCode::F) if input.state == ElementState::Pressed => {
+                        let post_effects = &mut game.environment.post_effects;
+                        let fxaa = post_effects.iter().any(|it| matches!(it, PostEffect::Fxaa));
+                        if fxaa {
+                            post_effects.retain(|it| !matches!(it, PostEffect::Fxaa));
+                        } else {
+                            post_effects.insert(0, PostEffect::Fxaa);
+                        }
+                        log::info!("fxaa: {}", !fxaa);
+                    }
+                    Some(VirtualKeyCode::V) if input.state == ElementState::Pressed => {
+                        // cycles through the debug views, skipping unsupported ones
+                        let views = DebugView::ALL;
+                        let current = game.graphics.debug_view();
+                        let mut index = views.iter().position(|it| *it == current).unwrap_or(0);
+                        loop {
+                            index = (index + 1) % views.len();
+                            match game.graphics.set_debug_view(views[index]) {
+                                Ok(()) => {
+                                    log::info!("debug view: {:?}", views[index]);
+                                    break;
+                                }
+                                Err(e) => log::error!("{}", e),
+                            }
+                        }
+                    }
+                    Some(VirtualKeyCode::M) if input.state == ElementState::Pressed => {
+                        // cycles through the supported multisampling modes
+                        let counts = game.graphics.supported_sample_counts();
+                        let current = counts
+                            .iter()
+                            .position(|it| *it == game.graphics.sample_count())
+                            .unwrap_or(0);
+                        let next = counts[(current + 1) % counts.len()];
+                        match game.graphics.set_sample_count(next) {
+                            Ok(()) => log::info!("MSAA: {} samples", next),
+                            Err(e) => log::error!("{}", e),
                         }
                     }
-                    _ => return,
+                    _ => {}
                 },
-                _ => return,
+                _ => {}
             },
             Event::MainEventsCleared => {
                 let delta = previous_frame_time.elapsed();
@@ -347,7 +447,7 @@ async fn async_main(event_loop: EventLoop<()>, window: Arc<Window>) {
             Event::RedrawRequested(..) => {
                 game.render(get_window_dimensions(&window));
             }
-            _ => return,
+            _ => {}
         }
     });
 }
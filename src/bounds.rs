@@ -0,0 +1,91 @@
+use glam::{Mat4, Vec3, Vec4};
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Smallest box containing every point, or an empty box at the origin if
+    /// there are none.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        let mut points = points.into_iter();
+        let first = match points.next() {
+            Some(it) => it,
+            None => return Self::new(Vec3::ZERO, Vec3::ZERO),
+        };
+        points.fold(Self::new(first, first), |bounds, it| Self {
+            min: bounds.min.min(it),
+            max: bounds.max.max(it),
+        })
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Half of the size in each axis.
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Box containing this one after it's transformed by `matrix`.
+    pub fn transform(&self, matrix: Mat4) -> Self {
+        let center = matrix.transform_point3(self.center());
+        let extents = self.extents();
+        let extents = matrix.x_axis.truncate().abs() * extents.x
+            + matrix.y_axis.truncate().abs() * extents.y
+            + matrix.z_axis.truncate().abs() * extents.z;
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+
+/// The volume visible through a camera, as planes facing inwards.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    /// xyz is the normal and w the distance, left, right, bottom, top, near, far
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    pub fn from_view_projection(view_projection: Mat4) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_projection.row(i));
+        let planes = [w + x, w - x, w + y, w - y, w + z, w - z].map(|it| {
+            let length = it.truncate().length();
+            if length > 0.0 {
+                it / length
+            } else {
+                it
+            }
+        });
+        Self { planes }
+    }
+
+    /// False only when the box is certainly outside, boxes near the corners of
+    /// the frustum may pass.
+    pub fn intersects(&self, bounds: &Aabb) -> bool {
+        let center = bounds.center();
+        let extents = bounds.extents();
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            let radius = extents.dot(normal.abs());
+            normal.dot(center) + plane.w >= -radius
+        })
+    }
+}
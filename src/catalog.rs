@@ -17,6 +17,12 @@ impl<T> std::iter::FromIterator<T> for Catalog<T> {
     }
 }
 
+impl<T> Default for Catalog<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> Catalog<T> {
     pub fn new() -> Self {
         Self {
@@ -44,6 +50,10 @@ impl<T> Catalog<T> {
         id
     }
 
+    pub fn remove(&mut self, id: Id<T>) -> Option<T> {
+        self.items.remove(&id)
+    }
+
     pub fn iter(&self) -> std::collections::hash_map::Values<'_, Id<T>, T> {
         self.items.values()
     }
@@ -61,7 +71,7 @@ pub struct Id<T>(u64, PhantomData<T>);
 
 impl<T> Clone for Id<T> {
     fn clone(&self) -> Self {
-        Self(self.0, PhantomData)
+        *self
     }
 }
 impl<T> Copy for Id<T> {}
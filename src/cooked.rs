@@ -0,0 +1,911 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use glam::Mat4;
+
+use crate::{
+    asset_cache::{AssetCache, AssetKey},
+    graphics::{GraphicsContext, Material, Mesh, TextureDescription, Vertex},
+    import_gltf::{self, ImportGltfError},
+    mmap::Mmap,
+    transform::Transform,
+    Node, NodeIndex, Scene, Skin,
+};
+
+const MAGIC: &[u8; 8] = b"AYUDECKD";
+/// Bumped whenever the layout of cooked files changes, older files are cooked again.
+const VERSION: u32 = 3;
+
+/// Loads the scene from `cooked_file_name` if it is up to date with the files it was
+/// imported from. Otherwise `file_name` is imported and cooked into `cooked_file_name`
+/// for the next time.
+pub fn import_default_scene_cooked(
+    file_name: &str,
+    cooked_file_name: &str,
+    graphics: &GraphicsContext,
+    cache: &mut AssetCache,
+) -> Result<Scene, CookError> {
+    if let Ok(mmap) = Mmap::open(Path::new(cooked_file_name)) {
+        let source = std::fs::canonicalize(file_name).unwrap_or_else(|_| PathBuf::from(file_name));
+        match CookedScene::read(&mmap) {
+            Ok(cooked) if cooked.source != source => log::info!(
+                "Cooked scene '{}' was imported from '{}'",
+                cooked_file_name,
+                cooked.source.display()
+            ),
+            Ok(cooked) if cooked.is_up_to_date() => return Ok(cooked.upload(graphics, cache)),
+            Ok(_) => log::info!("Cooked scene '{}' is out of date", cooked_file_name),
+            Err(e) => log::warn!("Cooked scene '{}' is not valid: {}", cooked_file_name, e),
+        }
+    }
+
+    let (scene, cooked) = import_gltf::import_default_scene_and_cook(file_name, graphics, cache)?;
+    // the new file replaces the old one instead of overwriting it, so a mapping of the
+    // old one never sees it change
+    let temporary_file_name = format!("{}.tmp", cooked_file_name);
+    std::fs::write(&temporary_file_name, cooked.write())?;
+    std::fs::rename(&temporary_file_name, cooked_file_name)?;
+    Ok(scene)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CookError {
+    #[error("io error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("import failed: {0}")]
+    ImportFailed(#[from] ImportGltfError),
+    #[error("not a cooked scene")]
+    WrongMagic,
+    #[error("cooked scene has version {0}, expected {VERSION}")]
+    WrongVersion(u32),
+    #[error("cooked scene ends unexpectedly")]
+    UnexpectedEnd,
+    #[error("cooked scene contains invalid data")]
+    InvalidData,
+}
+
+/// CPU side of everything the glTF importer uploads, in the layout of the cooked file.
+/// Loaded data borrows from the memory mapped file wherever it can.
+#[derive(Default)]
+pub(crate) struct CookedScene<'a> {
+    pub source: PathBuf,
+    /// files the scene was imported from and when they were modified at the time
+    pub dependencies: Vec<(PathBuf, Option<SystemTime>)>,
+    pub textures: Vec<CookedTexture<'a>>,
+    pub materials: Vec<CookedMaterial>,
+    pub meshes: Vec<CookedMesh<'a>>,
+    pub nodes: Vec<CookedNode>,
+    pub root_nodes: Vec<u32>,
+
+    // used while recording an import, to find out what was already recorded
+    pub texture_indices: HashMap<AssetKey, u32>,
+    pub material_indices: HashMap<AssetKey, u32>,
+    pub mesh_indices: HashMap<AssetKey, u32>,
+}
+
+pub(crate) struct CookedTexture<'a> {
+    pub key: AssetKey,
+    pub width: u32,
+    pub height: u32,
+    pub wrap_s: wgpu::AddressMode,
+    pub wrap_t: wgpu::AddressMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mag_filter: wgpu::FilterMode,
+    /// rgba8 texels
+    pub texels: Cow<'a, [u8]>,
+}
+
+pub(crate) struct CookedMaterial {
+    /// the default material of a document has no key
+    pub key: Option<AssetKey>,
+    pub diffuse: Option<u32>,
+    pub normal: Option<u32>,
+    pub base_diffuse_color: [f32; 4],
+    pub shaded: bool,
+    pub diffuse_tex_coord: u32,
+    pub normal_tex_coord: u32,
+    pub transparent: bool,
+}
+
+pub(crate) struct CookedMesh<'a> {
+    pub key: AssetKey,
+    pub primitives: Vec<CookedPrimitive<'a>>,
+}
+
+pub(crate) struct CookedPrimitive<'a> {
+    pub vertices: Cow<'a, [Vertex]>,
+    pub indices: Cow<'a, [u16]>,
+    pub material: u32,
+}
+
+pub(crate) struct CookedNode {
+    pub parent: Option<u32>,
+    pub children: Vec<u32>,
+    pub transform: [f32; 16],
+    pub mesh: Option<u32>,
+    pub instances: Vec<[f32; 16]>,
+    pub skin: Option<CookedSkin>,
+    pub name: Option<String>,
+}
+
+pub(crate) struct CookedSkin {
+    pub joints: Vec<u32>,
+    pub skeleton: Option<u32>,
+    pub inverse_bind_matrices: Vec<[f32; 16]>,
+}
+
+impl<'a> CookedScene<'a> {
+    pub fn is_up_to_date(&self) -> bool {
+        self.dependencies
+            .iter()
+            .all(|(file, modified)| file_modified(file) == *modified)
+    }
+
+    pub fn upload(&self, graphics: &GraphicsContext, cache: &mut AssetCache) -> Scene {
+        let textures = self
+            .textures
+            .iter()
+            .map(|it| match cache.texture(&it.key) {
+                Some(texture) => texture,
+                None => {
+                    let desc = TextureDescription::new(
+                        &it.texels,
+                        it.width,
+                        it.height,
+                        wgpu::TextureFormat::Rgba8Unorm,
+                    )
+                    .wrap_s(it.wrap_s)
+                    .wrap_t(it.wrap_t)
+                    .min_filter(it.min_filter)
+                    .mag_filter(it.mag_filter);
+                    let texture = graphics.create_texture(&desc);
+                    cache.insert_texture(it.key.clone(), texture.clone());
+                    texture
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let materials = self
+            .materials
+            .iter()
+            .map(
+                |it| match it.key.as_ref().and_then(|key| cache.material(key)) {
+                    Some(material) => material,
+                    None => {
+                        let material = Material {
+                            normal: it.normal.map(|i| textures[i as usize].clone()),
+                            diffuse: it.diffuse.map(|i| textures[i as usize].clone()),
+                            base_diffuse_color: it.base_diffuse_color,
+                            shaded: it.shaded,
+                            diffuse_tex_coord: it.diffuse_tex_coord,
+                            normal_tex_coord: it.normal_tex_coord,
+                            cast_shadows: true,
+                            receive_shadows: true,
+                            transparent: it.transparent,
+                            shader: None,
+                        };
+                        if let Some(key) = &it.key {
+                            cache.insert_material(key.clone(), material.clone());
+                        }
+                        material
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+
+        let meshes = self
+            .meshes
+            .iter()
+            .map(|it| match cache.mesh(&it.key) {
+                Some(mesh) => mesh,
+                None => {
+                    let primitives: Vec<(Mesh, Material)> = it
+                        .primitives
+                        .iter()
+                        .map(|p| {
+                            let mesh = graphics.create_mesh(&p.vertices, &p.indices);
+                            (mesh, materials[p.material as usize].clone())
+                        })
+                        .collect();
+                    cache.insert_mesh(it.key.clone(), primitives.clone());
+                    primitives
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|it| Node {
+                parent: it.parent.map(NodeIndex::from),
+                children: it.children.iter().copied().map(NodeIndex::from).collect(),
+                transform: Transform::from(Mat4::from_cols_array(&it.transform)),
+                meshes: match it.mesh {
+                    Some(mesh) => meshes[mesh as usize].clone(),
+                    None => vec![],
+                },
+                instances: match it.instances.is_empty() {
+                    true => None,
+                    false => {
+                        let transforms = it
+                            .instances
+                            .iter()
+                            .map(Mat4::from_cols_array)
+                            .collect::<Vec<_>>();
+                        Some(graphics.create_instance_buffer(&transforms))
+                    }
+                },
+                skin: it.skin.as_ref().map(|skin| Skin {
+                    joints: skin.joints.iter().copied().map(NodeIndex::from).collect(),
+                    skeleton: skin.skeleton.map(NodeIndex::from),
+                    inverse_bind_matrices: skin
+                        .inverse_bind_matrices
+                        .iter()
+                        .map(|it| Transform::from(Mat4::from_cols_array(it)))
+                        .collect(),
+                }),
+                name: it.name.clone(),
+            })
+            .collect();
+
+        let dependencies = self.dependencies.iter().map(|(it, _)| it.clone()).collect();
+        cache.add_dependencies(&self.source, dependencies);
+
+        Scene {
+            nodes,
+            root_nodes: self
+                .root_nodes
+                .iter()
+                .copied()
+                .map(NodeIndex::from)
+                .collect(),
+            transform: Transform::from(Mat4::IDENTITY),
+            source: Some(self.source.clone()),
+        }
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut w = Writer(vec![]);
+        w.0.extend_from_slice(MAGIC);
+        w.u32(VERSION);
+
+        w.path(&self.source);
+        w.u32(self.dependencies.len() as u32);
+        for (file, modified) in &self.dependencies {
+            w.path(file);
+            w.time(*modified);
+        }
+
+        w.u32(self.textures.len() as u32);
+        for texture in &self.textures {
+            w.key(&texture.key);
+            w.u32(texture.width);
+            w.u32(texture.height);
+            w.u8(address_mode_to_u8(texture.wrap_s));
+            w.u8(address_mode_to_u8(texture.wrap_t));
+            w.u8(filter_mode_to_u8(texture.min_filter));
+            w.u8(filter_mode_to_u8(texture.mag_filter));
+            w.bytes(&texture.texels);
+        }
+
+        w.u32(self.materials.len() as u32);
+        for material in &self.materials {
+            match &material.key {
+                Some(key) => {
+                    w.u8(1);
+                    w.key(key);
+                }
+                None => w.u8(0),
+            }
+            w.index(material.diffuse);
+            w.index(material.normal);
+            material.base_diffuse_color.iter().for_each(|it| w.f32(*it));
+            w.u8(material.shaded as u8);
+            w.u32(material.diffuse_tex_coord);
+            w.u32(material.normal_tex_coord);
+            w.u8(material.transparent as u8);
+        }
+
+        w.u32(self.meshes.len() as u32);
+        for mesh in &self.meshes {
+            w.key(&mesh.key);
+            w.u32(mesh.primitives.len() as u32);
+            for primitive in &mesh.primitives {
+                w.bytes(bytemuck::cast_slice(&primitive.vertices));
+                w.bytes(bytemuck::cast_slice(&primitive.indices));
+                w.u32(primitive.material);
+            }
+        }
+
+        w.u32(self.nodes.len() as u32);
+        for node in &self.nodes {
+            w.index(node.parent);
+            w.indices(&node.children);
+            w.matrix(&node.transform);
+            w.index(node.mesh);
+            w.u32(node.instances.len() as u32);
+            node.instances.iter().for_each(|it| w.matrix(it));
+            match &node.skin {
+                Some(skin) => {
+                    w.u8(1);
+                    w.indices(&skin.joints);
+                    w.index(skin.skeleton);
+                    w.u32(skin.inverse_bind_matrices.len() as u32);
+                    skin.inverse_bind_matrices
+                        .iter()
+                        .for_each(|it| w.matrix(it));
+                }
+                None => w.u8(0),
+            }
+            match &node.name {
+                Some(name) => {
+                    w.u8(1);
+                    w.string(name);
+                }
+                None => w.u8(0),
+            }
+        }
+        w.indices(&self.root_nodes);
+
+        w.0
+    }
+
+    pub fn read(data: &'a [u8]) -> Result<Self, CookError> {
+        let mut r = Reader { data, position: 0 };
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(CookError::WrongMagic);
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(CookError::WrongVersion(version));
+        }
+
+        let mut cooked = CookedScene {
+            source: r.path()?,
+            ..Default::default()
+        };
+
+        for _ in 0..r.u32()? {
+            let file = r.path()?;
+            let modified = r.time()?;
+            cooked.dependencies.push((file, modified));
+        }
+
+        for _ in 0..r.u32()? {
+            cooked.textures.push(CookedTexture {
+                key: r.key()?,
+                width: r.u32()?,
+                height: r.u32()?,
+                wrap_s: address_mode_from_u8(r.u8()?)?,
+                wrap_t: address_mode_from_u8(r.u8()?)?,
+                min_filter: filter_mode_from_u8(r.u8()?)?,
+                mag_filter: filter_mode_from_u8(r.u8()?)?,
+                texels: Cow::Borrowed(r.bytes()?),
+            });
+        }
+
+        for _ in 0..r.u32()? {
+            let key = match r.u8()? {
+                0 => None,
+                _ => Some(r.key()?),
+            };
+            cooked.materials.push(CookedMaterial {
+                key,
+                diffuse: r.index()?,
+                normal: r.index()?,
+                base_diffuse_color: [r.f32()?, r.f32()?, r.f32()?, r.f32()?],
+                shaded: r.u8()? != 0,
+                diffuse_tex_coord: r.u32()?,
+                normal_tex_coord: r.u32()?,
+                transparent: r.u8()? != 0,
+            });
+        }
+
+        for _ in 0..r.u32()? {
+            let key = r.key()?;
+            let mut primitives = vec![];
+            for _ in 0..r.u32()? {
+                primitives.push(CookedPrimitive {
+                    vertices: cast_or_copy(r.bytes()?)?,
+                    indices: cast_or_copy(r.bytes()?)?,
+                    material: r.u32()?,
+                });
+            }
+            cooked.meshes.push(CookedMesh { key, primitives });
+        }
+
+        for _ in 0..r.u32()? {
+            let parent = r.index()?;
+            let children = r.indices()?;
+            let transform = r.matrix()?;
+            let mesh = r.index()?;
+            let mut instances = vec![];
+            for _ in 0..r.u32()? {
+                instances.push(r.matrix()?);
+            }
+            let skin = match r.u8()? {
+                0 => None,
+                _ => {
+                    let joints = r.indices()?;
+                    let skeleton = r.index()?;
+                    let mut inverse_bind_matrices = vec![];
+                    for _ in 0..r.u32()? {
+                        inverse_bind_matrices.push(r.matrix()?);
+                    }
+                    Some(CookedSkin {
+                        joints,
+                        skeleton,
+                        inverse_bind_matrices,
+                    })
+                }
+            };
+            let name = match r.u8()? {
+                0 => None,
+                _ => Some(r.string()?),
+            };
+            cooked.nodes.push(CookedNode {
+                parent,
+                children,
+                transform,
+                mesh,
+                instances,
+                skin,
+                name,
+            });
+        }
+        cooked.root_nodes = r.indices()?;
+
+        cooked.validate()?;
+        Ok(cooked)
+    }
+
+    // indices between sections are used without checks when uploading
+    fn validate(&self) -> Result<(), CookError> {
+        let check = |index: Option<u32>, len: usize| match index {
+            Some(i) if i as usize >= len => Err(CookError::InvalidData),
+            _ => Ok(()),
+        };
+        for material in &self.materials {
+            check(material.diffuse, self.textures.len())?;
+            check(material.normal, self.textures.len())?;
+        }
+        for mesh in &self.meshes {
+            for primitive in &mesh.primitives {
+                check(Some(primitive.material), self.materials.len())?;
+            }
+        }
+        let nodes = self.nodes.len();
+        for node in &self.nodes {
+            check(node.mesh, self.meshes.len())?;
+            check(node.parent, nodes)?;
+            for child in &node.children {
+                check(Some(*child), nodes)?;
+            }
+            if let Some(skin) = &node.skin {
+                for joint in &skin.joints {
+                    check(Some(*joint), nodes)?;
+                }
+                check(skin.skeleton, nodes)?;
+            }
+        }
+        for root in &self.root_nodes {
+            check(Some(*root), nodes)?;
+        }
+        for texture in &self.textures {
+            if texture.texels.len() != texture.width as usize * texture.height as usize * 4 {
+                return Err(CookError::InvalidData);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn file_modified(file: &Path) -> Option<SystemTime> {
+    std::fs::metadata(file).and_then(|it| it.modified()).ok()
+}
+
+// mapped files are page aligned and sections are aligned to 4 bytes in the file,
+// so this only copies for data that was read some other way
+fn cast_or_copy<T: bytemuck::Pod>(bytes: &[u8]) -> Result<Cow<'_, [T]>, CookError> {
+    if !bytes.len().is_multiple_of(std::mem::size_of::<T>()) {
+        return Err(CookError::InvalidData);
+    }
+    match bytemuck::try_cast_slice(bytes) {
+        Ok(it) => Ok(Cow::Borrowed(it)),
+        Err(_) => {
+            let mut items = vec![T::zeroed(); bytes.len() / std::mem::size_of::<T>()];
+            bytemuck::cast_slice_mut(&mut items).copy_from_slice(bytes);
+            Ok(Cow::Owned(items))
+        }
+    }
+}
+
+fn address_mode_to_u8(mode: wgpu::AddressMode) -> u8 {
+    match mode {
+        wgpu::AddressMode::ClampToEdge => 0,
+        wgpu::AddressMode::Repeat => 1,
+        wgpu::AddressMode::MirrorRepeat => 2,
+        wgpu::AddressMode::ClampToBorder => 3,
+    }
+}
+
+fn address_mode_from_u8(mode: u8) -> Result<wgpu::AddressMode, CookError> {
+    match mode {
+        0 => Ok(wgpu::AddressMode::ClampToEdge),
+        1 => Ok(wgpu::AddressMode::Repeat),
+        2 => Ok(wgpu::AddressMode::MirrorRepeat),
+        3 => Ok(wgpu::AddressMode::ClampToBorder),
+        _ => Err(CookError::InvalidData),
+    }
+}
+
+fn filter_mode_to_u8(mode: wgpu::FilterMode) -> u8 {
+    match mode {
+        wgpu::FilterMode::Nearest => 0,
+        wgpu::FilterMode::Linear => 1,
+    }
+}
+
+fn filter_mode_from_u8(mode: u8) -> Result<wgpu::FilterMode, CookError> {
+    match mode {
+        0 => Ok(wgpu::FilterMode::Nearest),
+        1 => Ok(wgpu::FilterMode::Linear),
+        _ => Err(CookError::InvalidData),
+    }
+}
+
+// everything is little endian, byte arrays are padded to 4 bytes
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f32(&mut self, v: f32) {
+        self.0.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn bytes(&mut self, v: &[u8]) {
+        self.u64(v.len() as u64);
+        while !self.0.len().is_multiple_of(4) {
+            self.0.push(0);
+        }
+        self.0.extend_from_slice(v);
+    }
+
+    fn string(&mut self, v: &str) {
+        self.bytes(v.as_bytes());
+    }
+
+    fn path(&mut self, v: &Path) {
+        self.string(&v.to_string_lossy());
+    }
+
+    fn time(&mut self, v: Option<SystemTime>) {
+        match v.and_then(|it| it.duration_since(UNIX_EPOCH).ok()) {
+            Some(it) => {
+                self.u8(1);
+                self.u64(it.as_secs());
+                self.u32(it.subsec_nanos());
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn key(&mut self, v: &AssetKey) {
+        match v {
+            AssetKey::Gltf(path, index) => {
+                self.u8(0);
+                self.path(path);
+                self.u64(*index as u64);
+            }
+            AssetKey::File(path, hash) => {
+                self.u8(1);
+                self.path(path);
+                self.u64(*hash);
+            }
+        }
+    }
+
+    fn index(&mut self, v: Option<u32>) {
+        self.u32(v.unwrap_or(u32::MAX));
+    }
+
+    fn indices(&mut self, v: &[u32]) {
+        self.u32(v.len() as u32);
+        v.iter().for_each(|it| self.u32(*it));
+    }
+
+    fn matrix(&mut self, v: &[f32; 16]) {
+        v.iter().for_each(|it| self.f32(*it));
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CookError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .ok_or(CookError::UnexpectedEnd)?;
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or(CookError::UnexpectedEnd)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CookError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, CookError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, CookError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, CookError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, CookError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], CookError> {
+        let len = self.u64()? as usize;
+        self.position = (self.position + 3) & !3;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, CookError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| CookError::InvalidData)
+    }
+
+    fn path(&mut self) -> Result<PathBuf, CookError> {
+        self.string().map(PathBuf::from)
+    }
+
+    fn time(&mut self) -> Result<Option<SystemTime>, CookError> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => {
+                let secs = self.u64()?;
+                let nanos = self.u32()?;
+                if nanos >= 1_000_000_000 {
+                    return Err(CookError::InvalidData);
+                }
+                UNIX_EPOCH
+                    .checked_add(Duration::new(secs, nanos))
+                    .map(Some)
+                    .ok_or(CookError::InvalidData)
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<AssetKey, CookError> {
+        match self.u8()? {
+            0 => Ok(AssetKey::Gltf(self.path()?, self.u64()? as usize)),
+            1 => Ok(AssetKey::File(self.path()?, self.u64()?)),
+            _ => Err(CookError::InvalidData),
+        }
+    }
+
+    fn index(&mut self) -> Result<Option<u32>, CookError> {
+        match self.u32()? {
+            u32::MAX => Ok(None),
+            it => Ok(Some(it)),
+        }
+    }
+
+    fn indices(&mut self) -> Result<Vec<u32>, CookError> {
+        let len = self.u32()?;
+        (0..len).map(|_| self.u32()).collect()
+    }
+
+    fn matrix(&mut self) -> Result<[f32; 16], CookError> {
+        let mut matrix = [0.0; 16];
+        for it in &mut matrix {
+            *it = self.f32()?;
+        }
+        Ok(matrix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32) -> Vertex {
+        Vertex {
+            position: [x, 1.0, 2.0, 1.0],
+            normal: [0.0, 1.0, 0.0],
+            tex_coord: [x, 0.5],
+            tex_coord_1: [0.0, 0.0],
+            color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+
+    fn scene() -> CookedScene<'static> {
+        let mut transform = [0.0; 16];
+        transform[0] = 2.0;
+        CookedScene {
+            source: PathBuf::from("/scenes/box.gltf"),
+            dependencies: vec![
+                (
+                    PathBuf::from("/scenes/box.gltf"),
+                    Some(UNIX_EPOCH + Duration::new(1_600_000_000, 123)),
+                ),
+                (PathBuf::from("/scenes/box.bin"), None),
+            ],
+            textures: vec![CookedTexture {
+                key: AssetKey::File(PathBuf::from("/scenes/box.png"), 0x2901_2901_2601_2601),
+                width: 2,
+                height: 1,
+                wrap_s: wgpu::AddressMode::Repeat,
+                wrap_t: wgpu::AddressMode::ClampToEdge,
+                min_filter: wgpu::FilterMode::Linear,
+                mag_filter: wgpu::FilterMode::Nearest,
+                texels: Cow::Owned(vec![1, 2, 3, 4, 5, 6, 7, 8]),
+            }],
+            materials: vec![CookedMaterial {
+                key: None,
+                diffuse: Some(0),
+                normal: None,
+                base_diffuse_color: [0.5, 0.25, 1.0, 1.0],
+                shaded: true,
+                diffuse_tex_coord: 0,
+                normal_tex_coord: 1,
+                transparent: false,
+            }],
+            meshes: vec![CookedMesh {
+                key: AssetKey::gltf(Path::new("/scenes/box.gltf"), 0),
+                primitives: vec![CookedPrimitive {
+                    vertices: Cow::Owned(vec![vertex(0.0), vertex(1.0), vertex(2.0)]),
+                    indices: Cow::Owned(vec![0, 1, 2]),
+                    material: 0,
+                }],
+            }],
+            nodes: vec![
+                CookedNode {
+                    parent: None,
+                    children: vec![1],
+                    transform,
+                    mesh: Some(0),
+                    instances: vec![],
+                    skin: Some(CookedSkin {
+                        joints: vec![1],
+                        skeleton: Some(0),
+                        inverse_bind_matrices: vec![transform],
+                    }),
+                    name: Some("box".to_string()),
+                },
+                CookedNode {
+                    parent: Some(0),
+                    children: vec![],
+                    transform,
+                    mesh: None,
+                    instances: vec![transform, transform],
+                    skin: None,
+                    name: None,
+                },
+            ],
+            root_nodes: vec![0],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn scenes_read_back_as_written() {
+        let scene = scene();
+        let data = scene.write();
+        let read = CookedScene::read(&data).unwrap();
+
+        assert_eq!(read.source, scene.source);
+        assert_eq!(read.dependencies, scene.dependencies);
+
+        let (texture, expected) = (&read.textures[0], &scene.textures[0]);
+        assert_eq!(texture.key, expected.key);
+        assert_eq!((texture.width, texture.height), (2, 1));
+        assert_eq!(
+            (texture.wrap_s, texture.wrap_t),
+            (expected.wrap_s, expected.wrap_t)
+        );
+        assert_eq!(
+            (texture.min_filter, texture.mag_filter),
+            (expected.min_filter, expected.mag_filter)
+        );
+        assert_eq!(texture.texels, expected.texels);
+
+        let (material, expected) = (&read.materials[0], &scene.materials[0]);
+        assert_eq!(material.key, None);
+        assert_eq!((material.diffuse, material.normal), (Some(0), None));
+        assert_eq!(material.base_diffuse_color, expected.base_diffuse_color);
+        assert!(material.shaded && !material.transparent);
+        assert_eq!(
+            (material.diffuse_tex_coord, material.normal_tex_coord),
+            (0, 1)
+        );
+
+        let (mesh, expected) = (&read.meshes[0], &scene.meshes[0]);
+        assert_eq!(mesh.key, expected.key);
+        let (primitive, expected) = (&mesh.primitives[0], &expected.primitives[0]);
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(&primitive.vertices),
+            bytemuck::cast_slice::<_, u8>(&expected.vertices)
+        );
+        assert_eq!(primitive.indices, expected.indices);
+        assert_eq!(primitive.material, 0);
+
+        assert_eq!(read.nodes.len(), 2);
+        for (node, expected) in read.nodes.iter().zip(&scene.nodes) {
+            assert_eq!(node.parent, expected.parent);
+            assert_eq!(node.children, expected.children);
+            assert_eq!(node.transform, expected.transform);
+            assert_eq!(node.mesh, expected.mesh);
+            assert_eq!(node.instances, expected.instances);
+            assert_eq!(node.name, expected.name);
+            assert_eq!(node.skin.is_some(), expected.skin.is_some());
+        }
+        let (skin, expected) = (
+            read.nodes[0].skin.as_ref().unwrap(),
+            scene.nodes[0].skin.as_ref().unwrap(),
+        );
+        assert_eq!(skin.joints, expected.joints);
+        assert_eq!(skin.skeleton, expected.skeleton);
+        assert_eq!(skin.inverse_bind_matrices, expected.inverse_bind_matrices);
+        assert_eq!(read.root_nodes, scene.root_nodes);
+    }
+
+    #[test]
+    fn truncated_scenes_are_rejected() {
+        let data = scene().write();
+        for len in 0..data.len() {
+            assert!(CookedScene::read(&data[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_times_are_rejected() {
+        let mut w = Writer(vec![]);
+        w.u8(1);
+        w.u64(0);
+        w.u32(1_000_000_000);
+        let mut r = Reader {
+            data: &w.0,
+            position: 0,
+        };
+        assert!(matches!(r.time(), Err(CookError::InvalidData)));
+
+        let mut w = Writer(vec![]);
+        w.u8(1);
+        w.u64(u64::MAX);
+        w.u32(0);
+        let mut r = Reader {
+            data: &w.0,
+            position: 0,
+        };
+        assert!(matches!(r.time(), Err(CookError::InvalidData)));
+    }
+}
@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use glam::{Mat4, Quat, Vec3};
+
+use crate::bounds::Aabb;
+
+/// How a debug shape is drawn.
+#[derive(Debug, Clone, Copy)]
+pub struct DebugStyle {
+    pub color: [f32; 4],
+    /// hidden behind what is closer to the camera, otherwise drawn on top
+    pub depth_test: bool,
+    /// how long the shape stays after it's added, it's always drawn at least once
+    pub lifetime: Duration,
+}
+
+impl DebugStyle {
+    pub fn new(color: [f32; 4]) -> Self {
+        Self {
+            color,
+            depth_test: true,
+            lifetime: Duration::ZERO,
+        }
+    }
+
+    /// Drawn on top of everything.
+    pub fn on_top(mut self) -> Self {
+        self.depth_test = false;
+        self
+    }
+
+    pub fn lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+}
+
+impl From<[f32; 4]> for DebugStyle {
+    fn from(color: [f32; 4]) -> Self {
+        Self::new(color)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DebugLine {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub color: [f32; 4],
+    pub depth_test: bool,
+    /// seconds left before it's removed
+    remaining: f32,
+}
+
+/// Lines, boxes, spheres and other shapes to visualize things while debugging,
+/// added from anywhere and drawn with `Pass::render_debug`.
+///
+/// Shapes are kept until `advance` finds them past their lifetime, so it should be
+/// called once per frame after rendering.
+#[derive(Debug, Default)]
+pub struct DebugDraw {
+    lines: Vec<DebugLine>,
+}
+
+impl DebugDraw {
+    const CIRCLE_SEGMENTS: usize = 32;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ages the shapes by `delta`, removing the ones that outlived their lifetime.
+    pub fn advance(&mut self, delta: Duration) {
+        let delta = delta.as_secs_f32();
+        self.lines.retain_mut(|it| {
+            it.remaining -= delta;
+            it.remaining > 0.0
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    pub(crate) fn lines(&self) -> &[DebugLine] {
+        &self.lines
+    }
+
+    pub fn line(&mut self, start: Vec3, end: Vec3, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        self.lines.push(DebugLine {
+            start,
+            end,
+            color: style.color,
+            depth_test: style.depth_test,
+            remaining: style.lifetime.as_secs_f32(),
+        });
+    }
+
+    /// A line with a head at `end`.
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        self.line(start, end, style);
+
+        let direction = end - start;
+        let length = direction.length();
+        if length <= 0.0 {
+            return;
+        }
+        let direction = direction / length;
+        let (side, up) = direction.any_orthonormal_pair();
+        let head = length * 0.2;
+        let base = end - direction * head;
+        for offset in [side, -side, up, -up] {
+            self.line(end, base + offset * head * 0.5, style);
+        }
+    }
+
+    pub fn aabb(&mut self, bounds: &Aabb, style: impl Into<DebugStyle>) {
+        self.obb(bounds.center(), bounds.extents(), Quat::IDENTITY, style);
+    }
+
+    /// A box with half of its size in each axis given by `extents`, rotated around
+    /// its center.
+    pub fn obb(
+        &mut self,
+        center: Vec3,
+        extents: Vec3,
+        rotation: Quat,
+        style: impl Into<DebugStyle>,
+    ) {
+        let transform = Mat4::from_scale_rotation_translation(extents, rotation, center);
+        self.box_edges(
+            box_corners().map(|it| transform.transform_point3(it)),
+            style.into(),
+        );
+    }
+
+    /// Three circles around the axes.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        self.circle(center, Vec3::X * radius, Vec3::Y * radius, style);
+        self.circle(center, Vec3::Y * radius, Vec3::Z * radius, style);
+        self.circle(center, Vec3::Z * radius, Vec3::X * radius, style);
+    }
+
+    /// The volume visible through a camera with this view projection matrix.
+    pub fn frustum(&mut self, view_projection: Mat4, style: impl Into<DebugStyle>) {
+        let inverse = view_projection.inverse();
+        let corners = box_corners().map(|it| {
+            let it = inverse * it.extend(1.0);
+            it.truncate() / it.w
+        });
+        self.box_edges(corners, style.into());
+    }
+
+    /// The axes of `transform` in red, green and blue, `size` long. The color of
+    /// the style is ignored.
+    pub fn axes(&mut self, transform: Mat4, size: f32, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        let origin = transform.transform_point3(Vec3::ZERO);
+        let axes = [
+            (Vec3::X, [1.0, 0.0, 0.0, 1.0]),
+            (Vec3::Y, [0.0, 1.0, 0.0, 1.0]),
+            (Vec3::Z, [0.0, 0.0, 1.0, 1.0]),
+        ];
+        for (axis, color) in axes {
+            let end = transform.transform_point3(axis * size);
+            self.line(origin, end, DebugStyle { color, ..style });
+        }
+    }
+
+    /// A grid on the ground plane, `cells` by `cells` with each cell `cell_size`
+    /// wide.
+    pub fn grid(&mut self, center: Vec3, cells: u32, cell_size: f32, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        let half = cells as f32 * cell_size * 0.5;
+        for i in 0..=cells {
+            let offset = i as f32 * cell_size - half;
+            self.line(
+                center + Vec3::new(offset, 0.0, -half),
+                center + Vec3::new(offset, 0.0, half),
+                style,
+            );
+            self.line(
+                center + Vec3::new(-half, 0.0, offset),
+                center + Vec3::new(half, 0.0, offset),
+                style,
+            );
+        }
+    }
+
+    fn circle(&mut self, center: Vec3, x: Vec3, y: Vec3, style: DebugStyle) {
+        let point = |i: usize| {
+            let angle = i as f32 / Self::CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+            center + x * angle.cos() + y * angle.sin()
+        };
+        for i in 0..Self::CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), style);
+        }
+    }
+
+    /// Edges between corners ordered like `box_corners`.
+    fn box_edges(&mut self, corners: [Vec3; 8], style: DebugStyle) {
+        for (a, b) in BOX_EDGES {
+            self.line(corners[a], corners[b], style);
+        }
+    }
+}
+
+/// Corners of a cube from -1 to 1, the bits of the index select the positive
+/// side of x, y and z.
+fn box_corners() -> [Vec3; 8] {
+    let side = |i: usize, bit: usize| if i & (1 << bit) != 0 { 1.0 } else { -1.0 };
+    [0, 1, 2, 3, 4, 5, 6, 7].map(|i| Vec3::new(side(i, 0), side(i, 1), side(i, 2)))
+}
+
+const BOX_EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
@@ -0,0 +1,279 @@
+use std::borrow::Cow;
+
+use bytemuck::{Pod, Zeroable};
+use once_cell::sync::OnceCell;
+use wgpu::util::DeviceExt;
+
+use crate::graphics::GraphicsContext;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub(crate) struct EnvironmentMapUniforms {
+    /// +x, -x, +y, -y, +z, -z
+    pub face: u32,
+    /// of the prefiltered mip being rendered
+    pub roughness: f32,
+    /// texels along each side of the faces of the source
+    pub source_size: f32,
+    pub sample_count: u32,
+}
+
+/// The passes that precompute the lighting of environment maps, one face of a
+/// cube at a time, and the skybox they are drawn with.
+pub(crate) struct EnvironmentMapPipelines {
+    pub uniform_layout: wgpu::BindGroupLayout,
+    /// reads an equirectangular image
+    pub image_layout: wgpu::BindGroupLayout,
+    /// reads a cube, also the sky of the skybox
+    pub cube_layout: wgpu::BindGroupLayout,
+    pub sampler: wgpu::Sampler,
+    pub from_image: wgpu::RenderPipeline,
+    pub downsample: wgpu::RenderPipeline,
+    pub irradiance: wgpu::RenderPipeline,
+    pub prefilter: wgpu::RenderPipeline,
+    pub brdf: wgpu::RenderPipeline,
+    pub skybox_layout: wgpu::PipelineLayout,
+    pub skybox_shader: wgpu::ShaderModule,
+    /// depends on the sample count
+    /// by extra targets
+    pub skybox: [OnceCell<wgpu::RenderPipeline>; GraphicsContext::MAX_EXTRA_TARGETS + 1],
+}
+
+impl EnvironmentMapPipelines {
+    /// The irradiance sums this many steps of elevation, and four times as many
+    /// around the normal.
+    pub const IRRADIANCE_STEPS: u32 = 16;
+    pub const PREFILTER_SAMPLES: u32 = 512;
+
+    pub fn new(device: &wgpu::Device, uniform_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        // both have the sampler at 0, the shaders declare the image at 1 and the
+        // cube at 2
+        let source_layout = |binding, view_dimension| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension,
+                        },
+                        count: None,
+                    },
+                ],
+            })
+        };
+        let image_layout = source_layout(1, wgpu::TextureViewDimension::D2);
+        let cube_layout = source_layout(2, wgpu::TextureViewDimension::Cube);
+
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Environment Map Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(
+                "shader/environment_map.wgsl"
+            ))),
+        });
+        let create_pipeline =
+            |bind_group_layouts: &[&wgpu::BindGroupLayout], entry, format: wgpu::TextureFormat| {
+                let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts,
+                    push_constant_ranges: &[],
+                });
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(entry),
+                    layout: Some(&layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: "vs_main",
+                        buffers: &[],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: entry,
+                        targets: &[format.into()],
+                    }),
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState::default(),
+                    multiview: None,
+                })
+            };
+        let format = GraphicsContext::ENVIRONMENT_FORMAT;
+
+        Self {
+            from_image: create_pipeline(&[&uniform_layout, &image_layout], "fs_from_image", format),
+            downsample: create_pipeline(&[&uniform_layout, &cube_layout], "fs_downsample", format),
+            irradiance: create_pipeline(&[&uniform_layout, &cube_layout], "fs_irradiance", format),
+            prefilter: create_pipeline(&[&uniform_layout, &cube_layout], "fs_prefilter", format),
+            brdf: create_pipeline(&[], "fs_brdf", wgpu::TextureFormat::Rg16Float),
+            skybox_layout: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[uniform_bind_group_layout, &cube_layout],
+                push_constant_ranges: &[],
+            }),
+            skybox_shader: device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some("Skybox Shader"),
+                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader/skybox.wgsl"))),
+            }),
+            skybox: Default::default(),
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Environment Map Sampler"),
+                // equirectangular images wrap around horizontally
+                address_mode_u: wgpu::AddressMode::Repeat,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+            uniform_layout,
+            image_layout,
+            cube_layout,
+        }
+    }
+
+    /// Reads `view` with the layout of an image or a cube.
+    pub fn source_bind_group(
+        &self,
+        device: &wgpu::Device,
+        view: &wgpu::TextureView,
+        cube: bool,
+    ) -> wgpu::BindGroup {
+        let (layout, binding) = match cube {
+            true => (&self.cube_layout, 2),
+            false => (&self.image_layout, 1),
+        };
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+            ],
+        })
+    }
+
+    /// Renders `pipeline` into a face of a cube.
+    pub fn render_face(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        target: &wgpu::TextureView,
+        uniforms: EnvironmentMapUniforms,
+        source: &wgpu::BindGroup,
+    ) {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[uniforms]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let uniforms = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Environment Map Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &uniforms, &[]);
+        pass.set_bind_group(1, source, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    /// The scale and bias of the specular reflectance by the angle and
+    /// roughness, it's the same for every environment map.
+    pub fn render_brdf_lut(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::TextureView {
+        let view = device
+            .create_texture(&wgpu::TextureDescriptor {
+                size: wgpu::Extent3d {
+                    width: GraphicsContext::BRDF_LUT_SIZE,
+                    height: GraphicsContext::BRDF_LUT_SIZE,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rg16Float,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                label: Some("BRDF Lookup Table"),
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("BRDF Lookup Table Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(&self.brdf);
+            pass.draw(0..3, 0..1);
+        }
+        queue.submit(Some(encoder.finish()));
+
+        view
+    }
+}
+
+/// A single mip of a face of a cube, to render into.
+pub(crate) fn cube_face_view(texture: &wgpu::Texture, face: u32, mip: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        base_mip_level: mip,
+        mip_level_count: std::num::NonZeroU32::new(1),
+        base_array_layer: face,
+        array_layer_count: std::num::NonZeroU32::new(1),
+        ..Default::default()
+    })
+}
@@ -1,4 +1,25 @@
 use thiserror::Error;
 
 #[derive(Error, Debug)]
-pub enum AyudeError {}
+pub enum AyudeError {
+    #[error("failed to find an appropriate graphics adapter")]
+    AdapterNotFound,
+    #[error("failed to acquire GPU device: {0}")]
+    RequestDeviceFailed(#[from] wgpu::RequestDeviceError),
+    #[error("{0} samples per pixel are not supported by the adapter")]
+    UnsupportedSampleCount(u32),
+    #[error("the {0:?} debug view is not supported by the adapter")]
+    UnsupportedDebugView(crate::graphics::DebugView),
+    #[error("invalid shader: {0}")]
+    InvalidShader(String),
+    #[error("invalid material parameter: {0}")]
+    InvalidMaterialParameter(String),
+    #[error("invalid image: {0}")]
+    InvalidImage(String),
+    #[error("invalid color lookup table: {0}")]
+    InvalidColorLut(String),
+    #[error("incompatible render target: {0}")]
+    IncompatibleRenderTarget(String),
+    #[error("failed to read file: {0}")]
+    Io(#[from] std::io::Error),
+}
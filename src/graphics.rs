@@ -1,13 +1,32 @@
-use std::{borrow::Cow, rc::Rc};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    ops::Range,
+    path::Path,
+    rc::Rc,
+};
 
-use glam::{Mat4, Vec3};
+use glam::{Mat3, Mat4, Vec3, Vec4};
 
 use once_cell::sync::OnceCell;
 use wgpu::util::DeviceExt;
 
 use bytemuck::{Pod, Zeroable};
 
-use crate::transform::GLOBAL_UP;
+use crate::{
+    ambient_occlusion::{AmbientOcclusionPipelines, AmbientOcclusionUniforms},
+    bloom::{BloomPipelines, BloomUniforms},
+    bounds::Aabb,
+    debug_draw::DebugDraw,
+    environment_map::{cube_face_view, EnvironmentMapPipelines, EnvironmentMapUniforms},
+    hdr,
+    post_process::{PostEffectUniforms, PostProcessPipelines},
+    preprocess::preprocess,
+    shadow::{ShadowCascade, ShadowCascades},
+    transform::GLOBAL_UP,
+    AyudeError,
+};
 
 #[derive(Debug, Clone)]
 pub struct Material {
@@ -15,195 +34,2553 @@ pub struct Material {
     pub diffuse: Option<Texture>,
     pub base_diffuse_color: [f32; 4],
     pub shaded: bool,
+    /// uv set sampled by the diffuse texture, 0 is `tex_coord` and 1 is `tex_coord_1`
+    pub diffuse_tex_coord: u32,
+    /// uv set sampled by the normal texture, 0 is `tex_coord` and 1 is `tex_coord_1`
+    pub normal_tex_coord: u32,
+    /// rendered into shadow passes, always with the standard vertex transform, so
+    /// custom materials with their own `vs_main` cast the shadow of the unmodified mesh
+    pub cast_shadows: bool,
+    /// darkened where the sun is occluded
+    pub receive_shadows: bool,
+    /// blended over what is behind it, using the alpha of the diffuse color
+    pub transparent: bool,
+    /// drawn with a custom shader, which ignores the textures and colors above
+    pub shader: Option<ShaderMaterial>,
+}
+
+impl Material {
+    /// An opaque material drawn with `shader`, that receives shadows. It casts them
+    /// too unless the shader has its own vertex stage, which shadows don't follow.
+    pub fn custom(shader: ShaderMaterial) -> Self {
+        let cast_shadows = shader.shader.standard_vertex;
+        Self {
+            normal: None,
+            diffuse: None,
+            base_diffuse_color: [1.0, 1.0, 1.0, 1.0],
+            shaded: false,
+            diffuse_tex_coord: 0,
+            normal_tex_coord: 0,
+            cast_shadows,
+            receive_shadows: true,
+            transparent: false,
+            shader: Some(shader),
+        }
+    }
+
+    /// Variant of the built in shader this material is drawn with.
+    pub fn shader_features(&self) -> ShaderFeatures {
+        ShaderFeatures {
+            diffuse_texture: self.diffuse.is_some(),
+            normal_texture: self.normal.is_some(),
+            shaded: self.shaded,
+            receive_shadows: self.receive_shadows,
+            transparent: self.transparent,
+        }
+    }
+}
+
+/// What a draw needs from the main shader. Each combination is a separate
+/// pipeline, compiled the first time something is drawn with it.
+///
+/// Vertex colors aren't a feature, every vertex has one, white when the mesh
+/// has none, so multiplying by it always is as cheap as a variant without it.
+/// Neither is skinning, the vertices have no joints or weights and skinned
+/// meshes are drawn in their bind pose.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ShaderFeatures {
+    pub diffuse_texture: bool,
+    pub normal_texture: bool,
+    /// lit by the sun and the ambient color
+    pub shaded: bool,
+    pub receive_shadows: bool,
+    /// blended, without writing depth
+    pub transparent: bool,
+}
+
+impl ShaderFeatures {
+    /// Number of combinations of features.
+    const VARIANTS: usize = 1 << 5;
+
+    fn index(&self) -> usize {
+        [
+            self.diffuse_texture,
+            self.normal_texture,
+            self.shaded,
+            self.receive_shadows,
+            self.transparent,
+        ]
+        .iter()
+        .enumerate()
+        .map(|(bit, enabled)| (*enabled as usize) << bit)
+        .sum()
+    }
+
+    /// Names defined for the shader's `#ifdef`s.
+    fn defines(&self) -> Vec<&'static str> {
+        [
+            (self.diffuse_texture, "DIFFUSE_TEXTURE"),
+            (self.normal_texture, "NORMAL_TEXTURE"),
+            (self.shaded, "SHADED"),
+            (self.receive_shadows, "RECEIVE_SHADOWS"),
+            (self.transparent, "TRANSPARENT"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect()
+    }
+}
+
+/// Replaces what is drawn into the frame to inspect meshes and materials, see
+/// `GraphicsContext::set_debug_view`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugView {
+    /// nothing replaced
+    #[default]
+    Shaded,
+    /// the edges of the triangles over the shaded frame
+    Wireframe,
+    /// world space normals of the vertices
+    Normals,
+    /// texture coordinates of the diffuse texture, red for u and green for v
+    TexCoords,
+    /// the diffuse color, without lighting
+    BaseColor,
+    /// the normal texture as it's sampled
+    NormalMap,
+    /// the lighting of a white surface
+    Lighting,
+    /// how many times each pixel is drawn, brighter the more it is
+    Overdraw,
+    /// mip level the diffuse texture is sampled from, from blue for the full
+    /// size to red for the smallest mips
+    MipLevel,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 9] = [
+        DebugView::Shaded,
+        DebugView::Wireframe,
+        DebugView::Normals,
+        DebugView::TexCoords,
+        DebugView::BaseColor,
+        DebugView::NormalMap,
+        DebugView::Lighting,
+        DebugView::Overdraw,
+        DebugView::MipLevel,
+    ];
+
+    /// Whether every draw uses the main shader, with its debug output instead of
+    /// the material's.
+    fn replaces_materials(&self) -> bool {
+        !matches!(self, DebugView::Shaded | DebugView::Wireframe)
+    }
+
+    /// Names defined for the shader's `#ifdef`s.
+    fn defines(&self) -> Vec<&'static str> {
+        let name = match self {
+            DebugView::Shaded | DebugView::Wireframe => return vec![],
+            DebugView::Normals => "DEBUG_NORMALS",
+            DebugView::TexCoords => "DEBUG_TEX_COORDS",
+            DebugView::BaseColor => "DEBUG_BASE_COLOR",
+            DebugView::NormalMap => "DEBUG_NORMAL_MAP",
+            DebugView::Lighting => "DEBUG_LIGHTING",
+            DebugView::Overdraw => "DEBUG_OVERDRAW",
+            DebugView::MipLevel => "DEBUG_MIP_LEVEL",
+        };
+        vec!["DEBUG_VIEW", name]
+    }
+}
+
+/// Type of a custom material parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterType {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+}
+
+impl ParameterType {
+    fn components(&self) -> usize {
+        match self {
+            ParameterType::Float => 1,
+            ParameterType::Vec2 => 2,
+            ParameterType::Vec3 => 3,
+            ParameterType::Vec4 => 4,
+        }
+    }
+
+    /// In floats, as WGSL lays out uniforms.
+    fn alignment(&self) -> usize {
+        match self {
+            ParameterType::Float => 1,
+            ParameterType::Vec2 => 2,
+            ParameterType::Vec3 | ParameterType::Vec4 => 4,
+        }
+    }
+
+    fn wgsl(&self) -> &'static str {
+        match self {
+            ParameterType::Float => "f32",
+            ParameterType::Vec2 => "vec2<f32>",
+            ParameterType::Vec3 => "vec3<f32>",
+            ParameterType::Vec4 => "vec4<f32>",
+        }
+    }
+}
+
+/// Source, parameters and textures of a custom material, for
+/// `GraphicsContext::create_shader_material`.
+///
+/// The source is appended to declarations of the standard vertex inputs and
+/// outputs, the uniforms and the environment, and a `vs_main` that transforms
+/// like the built in materials do. It has to define `fs_main`, which takes a
+/// `VertexOutput`. Parameters are fields of `material`, and each texture
+/// `name` comes with a `name_sampler`.
+pub struct ShaderMaterialDescription<'a> {
+    source: &'a str,
+    parameters: Vec<(&'a str, ParameterType)>,
+    textures: Vec<(&'a str, Texture)>,
+    standard_vertex: bool,
+    extra_outputs: usize,
+}
+
+impl<'a> ShaderMaterialDescription<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            parameters: vec![],
+            textures: vec![],
+            standard_vertex: true,
+            extra_outputs: 0,
+        }
+    }
+
+    /// Declares a parameter, they take up to
+    /// `GraphicsContext::MAX_MATERIAL_PARAMETERS` vec4s between them.
+    pub fn parameter(mut self, name: &'a str, ty: ParameterType) -> Self {
+        self.parameters.push((name, ty));
+        self
+    }
+
+    /// Declares a texture, there can be up to two.
+    pub fn texture(mut self, name: &'a str, texture: &Texture) -> Self {
+        self.textures.push((name, texture.clone()));
+        self
+    }
+
+    /// The source defines its own `vs_main`, that returns a `VertexOutput`.
+    pub fn custom_vertex(mut self) -> Self {
+        self.standard_vertex = false;
+        self
+    }
+
+    /// `fs_main` returns `count` more colors, at locations 1 and up, for the extra
+    /// targets of a pass, see `PassDescription::extra_target`.
+    pub fn extra_outputs(mut self, count: usize) -> Self {
+        self.extra_outputs = count;
+        self
+    }
+}
+
+/// A material drawn with its own shader, see `Material::custom`. Clones share
+/// the shader, but not the parameters.
+#[derive(Debug, Clone)]
+pub struct ShaderMaterial {
+    shader: Rc<CustomShader>,
+    parameters: MaterialParameters,
+}
+
+impl ShaderMaterial {
+    /// Sets the parameter called `name`. Fails when there is no such parameter or
+    /// `value` doesn't have its number of components.
+    pub fn set_parameter(&mut self, name: &str, value: &[f32]) -> Result<(), AyudeError> {
+        let (ty, offset) = self
+            .shader
+            .parameters
+            .iter()
+            .find(|it| it.0 == name)
+            .map(|it| (it.1, it.2))
+            .ok_or_else(|| AyudeError::InvalidMaterialParameter(format!("no '{}'", name)))?;
+        if value.len() != ty.components() {
+            return Err(AyudeError::InvalidMaterialParameter(format!(
+                "'{}' has {} components, the value has {}",
+                name,
+                ty.components(),
+                value.len()
+            )));
+        }
+
+        let parameters: &mut [f32] = bytemuck::cast_slice_mut(&mut self.parameters);
+        parameters[offset..offset + value.len()].copy_from_slice(value);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct CustomShader {
+    module: wgpu::ShaderModule,
+    /// name, type and offset in floats
+    parameters: Vec<(String, ParameterType, usize)>,
+    /// bound where the built in materials have the diffuse and normal textures
+    textures: [Option<Texture>; 2],
+    /// whether `vs_main` is the one of the built in materials
+    standard_vertex: bool,
+    /// colors written into the extra targets of a pass
+    extra_outputs: usize,
+    /// by extra targets, then sample count and then transparency
+    pipelines: [OnceCell<wgpu::RenderPipeline>; 8 * (GraphicsContext::MAX_EXTRA_TARGETS + 1)],
+}
+
+/// Lighting and camera effects shared by everything drawn in a frame.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    /// world space direction towards the sun
+    pub sun_direction: Vec3,
+    pub sun_color: [f32; 3],
+    pub sun_intensity: f32,
+    pub ambient_color: [f32; 3],
+    /// multiplies the color before tone mapping
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+    pub bloom: Bloom,
+    pub ambient_occlusion: AmbientOcclusion,
+    /// whether the sun casts shadows
+    pub shadows: bool,
+    /// shadows are only rendered up to this distance from the camera
+    pub shadow_distance: f32,
+    /// applied in order after tone mapping, up to `GraphicsContext::MAX_POST_EFFECTS`
+    pub post_effects: Vec<PostEffect>,
+    /// lights the ambient term instead of `ambient_color`
+    pub environment_map: Option<EnvironmentMap>,
+    /// multiplies the light of the environment map, and the skybox
+    pub environment_intensity: f32,
+    /// whether the environment map is drawn behind the draws of passes that clear
+    /// the frame
+    pub skybox: bool,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            sun_direction: Vec3::new(-1.0, 0.4, 0.9),
+            sun_color: [1.0, 1.0, 1.0],
+            sun_intensity: 1.0,
+            ambient_color: [0.1, 0.1, 0.1],
+            exposure: 1.0,
+            tone_mapping: ToneMapping::Aces,
+            bloom: Bloom::default(),
+            ambient_occlusion: AmbientOcclusion::default(),
+            shadows: true,
+            shadow_distance: 100.0,
+            post_effects: vec![],
+            environment_map: None,
+            environment_intensity: 1.0,
+            skybox: true,
+        }
+    }
+}
+
+/// How the high dynamic range colors of a frame are fit into the range of the
+/// screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapping {
+    /// colors above 1 are clamped
+    None,
+    Reinhard,
+    /// fit of the ACES filmic curve
+    Aces,
+    /// AgX with its default look, bright colors are desaturated like on film
+    Agx,
+}
+
+/// Glow around the parts of a frame brighter than a threshold.
+#[derive(Debug, Clone, Copy)]
+pub struct Bloom {
+    pub enabled: bool,
+    /// how much of the glow is added to the frame
+    pub intensity: f32,
+    /// brightness where things start to glow, before exposure
+    pub threshold: f32,
+    /// how far the glow spreads, 1 is the tightest blur
+    pub radius: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            intensity: 0.5,
+            threshold: 1.0,
+            radius: 1.0,
+        }
+    }
+}
+
+/// Darkening of creases and contact areas, estimated from the depth and normals of
+/// what is on screen.
+#[derive(Debug, Clone, Copy)]
+pub struct AmbientOcclusion {
+    pub enabled: bool,
+    /// view space distance around a point where things occlude it
+    pub radius: f32,
+    /// depth difference that is ignored, against flat surfaces occluding themselves
+    pub bias: f32,
+    /// samples per pixel, up to `GraphicsContext::MAX_AMBIENT_OCCLUSION_SAMPLES`
+    pub samples: u32,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            radius: 0.5,
+            bias: 0.025,
+            samples: 16,
+        }
+    }
+}
+
+/// A full screen pass over the tone mapped frame, reading what the pass before it
+/// wrote.
+#[derive(Debug, Clone)]
+pub enum PostEffect {
+    /// fast approximate anti-aliasing, blurs edges along their direction
+    Fxaa,
+    /// darkens the corners, `smoothness` is how far towards the center it fades
+    Vignette { intensity: f32, smoothness: f32 },
+    /// replaces colors with the ones in a lookup table, `intensity` blends them
+    /// with the original ones
+    ColorGrading { lut: ColorLut, intensity: f32 },
+    /// `gamma` above 1 brightens the midtones, `contrast` above 1 pushes colors away
+    /// from middle grey
+    GammaContrast { gamma: f32, contrast: f32 },
+    /// a pass from `GraphicsContext::create_post_shader`, `values` are its
+    /// `effect.values`
+    Custom {
+        shader: PostShader,
+        values: [f32; 4],
+    },
+}
+
+impl PostEffect {
+    fn values(&self) -> [f32; 4] {
+        match self {
+            PostEffect::Fxaa => [0.0; 4],
+            PostEffect::Vignette {
+                intensity,
+                smoothness,
+            } => [*intensity, *smoothness, 0.0, 0.0],
+            PostEffect::ColorGrading { intensity, .. } => [*intensity, 0.0, 0.0, 0.0],
+            PostEffect::GammaContrast { gamma, contrast } => [*gamma, *contrast, 0.0, 0.0],
+            PostEffect::Custom { values, .. } => *values,
+        }
+    }
+}
+
+/// How a render pass starts and what it keeps when it ends, so a frame can be
+/// built from several passes.
+#[derive(Debug, Clone, Copy)]
+pub struct PassDescription<'a> {
+    /// `None` keeps what the target had
+    clear_color: Option<[f32; 4]>,
+    /// `None` keeps what the target had, a pass before has to store it
+    clear_depth: Option<f32>,
+    store_depth: bool,
+    /// `None` renders into the frame
+    target: Option<&'a RenderTarget>,
+    extra_targets: [Option<&'a RenderTarget>; GraphicsContext::MAX_EXTRA_TARGETS],
+    /// including the ones that didn't fit
+    extra_target_count: usize,
+}
+
+impl<'a> Default for PassDescription<'a> {
+    fn default() -> Self {
+        Self {
+            clear_color: Some([0.1, 0.2, 0.3, 1.0]),
+            clear_depth: Some(1.0),
+            store_depth: false,
+            target: None,
+            extra_targets: [None; GraphicsContext::MAX_EXTRA_TARGETS],
+            extra_target_count: 0,
+        }
+    }
+}
+
+impl<'a> PassDescription<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear_color(mut self, color: [f32; 4]) -> Self {
+        self.clear_color = Some(color);
+        self
+    }
+
+    /// Draws over what the target already had.
+    pub fn load_color(mut self) -> Self {
+        self.clear_color = None;
+        self
+    }
+
+    pub fn clear_depth(mut self, depth: f32) -> Self {
+        self.clear_depth = Some(depth);
+        self
+    }
+
+    /// Tests against the depth a pass before stored.
+    pub fn load_depth(mut self) -> Self {
+        self.clear_depth = None;
+        self
+    }
+
+    /// Keeps the depth when the pass ends, for passes after it to load.
+    pub fn store_depth(mut self, store: bool) -> Self {
+        self.store_depth = store;
+        self
+    }
+
+    /// Renders into `target` instead of the frame.
+    pub fn target(mut self, target: &'a RenderTarget) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Renders into `target` too, at the next color location. Custom materials
+    /// with extra outputs write into it and everything else leaves it as it was.
+    /// It's cleared to transparent black when the pass clears its color.
+    pub fn extra_target(mut self, target: &'a RenderTarget) -> Self {
+        if let Some(slot) = self.extra_targets.get_mut(self.extra_target_count) {
+            *slot = Some(target);
+        }
+        self.extra_target_count += 1;
+        self
+    }
+
+    fn extra_targets(&self) -> impl Iterator<Item = &'a RenderTarget> + '_ {
+        self.extra_targets.iter().flatten().copied()
+    }
+}
+
+/// Color and depth that passes can render into instead of the frame, its color
+/// can then be drawn with like any other texture.
+///
+/// Passes into render targets don't have ambient occlusion, and targets have to
+/// be created again after the sample count changes.
+#[derive(Debug)]
+pub struct RenderTarget {
+    color: wgpu::TextureView,
+    /// `None` when the sample count was 1
+    msaa: Option<wgpu::TextureView>,
+    depth: DepthTarget,
+    sample_count: u32,
+    texture: Texture,
+}
+
+impl RenderTarget {
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Depth that passes into the target stored, see `DepthTarget`.
+    pub fn depth(&self) -> &DepthTarget {
+        &self.depth
+    }
+}
+
+/// Depth of a render target or of the frame, which can be sampled or copied
+/// after a pass stores it with `PassDescription::store_depth`. It has as many
+/// samples per pixel as the context had when it was created.
+#[derive(Debug)]
+pub struct DepthTarget {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+impl DepthTarget {
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+}
+
+/// Lookup table for `PostEffect::ColorGrading`.
+#[derive(Debug, Clone)]
+pub struct ColorLut {
+    bind_group: Rc<wgpu::BindGroup>,
+    size: u32,
+}
+
+impl ColorLut {
+    /// Texels along each side.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+}
+
+/// Custom pass for `PostEffect::Custom`.
+#[derive(Debug, Clone)]
+pub struct PostShader {
+    pipeline: Rc<wgpu::RenderPipeline>,
+}
+
+/// A sky, with the light it casts precomputed, for `Environment::environment_map`.
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    /// reads the sky, for the skybox
+    sky_bind_group: Rc<wgpu::BindGroup>,
+    /// cosine weighted light from around each direction, for diffuse lighting
+    irradiance: Rc<wgpu::TextureView>,
+    /// light reflected by rougher surfaces in each mip
+    prefiltered: Rc<wgpu::TextureView>,
+    bind_groups: Rc<EnvironmentMapBindGroups>,
+    /// texels along each side of the faces of the sky
+    pub size: u32,
+}
+
+/// The environment bind groups of the context, with an environment map.
+#[derive(Debug)]
+struct EnvironmentMapBindGroups {
+    /// for passes into render targets
+    unoccluded: Rc<wgpu::BindGroup>,
+    /// for passes into the frame, with the version of the screen targets whose
+    /// ambient occlusion it reads
+    screen: RefCell<Option<(u64, Rc<wgpu::BindGroup>)>>,
 }
 
 pub struct GraphicsContext {
-    surface: wgpu::Surface,
+    /// `None` when rendering headless, into `offscreen_target`
+    surface: Option<wgpu::Surface>,
+    /// size and format of the color target, also used when there is no surface
     surface_config: wgpu::SurfaceConfiguration,
+    offscreen_target: Option<wgpu::Texture>,
     device: wgpu::Device, // todo! not pub
     queue: wgpu::Queue,   // todo! not pub
-    pipeline: wgpu::RenderPipeline,
+    pipeline_layout: wgpu::PipelineLayout,
+    /// with the parameters of custom materials next to the uniforms
+    material_pipeline_layout: wgpu::PipelineLayout,
+    /// variants of the main shader, by extra targets and then `ShaderFeatures::index`
+    pipelines: Vec<OnceCell<wgpu::RenderPipeline>>,
+    debug_view: DebugView,
+    /// edges drawn over everything in `DebugView::Wireframe`, by extra targets
+    wireframe_pipeline: [OnceCell<wgpu::RenderPipeline>; GraphicsContext::MAX_EXTRA_TARGETS + 1],
+    debug_lines: DebugLinePipelines,
+    sample_count: u32,
+    /// `None` when `sample_count` is 1
+    msaa_target: Option<wgpu::TextureView>,
+    screen_targets: ScreenTargets,
+    /// changes whenever the screen targets are recreated
+    screen_targets_version: u64,
+    ambient_occlusion: AmbientOcclusionPipelines,
+    bloom: BloomPipelines,
+    tone_map: ToneMapPipeline,
+    post_process: PostProcessPipelines,
+    environment_maps: EnvironmentMapPipelines,
     uniform_bind_group_layout: wgpu::BindGroupLayout,
+    /// the uniforms and the parameters of a custom material
+    material_uniform_bind_group_layout: wgpu::BindGroupLayout,
+    /// distance between the uniforms of two draws, respecting the offset alignment
+    uniform_stride: usize,
+    uniform_chunks: Vec<OnceCell<UniformChunk>>,
     textures_bind_group_layout: wgpu::BindGroupLayout,
+    environment: EnvironmentBindings,
+    /// the environment with nothing occluded, for passes into render targets
+    unoccluded_environment_bind_group: wgpu::BindGroup,
+    shadow_pipeline: wgpu::RenderPipeline,
+    shadow_cascades: Vec<ShadowCascade>,
     default_texture: OnceCell<Texture>,
     quad_mesh: OnceCell<Mesh>,
-    depth_view: wgpu::TextureView, // todo! not pub
+    identity_instances: OnceCell<InstanceBuffer>,
+    depth: DepthTarget,
+    /// counters of the last submitted frame
+    frame_stats: Cell<RenderStats>,
 }
 
-impl GraphicsContext {
-    const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24Plus;
+impl GraphicsContext {
+    /// 32 bits, so depth can be copied out of it
+    const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+    pub(crate) const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const BLOOM_MIPS: u32 = 6;
+    pub(crate) const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    pub(crate) const PREPASS_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub(crate) const AMBIENT_OCCLUSION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+    pub const MAX_AMBIENT_OCCLUSION_SAMPLES: usize = 64;
+    const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    pub(crate) const SHADOW_MAP_SIZE: u32 = 2048;
+    /// Number of shadow maps, each covering a further slice of the camera frustum.
+    pub const SHADOW_CASCADES: usize = 4;
+    const UNIFORM_CHUNK_DRAWS: usize = 1024;
+    const MAX_UNIFORM_CHUNKS: usize = 256;
+    const DEFAULT_SAMPLE_COUNT: u32 = 1;
+    pub const MAX_POST_EFFECTS: usize = 16;
+    /// Color targets a pass can render into besides its own.
+    pub const MAX_EXTRA_TARGETS: usize = 3;
+    /// Render passes of a frame with their own camera. The passes past this many
+    /// are lit as seen from the camera of the last one.
+    pub const MAX_PASSES: usize = 16;
+    /// Debug lines past this many vertices in a frame are not drawn.
+    pub const MAX_DEBUG_VERTICES: usize = 1 << 16;
+    /// Space for the parameters of a custom material, in vec4s.
+    pub const MAX_MATERIAL_PARAMETERS: usize = 4;
+    pub(crate) const ENVIRONMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+    const IRRADIANCE_SIZE: u32 = 32;
+    const PREFILTERED_SIZE: u32 = 128;
+    /// Mips of the prefiltered environment, from smooth to fully rough.
+    const PREFILTERED_MIPS: u32 = 5;
+    pub(crate) const BRDF_LUT_SIZE: u32 = 256;
+    /// Declarations every custom post processing shader starts with.
+    pub const POST_SHADER_PRELUDE: &'static str = include_str!("shader/post.wgsl");
+    /// Functions every shader that draws instanced meshes starts with.
+    pub(crate) const INSTANCE_SHADER_PRELUDE: &'static str = include_str!("shader/instance.wgsl");
+    /// Vertices, then the instances they are drawn with.
+    pub(crate) const VERTEX_BUFFERS: [wgpu::VertexBufferLayout<'static>; 2] = [
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: 0,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x3,
+                    offset: 4 * 4,
+                    shader_location: 1,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x2,
+                    offset: 4 * 7,
+                    shader_location: 2,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x2,
+                    offset: 4 * 9,
+                    shader_location: 3,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 4 * 11,
+                    shader_location: 4,
+                },
+            ],
+        },
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Instance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: 5,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 4 * 4,
+                    shader_location: 6,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 4 * 8,
+                    shader_location: 7,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 4 * 12,
+                    shader_location: 8,
+                },
+            ],
+        },
+    ];
+
+    pub async fn new(window: &winit::window::Window) -> Self {
+        let size = window.inner_size();
+
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let surface = unsafe { instance.create_surface(window) };
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: Some(&surface),
+                force_fallback_adapter: false,
+            })
+            .await
+            .expect("Failed to find an appropriate graphics adapter.");
+
+        let (device, queue) = Self::request_device(&adapter)
+            .await
+            .expect("Failed to acquire GPU device.");
+
+        let surface_format = surface
+            .get_preferred_format(&adapter)
+            .expect("Surface is not compatible with graphics adapter.");
+
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface_format,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        surface.configure(&device, &surface_config);
+
+        Self::with_device(device, queue, Some(surface), surface_config)
+    }
+
+    /// Creates a context that renders into an offscreen color target of the given
+    /// size instead of a window, so it works without a display. The software adapter
+    /// can be forced for machines without a GPU. Frames are read back with
+    /// `read_target`.
+    pub async fn new_headless(
+        width: u32,
+        height: u32,
+        force_fallback_adapter: bool,
+    ) -> Result<Self, AyudeError> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter,
+            })
+            .await
+            .ok_or(AyudeError::AdapterNotFound)?;
+
+        let (device, queue) = Self::request_device(&adapter).await?;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: Self::OFFSCREEN_FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+
+        Ok(Self::with_device(device, queue, None, config))
+    }
+
+    async fn request_device(
+        adapter: &wgpu::Adapter,
+    ) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+        let adapter_info = adapter.get_info();
+        println!("Using {} ({:?})", adapter_info.name, adapter_info.backend);
+
+        adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    // for multisampling with other than 1 or 4 samples, and
+                    // drawing wireframes
+                    features: adapter.features()
+                        & (wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                            | wgpu::Features::POLYGON_MODE_LINE),
+                    limits: wgpu::Limits::default(),
+                },
+                None,
+            )
+            .await
+    }
+
+    fn with_device(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        surface: Option<wgpu::Surface>,
+        surface_config: wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let surface_format = surface_config.format;
+
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<Uniforms>() as _
+                        ),
+                    },
+                    count: None,
+                }],
+            });
+        let material_uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(
+                                std::mem::size_of::<Uniforms>() as _,
+                            ),
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<
+                                MaterialParameters,
+                            >()
+                                as _),
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let textures_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        // the shadow maps, ambient occlusion and environment map are part of the
+        // environment, there are only four bind groups. Each pass has its own uniforms,
+        // with the camera it is drawn from.
+        let environment_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<
+                                EnvironmentUniforms,
+                            >()
+                                as _),
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::Cube,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as usize;
+        let environment_stride =
+            std::mem::size_of::<EnvironmentUniforms>().next_multiple_of(alignment);
+        let environment_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Environment Buffer"),
+            size: (environment_stride * Self::MAX_PASSES) as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let shadow_map = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: Self::SHADOW_MAP_SIZE,
+                height: Self::SHADOW_MAP_SIZE,
+                depth_or_array_layers: Self::SHADOW_CASCADES as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::SHADOW_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            label: Some("Shadow Map"),
+        });
+
+        let shadow_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        // for passes into render targets, which don't have ambient occlusion
+        let unoccluded = device
+            .create_texture_with_data(
+                &queue,
+                &wgpu::TextureDescriptor {
+                    size: wgpu::Extent3d {
+                        width: 1,
+                        height: 1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: Self::AMBIENT_OCCLUSION_FORMAT,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                    label: Some("Unoccluded"),
+                },
+                &[255],
+            )
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        // bound when there is no environment map
+        let black_cube = device
+            .create_texture_with_data(
+                &queue,
+                &wgpu::TextureDescriptor {
+                    size: wgpu::Extent3d {
+                        width: 1,
+                        height: 1,
+                        depth_or_array_layers: 6,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: Self::ENVIRONMENT_FORMAT,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                    label: Some("Black Cube"),
+                },
+                &[0; 8 * 6],
+            )
+            .create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                ..Default::default()
+            });
+
+        let environment_maps = EnvironmentMapPipelines::new(&device, &uniform_bind_group_layout);
+        let environment = EnvironmentBindings {
+            layout: environment_bind_group_layout,
+            buffer: environment_buffer,
+            stride: environment_stride,
+            shadow_map: shadow_map.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            }),
+            shadow_sampler,
+            unoccluded,
+            black_cube,
+            brdf_lut: environment_maps.render_brdf_lut(&device, &queue),
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Environment Sampler"),
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+        };
+        let unoccluded_environment_bind_group =
+            environment.bind_group(&device, &environment.unoccluded, None);
+
+        let cascade_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let shadow_cascades = (0..Self::SHADOW_CASCADES as u32)
+            .map(|layer| {
+                let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Shadow Cascade Buffer"),
+                    size: std::mem::size_of::<[f32; 16]>() as _,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: None,
+                    layout: &cascade_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    }],
+                });
+                let view = shadow_map.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                });
+                ShadowCascade {
+                    buffer,
+                    bind_group,
+                    view,
+                }
+            })
+            .collect();
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[
+                &uniform_bind_group_layout,
+                &textures_bind_group_layout,
+                &textures_bind_group_layout,
+                &environment.layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let material_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[
+                    &material_uniform_bind_group_layout,
+                    &textures_bind_group_layout,
+                    &textures_bind_group_layout,
+                    &environment.layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let debug_lines = DebugLinePipelines::new(&device, &uniform_bind_group_layout);
+
+        let tone_map_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        let tone_map_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tone Mapping Buffer"),
+            contents: bytemuck::cast_slice(&[ToneMappingUniforms::new(&Environment::default())]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let tone_map_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&tone_map_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let tone_map_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader/tonemap.wgsl"))),
+        });
+
+        let tone_map_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Tone Mapping Pipeline"),
+            layout: Some(&tone_map_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &tone_map_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &tone_map_shader,
+                entry_point: "fs_main",
+                targets: &[surface_format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let tone_map = ToneMapPipeline {
+            pipeline: tone_map_pipeline,
+            bind_group_layout: tone_map_bind_group_layout,
+            buffer: tone_map_buffer,
+        };
+
+        let shadow_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[&uniform_bind_group_layout, &cascade_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+        let shadow_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader/shadow.wgsl"))),
+        });
+
+        let shadow_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&shadow_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shadow_shader,
+                entry_point: "vs_main",
+                buffers: &Self::VERTEX_BUFFERS,
+            },
+            fragment: None,
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Self::SHADOW_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as usize;
+        let uniform_stride = std::mem::size_of::<Uniforms>().next_multiple_of(alignment);
+
+        let depth =
+            Self::create_depth_texture(&surface_config, &device, Self::DEFAULT_SAMPLE_COUNT);
+        let msaa_target =
+            Self::create_msaa_target(&surface_config, &device, Self::DEFAULT_SAMPLE_COUNT);
+        let ambient_occlusion = AmbientOcclusionPipelines::new(&device, &uniform_bind_group_layout);
+        let bloom = BloomPipelines::new(&device);
+        let post_process = PostProcessPipelines::new(&device, surface_format);
+        let screen_targets = ScreenTargets::new(
+            &surface_config,
+            &device,
+            &environment,
+            &ambient_occlusion,
+            &bloom,
+            &tone_map,
+            &post_process,
+        );
+        let offscreen_target = match surface {
+            Some(_) => None,
+            None => Some(Self::create_offscreen_target(&surface_config, &device)),
+        };
+
+        Self {
+            surface,
+            surface_config,
+            offscreen_target,
+            device,
+            queue,
+            pipeline_layout,
+            material_pipeline_layout,
+            pipelines: Self::empty_pipelines(),
+            debug_view: DebugView::default(),
+            wireframe_pipeline: Default::default(),
+            debug_lines,
+            sample_count: Self::DEFAULT_SAMPLE_COUNT,
+            msaa_target,
+            screen_targets,
+            screen_targets_version: 0,
+            ambient_occlusion,
+            bloom,
+            tone_map,
+            post_process,
+            environment_maps,
+            uniform_bind_group_layout,
+            material_uniform_bind_group_layout,
+            uniform_stride,
+            uniform_chunks: (0..Self::MAX_UNIFORM_CHUNKS)
+                .map(|_| OnceCell::new())
+                .collect(),
+            textures_bind_group_layout,
+            environment,
+            unoccluded_environment_bind_group,
+            shadow_pipeline,
+            shadow_cascades,
+            default_texture: OnceCell::new(),
+            quad_mesh: OnceCell::new(),
+            identity_instances: OnceCell::new(),
+            depth,
+            frame_stats: Cell::new(RenderStats::default()),
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.surface_config.width = width;
+        self.surface_config.height = height;
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.surface_config),
+            None => {
+                self.offscreen_target = Some(Self::create_offscreen_target(
+                    &self.surface_config,
+                    &self.device,
+                ))
+            }
+        }
+
+        self.depth =
+            Self::create_depth_texture(&self.surface_config, &self.device, self.sample_count);
+        self.msaa_target =
+            Self::create_msaa_target(&self.surface_config, &self.device, self.sample_count);
+        self.screen_targets = ScreenTargets::new(
+            &self.surface_config,
+            &self.device,
+            &self.environment,
+            &self.ambient_occlusion,
+            &self.bloom,
+            &self.tone_map,
+            &self.post_process,
+        );
+        self.screen_targets_version += 1;
+    }
+
+    /// Samples per pixel of the color and depth targets.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Sample counts `set_sample_count` accepts. 1 and 4 work everywhere, 2 and 8
+    /// only with adapter specific format features.
+    pub fn supported_sample_counts(&self) -> Vec<u32> {
+        let adapter_specific = self
+            .device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        [1, 2, 4, 8]
+            .into_iter()
+            .filter(|it| adapter_specific || *it == 1 || *it == 4)
+            .collect()
+    }
+
+    /// Changes the number of samples per pixel for multisample anti-aliasing,
+    /// rebuilding the pipelines and targets that depend on it.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> Result<(), AyudeError> {
+        if !self.supported_sample_counts().contains(&sample_count) {
+            return Err(AyudeError::UnsupportedSampleCount(sample_count));
+        }
+        if sample_count == self.sample_count {
+            return Ok(());
+        }
+
+        // compiled again with the new sample count when they are drawn with
+        self.pipelines = Self::empty_pipelines();
+        self.wireframe_pipeline = Default::default();
+        self.environment_maps.skybox = Default::default();
+        self.debug_lines.pipelines = Default::default();
+        self.sample_count = sample_count;
+        self.depth = Self::create_depth_texture(&self.surface_config, &self.device, sample_count);
+        self.msaa_target =
+            Self::create_msaa_target(&self.surface_config, &self.device, sample_count);
+        Ok(())
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+
+    /// Changes what is drawn into the frame for every material, to see why
+    /// something looks wrong. `DebugView::Wireframe` needs an adapter that can
+    /// draw polygons as lines.
+    pub fn set_debug_view(&mut self, debug_view: DebugView) -> Result<(), AyudeError> {
+        let polygon_mode_line = self
+            .device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE);
+        if debug_view == DebugView::Wireframe && !polygon_mode_line {
+            return Err(AyudeError::UnsupportedDebugView(debug_view));
+        }
+        if debug_view == self.debug_view {
+            return Ok(());
+        }
+
+        // compiled again with the debug output when they are drawn with
+        self.pipelines = Self::empty_pipelines();
+        self.debug_view = debug_view;
+        Ok(())
+    }
+
+    /// Width and height of the color target.
+    pub fn size(&self) -> (u32, u32) {
+        (self.surface_config.width, self.surface_config.height)
+    }
+
+    /// Counters of the render passes of the last submitted frame.
+    pub fn frame_stats(&self) -> RenderStats {
+        self.frame_stats.get()
+    }
+
+    /// Creates a high dynamic range target for `PassDescription::target`.
+    pub fn create_render_target(&self, width: u32, height: u32) -> RenderTarget {
+        let config = wgpu::SurfaceConfiguration {
+            width,
+            height,
+            ..self.surface_config.clone()
+        };
+        let color = self
+            .device
+            .create_texture(&wgpu::TextureDescriptor {
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                label: Some("Render Target"),
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = self.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.textures_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&color),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        RenderTarget {
+            msaa: Self::create_msaa_target(&config, &self.device, self.sample_count),
+            depth: Self::create_depth_texture(&config, &self.device, self.sample_count),
+            sample_count: self.sample_count,
+            texture: Texture {
+                bind_group: bind_group.into(),
+                width,
+                height,
+            },
+            color,
+        }
+    }
+
+    /// Creates a lookup table for color grading from `size`³ sRGB RGBA texels, with
+    /// red increasing along rows, green along columns and blue along slices.
+    pub fn create_color_lut(&self, texels: &[u8], size: u32) -> Result<ColorLut, AyudeError> {
+        let max_size = self.device.limits().max_texture_dimension_3d;
+        if size == 0 || size > max_size {
+            return Err(AyudeError::InvalidColorLut(format!(
+                "size {} is not between 1 and {}",
+                size, max_size
+            )));
+        }
+        let expected = (size as usize)
+            .checked_pow(3)
+            .and_then(|it| it.checked_mul(4));
+        if expected != Some(texels.len()) {
+            return Err(AyudeError::InvalidColorLut(format!(
+                "{} bytes of texels for a size of {}",
+                texels.len(),
+                size
+            )));
+        }
+
+        let texture_extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Color Lookup Table"),
+            size: texture_extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        self.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::default(),
+            },
+            texels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(size * 4),
+                rows_per_image: std::num::NonZeroU32::new(size),
+            },
+            texture_extent,
+        );
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.post_process.lut_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.post_process.sampler),
+                },
+            ],
+        });
+
+        Ok(ColorLut {
+            bind_group: bind_group.into(),
+            size,
+        })
+    }
+
+    /// Loads an equirectangular environment map, like the `.hdr` images made for
+    /// lighting. Other formats are read as sRGB.
+    pub fn load_environment_map(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<EnvironmentMap, AyudeError> {
+        let image = hdr::load(path.as_ref())?;
+        self.check_texture_size(image.width, image.height)?;
+        let texture_extent = wgpu::Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Equirectangular Environment Map"),
+            size: texture_extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::ENVIRONMENT_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        self.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::default(),
+            },
+            &image.to_f16_bytes(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(image.width * 8),
+                rows_per_image: std::num::NonZeroU32::new(image.height),
+            },
+            texture_extent,
+        );
+
+        // four faces go around the image, so they keep its resolution
+        let size = (image.width / 4).max(1);
+        let sky = self.create_cube_texture("Sky", size, 32 - size.leading_zeros());
+        let maps = &self.environment_maps;
+        let source = maps.source_bind_group(
+            &self.device,
+            &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            false,
+        );
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        for face in 0..6 {
+            maps.render_face(
+                &self.device,
+                &mut encoder,
+                &maps.from_image,
+                &cube_face_view(&sky, face, 0),
+                EnvironmentMapUniforms {
+                    face,
+                    ..Zeroable::zeroed()
+                },
+                &source,
+            );
+        }
+
+        Ok(self.create_environment_map(encoder, sky, size))
+    }
+
+    /// Loads an environment map from the six square faces of a cube, in the order
+    /// +x, -x, +y, -y, +z, -z. `.hdr` images are read as linear colors and other
+    /// formats as sRGB.
+    pub fn load_environment_cubemap(
+        &self,
+        faces: [impl AsRef<Path>; 6],
+    ) -> Result<EnvironmentMap, AyudeError> {
+        let images = faces
+            .iter()
+            .map(|it| hdr::load(it.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let size = images[0].width;
+        if images
+            .iter()
+            .any(|it| it.width != size || it.height != size)
+        {
+            return Err(AyudeError::InvalidImage(
+                "the faces of a cubemap have to be squares of the same size".to_string(),
+            ));
+        }
+        self.check_texture_size(size, size)?;
+
+        let sky = self.create_cube_texture("Sky", size, 32 - size.leading_zeros());
+        for (face, image) in images.iter().enumerate() {
+            self.queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &sky,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: face as u32,
+                    },
+                    aspect: wgpu::TextureAspect::default(),
+                },
+                &image.to_f16_bytes(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(size * 8),
+                    rows_per_image: std::num::NonZeroU32::new(size),
+                },
+                wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        Ok(self.create_environment_map(encoder, sky, size))
+    }
+
+    /// Fills the mips of `sky`, whose first mip is rendered by `encoder`, and
+    /// precomputes the light it casts.
+    fn create_environment_map(
+        &self,
+        mut encoder: wgpu::CommandEncoder,
+        sky: wgpu::Texture,
+        size: u32,
+    ) -> EnvironmentMap {
+        let maps = &self.environment_maps;
+        let cube_view = |texture: &wgpu::Texture, base_mip_level, mip_level_count| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                base_mip_level,
+                mip_level_count,
+                ..Default::default()
+            })
+        };
+
+        // each mip averages the one before it, so the sky can be read blurred
+        // without aliasing
+        let mips = 32 - size.leading_zeros();
+        for mip in 1..mips {
+            let source = cube_view(&sky, mip - 1, std::num::NonZeroU32::new(1));
+            let source = maps.source_bind_group(&self.device, &source, true);
+            for face in 0..6 {
+                maps.render_face(
+                    &self.device,
+                    &mut encoder,
+                    &maps.downsample,
+                    &cube_face_view(&sky, face, mip),
+                    EnvironmentMapUniforms {
+                        face,
+                        ..Zeroable::zeroed()
+                    },
+                    &source,
+                );
+            }
+        }
+        let sky_bind_group = maps.source_bind_group(&self.device, &cube_view(&sky, 0, None), true);
+
+        let irradiance = self.create_cube_texture("Irradiance", Self::IRRADIANCE_SIZE, 1);
+        for face in 0..6 {
+            maps.render_face(
+                &self.device,
+                &mut encoder,
+                &maps.irradiance,
+                &cube_face_view(&irradiance, face, 0),
+                EnvironmentMapUniforms {
+                    face,
+                    roughness: 0.0,
+                    source_size: size as f32,
+                    sample_count: EnvironmentMapPipelines::IRRADIANCE_STEPS,
+                },
+                &sky_bind_group,
+            );
+        }
+
+        let prefiltered = self.create_cube_texture(
+            "Prefiltered Environment",
+            Self::PREFILTERED_SIZE,
+            Self::PREFILTERED_MIPS,
+        );
+        for mip in 0..Self::PREFILTERED_MIPS {
+            for face in 0..6 {
+                maps.render_face(
+                    &self.device,
+                    &mut encoder,
+                    &maps.prefilter,
+                    &cube_face_view(&prefiltered, face, mip),
+                    EnvironmentMapUniforms {
+                        face,
+                        roughness: mip as f32 / (Self::PREFILTERED_MIPS - 1) as f32,
+                        source_size: size as f32,
+                        sample_count: EnvironmentMapPipelines::PREFILTER_SAMPLES,
+                    },
+                    &sky_bind_group,
+                );
+            }
+        }
+
+        self.queue.submit(Some(encoder.finish()));
+
+        let irradiance = cube_view(&irradiance, 0, None);
+        let prefiltered = cube_view(&prefiltered, 0, None);
+        let unoccluded = self.environment.bind_group(
+            &self.device,
+            &self.environment.unoccluded,
+            Some([&irradiance, &prefiltered]),
+        );
+        EnvironmentMap {
+            sky_bind_group: sky_bind_group.into(),
+            irradiance: irradiance.into(),
+            prefiltered: prefiltered.into(),
+            bind_groups: Rc::new(EnvironmentMapBindGroups {
+                unoccluded: unoccluded.into(),
+                screen: RefCell::new(None),
+            }),
+            size,
+        }
+    }
+
+    /// The environment bind groups for passes into the frame and into render
+    /// targets, with `map`. The first one is created again when the screen
+    /// targets change.
+    fn environment_map_bind_groups(&self, map: &EnvironmentMap) -> [Rc<wgpu::BindGroup>; 2] {
+        let bind_groups = &map.bind_groups;
+        let mut screen = bind_groups.screen.borrow_mut();
+        let screen = match &*screen {
+            Some((version, bind_group)) if *version == self.screen_targets_version => {
+                bind_group.clone()
+            }
+            _ => {
+                let bind_group = Rc::new(self.environment.bind_group(
+                    &self.device,
+                    &self.screen_targets.ambient_occlusion,
+                    Some([&map.irradiance, &map.prefiltered]),
+                ));
+                *screen = Some((self.screen_targets_version, bind_group.clone()));
+                bind_group
+            }
+        };
+        [screen, bind_groups.unoccluded.clone()]
+    }
+
+    /// Fails for images larger than the textures the adapter supports.
+    fn check_texture_size(&self, width: u32, height: u32) -> Result<(), AyudeError> {
+        let max = self.device.limits().max_texture_dimension_2d;
+        if width.max(height) > max {
+            return Err(AyudeError::InvalidImage(format!(
+                "{}x{} is larger than the {} texels per side the adapter supports",
+                width, height, max
+            )));
+        }
+        Ok(())
+    }
+
+    fn create_cube_texture(&self, label: &str, size: u32, mip_level_count: u32) -> wgpu::Texture {
+        self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::ENVIRONMENT_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+        })
+    }
+
+    /// Creates a post processing pass from WGSL. The source is appended to
+    /// `POST_SHADER_PRELUDE` and has to define `fs_main`, taking a `VertexOutput`
+    /// and returning the color at `location(0)`.
+    pub fn create_post_shader(&self, source: &str) -> Result<PostShader, AyudeError> {
+        let source = format!("{}{}", Self::POST_SHADER_PRELUDE, source);
+
+        // errors would only be reported when the pipeline is used otherwise
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = self
+            .device
+            .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some("Custom Post Shader"),
+                source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+            });
+        let pipeline = PostProcessPipelines::create_pipeline(
+            &self.device,
+            self.post_process.format,
+            &self.post_process.pipeline_layout,
+            &shader,
+            "fs_main",
+        );
+        if let Some(error) = pollster::block_on(self.device.pop_error_scope()) {
+            return Err(AyudeError::InvalidShader(error.to_string()));
+        }
+
+        Ok(PostShader {
+            pipeline: pipeline.into(),
+        })
+    }
+
+    /// Runs `effects` over the first post processing target, the last one writes
+    /// into `target`.
+    fn render_post_effects(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        effects: &[PostEffect],
+        target: &wgpu::TextureView,
+    ) {
+        let post_process = &self.post_process;
+        let mut data = vec![0; post_process.stride * effects.len()];
+        for (effect, chunk) in effects.iter().zip(data.chunks_mut(post_process.stride)) {
+            let uniforms = PostEffectUniforms {
+                values: effect.values(),
+            };
+            chunk[..std::mem::size_of::<PostEffectUniforms>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniforms));
+        }
+        self.queue.write_buffer(&post_process.buffer, 0, &data);
+
+        let targets = &self.screen_targets;
+        for (i, effect) in effects.iter().enumerate() {
+            let output = if i + 1 == effects.len() {
+                target
+            } else {
+                &targets.post_targets[(i + 1) % 2]
+            };
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Post Processing Pass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            let pipeline = match effect {
+                PostEffect::Fxaa => &post_process.fxaa,
+                PostEffect::Vignette { .. } => &post_process.vignette,
+                PostEffect::ColorGrading { lut, .. } => {
+                    pass.set_bind_group(1, &lut.bind_group, &[]);
+                    &post_process.color_grading
+                }
+                PostEffect::GammaContrast { .. } => &post_process.gamma_contrast,
+                PostEffect::Custom { shader, .. } => &*shader.pipeline,
+            };
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(
+                0,
+                &targets.post_sources[i % 2],
+                &[(post_process.stride * i) as u32],
+            );
+            pass.draw(0..3, 0..1);
+        }
+    }
+
+    /// Renders the depth and normals of the opaque draws, and estimates how much of
+    /// the ambient light reaches each pixel from them.
+    fn render_ambient_occlusion(&self, encoder: &mut wgpu::CommandEncoder, draws: &[QueuedDraw]) {
+        let targets = &self.screen_targets;
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Prepass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &targets.normals,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &targets.prepass_depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_pipeline(&self.ambient_occlusion.prepass);
+            for draw in draws.iter().filter(|it| !it.features.transparent) {
+                let (chunk, offset) = draw.uniforms;
+                let chunk = self.get_uniform_chunk(chunk);
+                pass.set_bind_group(0, &chunk.bind_group, &[offset]);
+                pass.set_index_buffer(draw.mesh.index().slice(..), wgpu::IndexFormat::Uint16);
+                pass.set_vertex_buffer(0, draw.mesh.vertex().slice(..));
+                pass.set_vertex_buffer(1, draw.instances.buffer.slice(..));
+                pass.draw_indexed(0..draw.mesh.index_count as u32, 0, 0..draw.instances.count);
+            }
+        }
+
+        draw_fullscreen(
+            encoder,
+            "Ambient Occlusion Pass",
+            &targets.noisy_ambient_occlusion,
+            &self.ambient_occlusion.occlusion,
+            &targets.ambient_occlusion_bind_group,
+        );
+        draw_fullscreen(
+            encoder,
+            "Ambient Occlusion Blur Pass",
+            &targets.ambient_occlusion,
+            &self.ambient_occlusion.blur,
+            &targets.ambient_occlusion_blur_bind_group,
+        );
+    }
 
-    pub async fn new(window: &winit::window::Window) -> Self {
-        let size = window.inner_size();
+    /// Blurs what is brighter than the bloom threshold in the HDR target, by
+    /// downsampling it through the bloom levels and blending them back up into
+    /// the first one.
+    fn render_bloom(&self, encoder: &mut wgpu::CommandEncoder) {
+        let targets = &self.screen_targets;
+        let levels = targets.bloom_mips.len();
 
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
-        let surface = unsafe { instance.create_surface(window) };
+        draw_fullscreen(
+            encoder,
+            "Bloom Prefilter Pass",
+            &targets.bloom_mips[0],
+            &self.bloom.prefilter,
+            &targets.bloom_sources[0],
+        );
+        for level in 1..levels {
+            draw_fullscreen(
+                encoder,
+                "Bloom Downsample Pass",
+                &targets.bloom_mips[level],
+                &self.bloom.downsample,
+                &targets.bloom_sources[level],
+            );
+        }
+        for level in (0..levels - 1).rev() {
+            draw_fullscreen(
+                encoder,
+                "Bloom Upsample Pass",
+                &targets.bloom_mips[level],
+                &self.bloom.upsample,
+                &targets.bloom_sources[level + 2],
+            );
+        }
+    }
 
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .expect("Failed to find an appropriate graphics adapter.");
+    fn create_offscreen_target(
+        config: &wgpu::SurfaceConfiguration,
+        device: &wgpu::Device,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: config.usage,
+            label: Some("Offscreen Target"),
+        })
+    }
 
-        let adapter_info = adapter.get_info();
-        println!("Using {} ({:?})", adapter_info.name, adapter_info.backend);
+    /// Copies the offscreen color target into an image, waiting for all submitted
+    /// frames to finish. Returns `None` when rendering to a window.
+    pub fn read_target(&self) -> Option<image::RgbaImage> {
+        let target = self.offscreen_target.as_ref()?;
+        let (width, height) = self.size();
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
+        // rows of a buffer copy must be aligned, the padding is removed afterwards
+        let row_bytes = width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_bytes = row_bytes.next_multiple_of(align);
+
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_row_bytes * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            target.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_row_bytes),
+                    rows_per_image: None,
                 },
-                None,
-            )
-            .await
-            .expect("Failed to acquire GPU device.");
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
 
-        let surface_format = surface
-            .get_preferred_format(&adapter)
-            .expect("Surface is not compatible with graphics adapter.");
+        let slice = buffer.slice(..);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).expect("Failed to map readback buffer.");
 
-        let surface_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
-        };
-        surface.configure(&device, &surface_config);
+        let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+        for row in slice.get_mapped_range().chunks(padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..row_bytes as usize]);
+        }
+        buffer.unmap();
 
-        let uniform_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+        image::RgbaImage::from_raw(width, height, pixels)
+    }
+
+    /// Variants are compiled the first time they are drawn with and reused after
+    /// that.
+    fn empty_pipelines() -> Vec<OnceCell<wgpu::RenderPipeline>> {
+        (0..ShaderFeatures::VARIANTS * (Self::MAX_EXTRA_TARGETS + 1))
+            .map(|_| OnceCell::new())
+            .collect()
+    }
+
+    fn get_pipeline(
+        &self,
+        features: ShaderFeatures,
+        extra_targets: usize,
+    ) -> &wgpu::RenderPipeline {
+        let index = extra_targets * ShaderFeatures::VARIANTS + features.index();
+        self.pipelines[index].get_or_init(|| {
+            let mut defines = features.defines();
+            defines.extend(self.debug_view.defines());
+            let source = preprocess(include_str!("shader/shader.wgsl"), &defines)
+                .expect("the directives of the main shader are balanced");
+            let source = format!("{}{}", Self::INSTANCE_SHADER_PRELUDE, source);
+            let shader = self
+                .device
+                .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                    label: None,
+                    source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+                });
+            let overdraw = self.debug_view == DebugView::Overdraw;
+            self.create_material_pipeline(
+                &self.pipeline_layout,
+                &shader,
+                features.transparent,
+                overdraw,
+                extra_targets,
+                0,
+            )
+        })
+    }
+
+    /// The edges of the triangles in a single color, drawn again over the
+    /// shaded draws.
+    fn get_wireframe_pipeline(&self, extra_targets: usize) -> &wgpu::RenderPipeline {
+        self.wireframe_pipeline[extra_targets].get_or_init(|| {
+            let source = preprocess(
+                include_str!("shader/shader.wgsl"),
+                &["DEBUG_VIEW", "DEBUG_WIREFRAME"],
+            )
+            .expect("the directives of the main shader are balanced");
+            let source = format!("{}{}", Self::INSTANCE_SHADER_PRELUDE, source);
+            let shader = self
+                .device
+                .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                    label: None,
+                    source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+                });
+            self.device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("Wireframe Pipeline"),
+                    layout: Some(&self.pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: "vs_main",
+                        buffers: &Self::VERTEX_BUFFERS,
                     },
-                    count: None,
-                }],
-            });
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: "fs_main",
+                        targets: &Self::color_targets(
+                            wgpu::ColorTargetState {
+                                format: Self::HDR_FORMAT,
+                                blend: None,
+                                write_mask: wgpu::ColorWrites::COLOR,
+                            },
+                            extra_targets,
+                            0,
+                        ),
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        polygon_mode: wgpu::PolygonMode::Line,
+                        ..Default::default()
+                    },
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: Self::DEPTH_FORMAT,
+                        depth_write_enabled: false,
+                        depth_compare: wgpu::CompareFunction::LessEqual,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState {
+                        count: self.sample_count,
+                        ..Default::default()
+                    },
+                    multiview: None,
+                })
+        })
+    }
 
-        let textures_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
+    fn get_custom_pipeline<'a>(
+        &self,
+        shader: &'a CustomShader,
+        transparent: bool,
+        extra_targets: usize,
+    ) -> &'a wgpu::RenderPipeline {
+        let index = extra_targets * 8
+            + self.sample_count.trailing_zeros() as usize * 2
+            + transparent as usize;
+        shader.pipelines[index].get_or_init(|| {
+            self.create_material_pipeline(
+                &self.material_pipeline_layout,
+                &shader.module,
+                transparent,
+                false,
+                extra_targets,
+                shader.extra_outputs,
+            )
+        })
+    }
+
+    /// Lines are blended and don't write depth, they are tested against it or
+    /// drawn on top.
+    fn get_debug_pipeline(&self, depth_test: bool, extra_targets: usize) -> &wgpu::RenderPipeline {
+        let debug_lines = &self.debug_lines;
+        debug_lines.pipelines[extra_targets * 2 + depth_test as usize].get_or_init(|| {
+            self.device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("Debug Line Pipeline"),
+                    layout: Some(&debug_lines.layout),
+                    vertex: wgpu::VertexState {
+                        module: &debug_lines.shader,
+                        entry_point: "vs_main",
+                        buffers: &[wgpu::VertexBufferLayout {
+                            array_stride: std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+                            step_mode: wgpu::VertexStepMode::Vertex,
+                            attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4],
+                        }],
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &debug_lines.shader,
+                        entry_point: "fs_main",
+                        targets: &Self::color_targets(
+                            wgpu::ColorTargetState {
+                                format: Self::HDR_FORMAT,
+                                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                                write_mask: wgpu::ColorWrites::COLOR,
+                            },
+                            extra_targets,
+                            0,
+                        ),
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::LineList,
+                        ..Default::default()
+                    },
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: Self::DEPTH_FORMAT,
+                        depth_write_enabled: false,
+                        depth_compare: if depth_test {
+                            wgpu::CompareFunction::LessEqual
+                        } else {
+                            wgpu::CompareFunction::Always
                         },
-                        count: None,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState {
+                        count: self.sample_count,
+                        ..Default::default()
                     },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
+                    multiview: None,
+                })
+        })
+    }
+
+    /// The sky is drawn first and doesn't write depth, everything else is drawn
+    /// over it.
+    fn get_skybox_pipeline(&self, extra_targets: usize) -> &wgpu::RenderPipeline {
+        let maps = &self.environment_maps;
+        maps.skybox[extra_targets].get_or_init(|| {
+            self.device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("Skybox Pipeline"),
+                    layout: Some(&maps.skybox_layout),
+                    vertex: wgpu::VertexState {
+                        module: &maps.skybox_shader,
+                        entry_point: "vs_main",
+                        buffers: &[],
                     },
-                ],
-            });
+                    fragment: Some(wgpu::FragmentState {
+                        module: &maps.skybox_shader,
+                        entry_point: "fs_main",
+                        targets: &Self::color_targets(
+                            wgpu::ColorTargetState {
+                                format: Self::HDR_FORMAT,
+                                blend: None,
+                                write_mask: wgpu::ColorWrites::COLOR,
+                            },
+                            extra_targets,
+                            0,
+                        ),
+                    }),
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: Self::DEPTH_FORMAT,
+                        depth_write_enabled: false,
+                        depth_compare: wgpu::CompareFunction::Always,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState {
+                        count: self.sample_count,
+                        ..Default::default()
+                    },
+                    multiview: None,
+                })
+        })
+    }
 
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &[
-                &uniform_bind_group_layout,
-                &textures_bind_group_layout,
-                &textures_bind_group_layout,
-            ],
-            push_constant_ranges: &[],
+    /// `target`, followed by the extra targets of a pass. Only the first
+    /// `extra_outputs` of them are written, the shader has no outputs for the rest.
+    fn color_targets(
+        target: wgpu::ColorTargetState,
+        extra_targets: usize,
+        extra_outputs: usize,
+    ) -> Vec<wgpu::ColorTargetState> {
+        let extra = (0..extra_targets).map(|i| wgpu::ColorTargetState {
+            format: Self::HDR_FORMAT,
+            blend: None,
+            write_mask: if i < extra_outputs {
+                wgpu::ColorWrites::ALL
+            } else {
+                wgpu::ColorWrites::empty()
+            },
         });
+        std::iter::once(target).chain(extra).collect()
+    }
 
-        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-            label: None,
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader/shader.wgsl"))),
-        });
+    /// With `overdraw`, draws add up instead of hiding each other.
+    fn create_material_pipeline(
+        &self,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        transparent: bool,
+        overdraw: bool,
+        extra_targets: usize,
+        extra_outputs: usize,
+    ) -> wgpu::RenderPipeline {
+        let (label, target, depth_write_enabled) = if overdraw {
+            // every draw adds to the color, hidden or not
+            let target = wgpu::ColorTargetState {
+                format: Self::HDR_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::COLOR,
+            };
+            ("Overdraw Pipeline", target, false)
+        } else if transparent {
+            // transparent draws are sorted back to front, and don't hide what is
+            // behind them
+            let target = wgpu::ColorTargetState {
+                format: Self::HDR_FORMAT,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            };
+            ("Transparent Pipeline", target, false)
+        } else {
+            // opaque draws keep the alpha of the target, so a read back frame
+            // stays opaque
+            let target = wgpu::ColorTargetState {
+                format: Self::HDR_FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::COLOR,
+            };
+            ("Opaque Pipeline", target, true)
+        };
 
-        let vertex_buffers = [wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    format: wgpu::VertexFormat::Float32x4,
-                    offset: 0,
-                    shader_location: 0,
-                },
-                wgpu::VertexAttribute {
-                    format: wgpu::VertexFormat::Float32x3,
-                    offset: 4 * 4,
-                    shader_location: 1,
+        self.device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: "vs_main",
+                    buffers: &Self::VERTEX_BUFFERS,
                 },
-                wgpu::VertexAttribute {
-                    format: wgpu::VertexFormat::Float32x2,
-                    offset: 4 * 7,
-                    shader_location: 2,
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_main",
+                    targets: &Self::color_targets(target, extra_targets, extra_outputs),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Self::DEPTH_FORMAT,
+                    depth_write_enabled,
+                    depth_compare: if overdraw {
+                        wgpu::CompareFunction::Always
+                    } else {
+                        wgpu::CompareFunction::LessEqual
+                    },
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: self.sample_count,
+                    ..Default::default()
                 },
-            ],
-        }];
+                multiview: None,
+            })
+    }
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &vertex_buffers,
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[surface_format.into()],
-            }),
-            primitive: wgpu::PrimitiveState::default(),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Self::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState::default(),
-            multiview: None,
-        });
+    /// Compiles the shader of a custom material, see `ShaderMaterialDescription`.
+    pub fn create_shader_material(
+        &self,
+        description: &ShaderMaterialDescription,
+    ) -> Result<ShaderMaterial, AyudeError> {
+        if description.textures.len() > 2 {
+            return Err(AyudeError::InvalidShader(format!(
+                "{} textures were declared, there can be up to 2",
+                description.textures.len()
+            )));
+        }
+        if description.extra_outputs > Self::MAX_EXTRA_TARGETS {
+            return Err(AyudeError::InvalidShader(format!(
+                "{} extra outputs were declared, there can be up to {}",
+                description.extra_outputs,
+                Self::MAX_EXTRA_TARGETS
+            )));
+        }
 
-        let depth_texture = Self::create_depth_texture(&&surface_config, &device);
+        let mut parameters = vec![];
+        let mut fields = String::new();
+        let mut size: usize = 0;
+        for (name, ty) in &description.parameters {
+            let offset = size.next_multiple_of(ty.alignment());
+            size = offset + ty.components();
+            parameters.push((name.to_string(), *ty, offset));
+            fields += &format!("    {}: {};\n", name, ty.wgsl());
+        }
+        if size > Self::MAX_MATERIAL_PARAMETERS * 4 {
+            return Err(AyudeError::InvalidShader(format!(
+                "the parameters take {} floats, there is space for {}",
+                size,
+                Self::MAX_MATERIAL_PARAMETERS * 4
+            )));
+        }
+        if parameters.is_empty() {
+            // structs can't be empty
+            fields += "    unused: f32;\n";
+        }
 
-        Self {
-            surface,
-            surface_config,
-            device,
-            queue,
-            pipeline: render_pipeline,
-            uniform_bind_group_layout,
-            textures_bind_group_layout,
-            default_texture: OnceCell::new(),
-            quad_mesh: OnceCell::new(),
-            depth_view: depth_texture,
+        let mut textures = String::new();
+        for (group, (name, _)) in description.textures.iter().enumerate() {
+            textures += &format!(
+                "[[group({group}), binding(0)]]\nvar {name}: texture_2d<f32>;\n\
+                 [[group({group}), binding(1)]]\nvar {name}_sampler: sampler;\n",
+                group = group + 1,
+                name = name
+            );
         }
-    }
 
-    pub fn resize(&mut self, width: u32, height: u32) {
-        self.surface_config.width = width;
-        self.surface_config.height = height;
-        self.surface.configure(&self.device, &self.surface_config);
+        let defines: &[&str] = if description.standard_vertex {
+            &["STANDARD_VERTEX"]
+        } else {
+            &[]
+        };
+        let source = Self::INSTANCE_SHADER_PRELUDE.to_string()
+            + &preprocess(include_str!("shader/material.wgsl"), defines)?
+                .replace("MATERIAL_PARAMETERS", fields.trim_end())
+                .replace("MATERIAL_TEXTURES", &textures)
+            + description.source;
 
-        self.depth_view = Self::create_depth_texture(&self.surface_config, &self.device);
+        // errors would only be reported when the pipeline is used otherwise
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = self
+            .device
+            .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: Some("Custom Material Shader"),
+                source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+            });
+        let shader = CustomShader {
+            module,
+            parameters,
+            textures: [0, 1].map(|i| description.textures.get(i).map(|it| it.1.clone())),
+            standard_vertex: description.standard_vertex,
+            extra_outputs: description.extra_outputs,
+            pipelines: Default::default(),
+        };
+        // with a target for each extra output, so missing outputs are reported
+        self.get_custom_pipeline(&shader, false, description.extra_outputs);
+        if let Some(error) = pollster::block_on(self.device.pop_error_scope()) {
+            return Err(AyudeError::InvalidShader(error.to_string()));
+        }
+
+        Ok(ShaderMaterial {
+            shader: shader.into(),
+            parameters: Zeroable::zeroed(),
+        })
     }
 
     fn create_depth_texture(
         sc_desc: &wgpu::SurfaceConfiguration,
         device: &wgpu::Device,
-    ) -> wgpu::TextureView {
+        sample_count: u32,
+    ) -> DepthTarget {
         let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
             size: wgpu::Extent3d {
                 width: sc_desc.width,
@@ -211,17 +2588,60 @@ impl GraphicsContext {
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            label: None,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            label: Some("Depth Target"),
         });
 
-        depth_texture.create_view(&wgpu::TextureViewDescriptor::default())
+        DepthTarget {
+            view: depth_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture: depth_texture,
+        }
+    }
+
+    /// Multisampled color target that is resolved into the surface, `None` without
+    /// multisampling.
+    fn create_msaa_target(
+        config: &wgpu::SurfaceConfiguration,
+        device: &wgpu::Device,
+        sample_count: u32,
+    ) -> Option<wgpu::TextureView> {
+        if sample_count == 1 {
+            return None;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            label: Some("Multisampled Target"),
+        });
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
     }
 
     pub fn create_mesh(&self, vertices: &[Vertex], indices: &[u16]) -> Mesh {
+        let bounds =
+            Aabb::from_points(vertices.iter().map(|it| Vec4::from(it.position).truncate()));
+        self.create_mesh_with_bounds(vertices, indices, bounds)
+    }
+
+    /// Like `create_mesh`, for when the bounds of the vertices are already known.
+    pub fn create_mesh_with_bounds(
+        &self,
+        vertices: &[Vertex],
+        indices: &[u16],
+        bounds: Aabb,
+    ) -> Mesh {
         let vertex_buffer = self
             .device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -234,34 +2654,105 @@ impl GraphicsContext {
             .device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: None,
-                contents: bytemuck::cast_slice(&indices),
+                contents: bytemuck::cast_slice(indices),
                 usage: wgpu::BufferUsages::INDEX,
             });
 
         Mesh {
             inner: (vertex_buffer, index_buffer).into(),
             index_count: indices.len(),
+            bounds,
         }
     }
 
-    pub fn create_uniform_buffer(&self) -> UniformBuffer {
-        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Uniform Buffer"),
-            size: (std::mem::size_of::<Uniforms>() + 4) as _,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+    pub fn create_instance_buffer(&self, transforms: &[Mat4]) -> InstanceBuffer {
+        let instances = transforms
+            .iter()
+            .map(|it| Instance {
+                model: it.to_cols_array(),
+            })
+            .collect::<Vec<_>>();
 
-        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &self.uniform_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
+        let buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Instance Buffer"),
+                contents: bytemuck::cast_slice(&instances),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            });
+
+        InstanceBuffer {
+            buffer: buffer.into(),
+            count: instances.len() as u32,
+        }
+    }
+
+    /// Overwrites the transforms of an instance buffer, there can't be more of them
+    /// than the buffer was created with.
+    pub fn update_instance_buffer(&self, instances: &InstanceBuffer, transforms: &[Mat4]) {
+        assert!(transforms.len() <= instances.count as usize);
+        let data = transforms
+            .iter()
+            .map(|it| Instance {
+                model: it.to_cols_array(),
+            })
+            .collect::<Vec<_>>();
+        self.queue
+            .write_buffer(&instances.buffer, 0, bytemuck::cast_slice(&data));
+    }
+
+    /// Chunks are created the first time a frame needs them and reused by every
+    /// frame after that.
+    fn get_uniform_chunk(&self, index: usize) -> &UniformChunk {
+        let chunk = self
+            .uniform_chunks
+            .get(index)
+            .expect("Too many draws in a single frame.");
+        chunk.get_or_init(|| {
+            let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Uniform Chunk"),
+                size: (self.uniform_stride * Self::UNIFORM_CHUNK_DRAWS) as _,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+            let uniforms = wgpu::BindGroupEntry {
                 binding: 0,
-                resource: buffer.as_entire_binding(),
-            }],
-        });
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<Uniforms>() as _),
+                }),
+            };
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &self.uniform_bind_group_layout,
+                entries: std::slice::from_ref(&uniforms),
+            });
+            let material_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &self.material_uniform_bind_group_layout,
+                entries: &[
+                    uniforms,
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: &buffer,
+                            offset: 0,
+                            size: wgpu::BufferSize::new(
+                                std::mem::size_of::<MaterialParameters>() as _
+                            ),
+                        }),
+                    },
+                ],
+            });
 
-        UniformBuffer { buffer, bind_group }
+            UniformChunk {
+                buffer,
+                bind_group,
+                material_bind_group,
+            }
+        })
     }
 
     pub fn create_texture(&self, desc: &TextureDescription) -> Texture {
@@ -331,26 +2822,41 @@ impl GraphicsContext {
     }
 
     pub fn get_current_frame<'gfx>(&'gfx mut self) -> Frame<'gfx> {
-        let current_texture = match self.surface.get_current_texture() {
-            Ok(texture) => texture,
-            Err(_) => {
-                self.surface.configure(&self.device, &self.surface_config);
-                self.surface
-                    .get_current_texture()
-                    .expect("Failed to acquire next swap chain texture!")
-            }
-        };
+        let current_texture =
+            self.surface
+                .as_ref()
+                .map(|surface| match surface.get_current_texture() {
+                    Ok(texture) => texture,
+                    Err(_) => {
+                        surface.configure(&self.device, &self.surface_config);
+                        surface
+                            .get_current_texture()
+                            .expect("Failed to acquire next swap chain texture!")
+                    }
+                });
 
         let encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-        let current_texture_view = current_texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let current_texture_view = match (&current_texture, &self.offscreen_target) {
+            (Some(current_texture), _) => &current_texture.texture,
+            (None, Some(offscreen_target)) => offscreen_target,
+            (None, None) => unreachable!("there is always a surface or an offscreen target"),
+        }
+        .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let uniforms = UniformArena::new(self.uniform_stride);
 
         Frame {
             graphics: self,
+            uniforms,
+            environment: Environment::default(),
+            environment_bind_groups: None,
+            shadow_cascades: None,
+            pass_views: vec![],
+            stats: RenderStats::default(),
+            debug_vertices: vec![],
             _current_texture: current_texture,
             current_texture_view,
             encoder,
@@ -365,6 +2871,8 @@ impl GraphicsContext {
                         position: $pos,
                         normal: $norm,
                         tex_coord: $uv,
+                        tex_coord_1: $uv,
+                        color: [1.0, 1.0, 1.0, 1.0],
                     }
                 };
             }
@@ -375,11 +2883,15 @@ impl GraphicsContext {
                 v!([1.0, 1.0, 0.0, 1.0], [1.0, 0.0, 0.0], [1.0, 0.0]),
             ];
             let indices = [0, 1, 2, 3, 2, 1];
-            let mesh = self.create_mesh(&vertices, &indices);
-            mesh
+            self.create_mesh(&vertices, &indices)
         })
     }
 
+    fn get_identity_instances(&self) -> &InstanceBuffer {
+        self.identity_instances
+            .get_or_init(|| self.create_instance_buffer(&[Mat4::IDENTITY]))
+    }
+
     fn get_default_texture(&self) -> &Texture {
         self.default_texture.get_or_init(|| {
             let pixels = [
@@ -395,206 +2907,998 @@ impl GraphicsContext {
     }
 }
 
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-pub struct Vertex {
-    pub position: [f32; 4],
-    pub normal: [f32; 3],
-    pub tex_coord: [f32; 2],
-}
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 4],
+    pub normal: [f32; 3],
+    pub tex_coord: [f32; 2],
+    pub tex_coord_1: [f32; 2],
+    pub color: [f32; 4],
+}
+
+/// Per-instance data, read by the vertex shader once per drawn copy of a mesh.
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct Instance {
+    model: [f32; 16],
+}
+
+/// Transforms for drawing many copies of a mesh with a single draw call. They are
+/// applied before the model matrix passed to `Pass::render_mesh_instanced`.
+#[derive(Debug, Clone)]
+pub struct InstanceBuffer {
+    buffer: Rc<wgpu::Buffer>,
+    count: u32,
+}
+
+impl InstanceBuffer {
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    /// vertex_buffer, index_buffer
+    inner: Rc<(wgpu::Buffer, wgpu::Buffer)>,
+    pub index_count: usize,
+    /// bounds of the vertices, in model space
+    pub bounds: Aabb,
+}
+
+impl Mesh {
+    pub fn vertex(&self) -> &wgpu::Buffer {
+        let (vertex, _) = self.inner.as_ref();
+        vertex
+    }
+    pub fn index(&self) -> &wgpu::Buffer {
+        let (_, index) = self.inner.as_ref();
+        index
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Texture {
+    bind_group: Rc<wgpu::BindGroup>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Texture {
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
+
+pub struct TextureDescription<'a> {
+    texels: &'a [u8],
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    wrap_s: wgpu::AddressMode,
+    wrap_t: wgpu::AddressMode,
+    min_filter: wgpu::FilterMode,
+    mag_filter: wgpu::FilterMode,
+}
+
+impl<'a> TextureDescription<'a> {
+    pub fn new(texels: &'a [u8], width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        Self {
+            texels,
+            width,
+            height,
+            format,
+            wrap_s: wgpu::AddressMode::ClampToEdge,
+            wrap_t: wgpu::AddressMode::ClampToEdge,
+            min_filter: wgpu::FilterMode::Linear,
+            mag_filter: wgpu::FilterMode::Linear,
+        }
+    }
+    pub fn wrap_s(mut self, mode: wgpu::AddressMode) -> Self {
+        self.wrap_s = mode;
+        self
+    }
+    pub fn wrap_t(mut self, mode: wgpu::AddressMode) -> Self {
+        self.wrap_t = mode;
+        self
+    }
+    pub fn min_filter(mut self, mode: wgpu::FilterMode) -> Self {
+        self.min_filter = mode;
+        self
+    }
+    pub fn mag_filter(mut self, mode: wgpu::FilterMode) -> Self {
+        self.mag_filter = mode;
+        self
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+struct Uniforms {
+    mvp: [f32; 16],
+    transpose_inverse_modelview: [f32; 16],
+    model: [f32; 16],
+    base_diffuse_color: [f32; 4],
+    diffuse_tex_coord: u32,
+    normal_tex_coord: u32,
+    _padding: [u32; 2],
+}
+
+/// Of a custom material, placed after the uniforms of its draw.
+type MaterialParameters = [[f32; 4]; GraphicsContext::MAX_MATERIAL_PARAMETERS];
+
+struct UniformChunk {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// with the parameters of custom materials too
+    material_bind_group: wgpu::BindGroup,
+}
+
+/// The uniforms of every draw in a frame, packed in the layout of the uniform chunks
+/// so each chunk is uploaded with a single write when the frame is submitted.
+struct UniformArena {
+    data: Vec<u8>,
+    stride: usize,
+}
+
+impl UniformArena {
+    fn new(stride: usize) -> Self {
+        Self {
+            data: vec![],
+            stride,
+        }
+    }
+
+    fn chunk_size(&self) -> usize {
+        self.stride * GraphicsContext::UNIFORM_CHUNK_DRAWS
+    }
+
+    /// Returns the index of the chunk the uniforms were placed in, and their dynamic
+    /// offset inside of it.
+    fn push<T: Pod>(&mut self, uniforms: &T) -> (usize, u32) {
+        let position = self.data.len();
+        self.data.extend_from_slice(bytemuck::bytes_of(uniforms));
+        self.data.resize(position + self.stride, 0);
+
+        let chunk_size = self.chunk_size();
+        (position / chunk_size, (position % chunk_size) as u32)
+    }
+
+    /// Like `push`, with the parameters of a custom material in the same chunk,
+    /// whose offset is returned too.
+    fn push_with_parameters(
+        &mut self,
+        uniforms: &Uniforms,
+        parameters: &MaterialParameters,
+    ) -> (usize, u32, u32) {
+        // a chunk is bound at a time, so they can't be split between two
+        if (self.data.len() + self.stride).is_multiple_of(self.chunk_size()) {
+            self.data.resize(self.data.len() + self.stride, 0);
+        }
+        let (chunk, offset) = self.push(uniforms);
+        let (_, parameters_offset) = self.push(parameters);
+        (chunk, offset, parameters_offset)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+struct EnvironmentUniforms {
+    sun_direction: [f32; 4],
+    /// color multiplied by intensity
+    sun_color: [f32; 4],
+    ambient_color: [f32; 4],
+    has_shadows: u32,
+    has_ambient_occlusion: u32,
+    _padding: [u32; 2],
+    cascade_view_projections: [[f32; 16]; GraphicsContext::SHADOW_CASCADES],
+    /// view space distance where each cascade ends
+    cascade_splits: [f32; GraphicsContext::SHADOW_CASCADES],
+    has_environment_map: u32,
+    environment_intensity: f32,
+    _padding_1: [u32; 2],
+    /// of the pass's camera
+    view_sun_direction: [f32; 4],
+    view: [f32; 16],
+}
+
+impl EnvironmentUniforms {
+    fn new(environment: &Environment, cascades: Option<&ShadowCascades>, view: Mat4) -> Self {
+        let sun_direction = environment.sun_direction.normalize().extend(0.0);
+        let [r, g, b] = environment.sun_color;
+        let [ar, ag, ab] = environment.ambient_color;
+        let intensity = environment.sun_intensity;
+        let mut uniforms = Self {
+            sun_direction: sun_direction.into(),
+            sun_color: [r * intensity, g * intensity, b * intensity, 0.0],
+            ambient_color: [ar, ag, ab, 0.0],
+            has_shadows: 0,
+            has_ambient_occlusion: environment.ambient_occlusion.enabled as u32,
+            _padding: [0; 2],
+            cascade_view_projections: [[0.0; 16]; GraphicsContext::SHADOW_CASCADES],
+            cascade_splits: [0.0; GraphicsContext::SHADOW_CASCADES],
+            has_environment_map: environment.environment_map.is_some() as u32,
+            environment_intensity: environment.environment_intensity,
+            _padding_1: [0; 2],
+            view_sun_direction: (view * sun_direction).into(),
+            view: view.to_cols_array(),
+        };
+        if let (true, Some(cascades)) = (environment.shadows, cascades) {
+            uniforms.has_shadows = 1;
+            for (i, view_projection) in cascades.view_projections.iter().enumerate() {
+                uniforms.cascade_view_projections[i] = view_projection.to_cols_array();
+            }
+            uniforms.cascade_splits = cascades.splits;
+        }
+        uniforms
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+struct ToneMappingUniforms {
+    exposure: f32,
+    operator: u32,
+    bloom_intensity: f32,
+    _padding: u32,
+}
+
+impl ToneMappingUniforms {
+    fn new(environment: &Environment) -> Self {
+        Self {
+            exposure: environment.exposure,
+            operator: match environment.tone_mapping {
+                ToneMapping::None => 0,
+                ToneMapping::Reinhard => 1,
+                ToneMapping::Aces => 2,
+                ToneMapping::Agx => 3,
+            },
+            bloom_intensity: match environment.bloom.enabled {
+                true => environment.bloom.intensity,
+                false => 0.0,
+            },
+            _padding: 0,
+        }
+    }
+}
+
+/// Bindings of the environment that don't depend on the size of the color target.
+struct EnvironmentBindings {
+    layout: wgpu::BindGroupLayout,
+    /// the uniforms of each pass of a frame
+    buffer: wgpu::Buffer,
+    stride: usize,
+    /// every cascade, as layers
+    shadow_map: wgpu::TextureView,
+    shadow_sampler: wgpu::Sampler,
+    /// ambient occlusion of passes into render targets
+    unoccluded: wgpu::TextureView,
+    /// in place of the environment map when there is none
+    black_cube: wgpu::TextureView,
+    brdf_lut: wgpu::TextureView,
+    /// for the environment map and the BRDF lookup table
+    sampler: wgpu::Sampler,
+}
+
+impl EnvironmentBindings {
+    /// With the irradiance and prefiltered cubes of an environment map, if any.
+    fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        ambient_occlusion: &wgpu::TextureView,
+        environment_map: Option<[&wgpu::TextureView; 2]>,
+    ) -> wgpu::BindGroup {
+        let [irradiance, prefiltered] =
+            environment_map.unwrap_or([&self.black_cube, &self.black_cube]);
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &self.buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(
+                            std::mem::size_of::<EnvironmentUniforms>() as _
+                        ),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&self.shadow_map),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.shadow_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(ambient_occlusion),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(irradiance),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(prefiltered),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&self.brdf_lut),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+}
+
+struct ToneMapPipeline {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    buffer: wgpu::Buffer,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+struct DebugLinePipelines {
+    layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    /// drawn on top and depth tested, they depend on the sample count
+    /// by extra targets and then depth testing
+    pipelines: [OnceCell<wgpu::RenderPipeline>; 2 * (GraphicsContext::MAX_EXTRA_TARGETS + 1)],
+    /// the debug lines of every pass in a frame
+    vertex_buffer: wgpu::Buffer,
+}
+
+impl DebugLinePipelines {
+    fn new(device: &wgpu::Device, uniform_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        Self {
+            layout: device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: None,
+                bind_group_layouts: &[uniform_bind_group_layout],
+                push_constant_ranges: &[],
+            }),
+            shader: device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+                label: None,
+                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader/debug.wgsl"))),
+            }),
+            pipelines: Default::default(),
+            vertex_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Debug Line Buffer"),
+                size: (std::mem::size_of::<DebugVertex>() * GraphicsContext::MAX_DEBUG_VERTICES)
+                    as _,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+        }
+    }
+}
+
+/// Targets with the size of the color target, created again when it's resized.
+struct ScreenTargets {
+    /// where the scene is rendered, before it's tone mapped into the color target
+    hdr: wgpu::TextureView,
+    /// levels of the bloom blur, each half the size of the one before, starting
+    /// at half the size of `hdr`
+    bloom_mips: Vec<wgpu::TextureView>,
+    /// the first one reads `hdr`, the others the bloom level before them
+    bloom_sources: Vec<wgpu::BindGroup>,
+    /// reads `hdr` and the first bloom level
+    tone_map_bind_group: wgpu::BindGroup,
+    /// view space normals of the opaque draws, from the prepass
+    normals: wgpu::TextureView,
+    prepass_depth: wgpu::TextureView,
+    /// unfiltered ambient occlusion
+    noisy_ambient_occlusion: wgpu::TextureView,
+    ambient_occlusion: wgpu::TextureView,
+    /// reads `prepass_depth` and `normals`
+    ambient_occlusion_bind_group: wgpu::BindGroup,
+    /// reads `noisy_ambient_occlusion`
+    ambient_occlusion_blur_bind_group: wgpu::BindGroup,
+    /// the environment includes the ambient occlusion
+    environment_bind_group: wgpu::BindGroup,
+    /// post processing passes alternate between these, the last one writes into
+    /// the color target instead
+    post_targets: [wgpu::TextureView; 2],
+    /// each reads the post processing target with the same index
+    post_sources: [wgpu::BindGroup; 2],
+}
+
+impl ScreenTargets {
+    fn new(
+        config: &wgpu::SurfaceConfiguration,
+        device: &wgpu::Device,
+        environment: &EnvironmentBindings,
+        ambient_occlusion: &AmbientOcclusionPipelines,
+        bloom: &BloomPipelines,
+        tone_map: &ToneMapPipeline,
+        post_process: &PostProcessPipelines,
+    ) -> Self {
+        let create_target = |label, format, usage| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    size: wgpu::Extent3d {
+                        width: config.width,
+                        height: config.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage,
+                    label: Some(label),
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let usage = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+
+        let hdr = create_target("HDR Target", GraphicsContext::HDR_FORMAT, usage);
+
+        let bloom_size = wgpu::Extent3d {
+            width: (config.width / 2).max(1),
+            height: (config.height / 2).max(1),
+            depth_or_array_layers: 1,
+        };
+        let bloom_texture = device.create_texture(&wgpu::TextureDescriptor {
+            size: bloom_size,
+            mip_level_count: GraphicsContext::BLOOM_MIPS.min(bloom_size.max_mips()),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: GraphicsContext::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            label: Some("Bloom Texture"),
+        });
+        let bloom_mips = (0..GraphicsContext::BLOOM_MIPS.min(bloom_size.max_mips()))
+            .map(|level| {
+                bloom_texture.create_view(&wgpu::TextureViewDescriptor {
+                    base_mip_level: level,
+                    mip_level_count: std::num::NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let bloom_sources = std::iter::once(&hdr)
+            .chain(&bloom_mips)
+            .map(|source| bloom.bind_group(device, source))
+            .collect();
+
+        let tone_map_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &tone_map.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: tone_map.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&bloom_mips[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&bloom.sampler),
+                },
+            ],
+        });
+
+        let normals = create_target("Normal Target", GraphicsContext::NORMAL_FORMAT, usage);
+        let prepass_depth = create_target(
+            "Prepass Depth",
+            GraphicsContext::PREPASS_DEPTH_FORMAT,
+            usage,
+        );
+        let noisy_ambient_occlusion = create_target(
+            "Noisy Ambient Occlusion",
+            GraphicsContext::AMBIENT_OCCLUSION_FORMAT,
+            usage,
+        );
+        let ambient_occlusion_target = create_target(
+            "Ambient Occlusion",
+            GraphicsContext::AMBIENT_OCCLUSION_FORMAT,
+            usage,
+        );
+
+        let ambient_occlusion_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &ambient_occlusion.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: ambient_occlusion.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&prepass_depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normals),
+                },
+            ],
+        });
+        let ambient_occlusion_blur_bind_group =
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &ambient_occlusion.blur_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&noisy_ambient_occlusion),
+                }],
+            });
+
+        let environment_bind_group =
+            environment.bind_group(device, &ambient_occlusion_target, None);
+
+        let post_targets = [
+            create_target("Post Processing Target", config.format, usage),
+            create_target("Post Processing Target", config.format, usage),
+        ];
+        let post_sources = [
+            post_process.bind_group(device, &post_targets[0]),
+            post_process.bind_group(device, &post_targets[1]),
+        ];
 
-#[derive(Debug, Clone)]
-pub struct Mesh {
-    /// vertex_buffer, index_buffer
-    inner: Rc<(wgpu::Buffer, wgpu::Buffer)>,
-    pub index_count: usize,
+        Self {
+            hdr,
+            bloom_mips,
+            bloom_sources,
+            tone_map_bind_group,
+            normals,
+            prepass_depth,
+            noisy_ambient_occlusion,
+            ambient_occlusion: ambient_occlusion_target,
+            ambient_occlusion_bind_group,
+            ambient_occlusion_blur_bind_group,
+            environment_bind_group,
+            post_targets,
+            post_sources,
+        }
+    }
 }
 
-impl Mesh {
-    pub fn vertex(&self) -> &wgpu::Buffer {
-        let (vertex, _) = self.inner.as_ref();
-        vertex
-    }
-    pub fn index(&self) -> &wgpu::Buffer {
-        let (_, index) = self.inner.as_ref();
-        index
-    }
+/// Draws a triangle covering `target`, for passes that work on whole images.
+fn draw_fullscreen(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    target: &wgpu::TextureView,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                // passes that blend over the target load it
+                load: wgpu::LoadOp::Load,
+                store: true,
+            },
+        }],
+        depth_stencil_attachment: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..3, 0..1);
 }
 
-#[derive(Debug, Clone)]
-pub struct Texture {
-    bind_group: Rc<wgpu::BindGroup>,
-    pub width: u32,
-    pub height: u32,
+pub struct Frame<'gfx> {
+    graphics: &'gfx GraphicsContext,
+    uniforms: UniformArena,
+    environment: Environment,
+    /// the environment bind groups of the context, with the environment map of
+    /// `environment` when it has one
+    environment_bind_groups: Option<[Rc<wgpu::BindGroup>; 2]>,
+    shadow_cascades: Option<ShadowCascades>,
+    /// the camera of each pass, up to `GraphicsContext::MAX_PASSES`
+    pass_views: Vec<Mat4>,
+    stats: RenderStats,
+    /// of every pass, written when the frame is submitted
+    debug_vertices: Vec<DebugVertex>,
+    /// `None` when rendering headless
+    _current_texture: Option<wgpu::SurfaceTexture>,
+    current_texture_view: wgpu::TextureView,
+    encoder: wgpu::CommandEncoder,
 }
 
-impl Texture {
-    pub fn bind_group(&self) -> &wgpu::BindGroup {
-        &self.bind_group
+impl<'gfx> Frame<'gfx> {
+    /// Starts a pass into the frame that clears it, drawn from the camera with these
+    /// projection and view matrices.
+    pub fn begin_render_pass<'frame>(
+        &'frame mut self,
+        perspective: Mat4,
+        view: Mat4,
+    ) -> Pass<'gfx, 'frame> {
+        let description = PassDescription::default();
+        self.begin_render_pass_with(description, perspective, view)
+            .expect("the default pass has no targets to mismatch")
     }
-}
 
-pub struct TextureDescription<'a> {
-    texels: &'a [u8],
-    width: u32,
-    height: u32,
-    format: wgpu::TextureFormat,
-    wrap_s: wgpu::AddressMode,
-    wrap_t: wgpu::AddressMode,
-    min_filter: wgpu::FilterMode,
-    mag_filter: wgpu::FilterMode,
-}
+    /// Fails when its targets were created with another sample count than the
+    /// context's, when the extra targets differ in size from the main one or when
+    /// there are more than `GraphicsContext::MAX_EXTRA_TARGETS` of them.
+    pub fn begin_render_pass_with<'frame>(
+        &'frame mut self,
+        description: PassDescription<'frame>,
+        perspective: Mat4,
+        view: Mat4,
+    ) -> Result<Pass<'gfx, 'frame>, AyudeError> {
+        let graphics = self.graphics;
+        if description.extra_target_count > GraphicsContext::MAX_EXTRA_TARGETS {
+            return Err(AyudeError::IncompatibleRenderTarget(format!(
+                "{} extra targets, there can be up to {}",
+                description.extra_target_count,
+                GraphicsContext::MAX_EXTRA_TARGETS
+            )));
+        }
+        let size = match description.target {
+            Some(target) => (target.texture.width, target.texture.height),
+            None => graphics.size(),
+        };
+        let targets = description
+            .target
+            .into_iter()
+            .chain(description.extra_targets());
+        for target in targets {
+            if target.sample_count != graphics.sample_count {
+                return Err(AyudeError::IncompatibleRenderTarget(format!(
+                    "created with {} samples per pixel, the context has {}",
+                    target.sample_count, graphics.sample_count
+                )));
+            }
+        }
+        for target in description.extra_targets() {
+            if (target.texture.width, target.texture.height) != size {
+                return Err(AyudeError::IncompatibleRenderTarget(format!(
+                    "an extra target is {}x{}, the pass is {}x{}",
+                    target.texture.width, target.texture.height, size.0, size.1
+                )));
+            }
+        }
 
-impl<'a> TextureDescription<'a> {
-    pub fn new(texels: &'a [u8], width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
-        Self {
-            texels,
-            width,
-            height,
-            format,
-            wrap_s: wgpu::AddressMode::ClampToEdge,
-            wrap_t: wgpu::AddressMode::ClampToEdge,
-            min_filter: wgpu::FilterMode::Linear,
-            mag_filter: wgpu::FilterMode::Linear,
+        // the passes past the limit share the last slot
+        if self.pass_views.len() < GraphicsContext::MAX_PASSES {
+            self.pass_views.push(view);
+        } else {
+            *self.pass_views.last_mut().unwrap() = view;
         }
+        let environment_offset = ((self.pass_views.len() - 1) * graphics.environment.stride) as u32;
+
+        Ok(Pass {
+            graphics,
+            description,
+            encoder: &mut self.encoder,
+            uniforms: &mut self.uniforms,
+            stats: &mut self.stats,
+            debug_vertices: &mut self.debug_vertices,
+            environment: &self.environment,
+            environment_bind_groups: self.environment_bind_groups.as_ref(),
+            environment_offset,
+            perspective,
+            view,
+            queue: vec![],
+            debug_queue: vec![],
+        })
     }
-    pub fn wrap_s(mut self, mode: wgpu::AddressMode) -> Self {
-        self.wrap_s = mode;
-        self
-    }
-    pub fn wrap_t(mut self, mode: wgpu::AddressMode) -> Self {
-        self.wrap_t = mode;
-        self
+
+    /// Depth of the passes into the frame, see `DepthTarget`.
+    pub fn depth(&self) -> &DepthTarget {
+        &self.graphics.depth
     }
-    pub fn min_filter(mut self, mode: wgpu::FilterMode) -> Self {
-        self.min_filter = mode;
-        self
+
+    /// Counters of the render passes that ended so far.
+    pub fn stats(&self) -> RenderStats {
+        self.stats
     }
-    pub fn mag_filter(mut self, mode: wgpu::FilterMode) -> Self {
-        self.mag_filter = mode;
-        self
+
+    /// Sets the lighting for everything drawn in this frame.
+    pub fn set_environment(&mut self, environment: &Environment) {
+        self.environment = environment.clone();
+
+        let graphics = self.graphics;
+        self.environment_bind_groups = environment
+            .environment_map
+            .as_ref()
+            .map(|map| graphics.environment_map_bind_groups(map));
     }
-}
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
-struct Uniforms {
-    mvp: [f32; 16],
-    transpose_inverse_modelview: [f32; 16],
-    light_direction: [f32; 4],
-    base_diffuse_color: [f32; 4],
-    has_diffuse_texture: u32,
-    has_normal_texture: u32,
-    shaded: u32,
-}
+    /// Fits the shadow cascades to the camera, using the sun of the current
+    /// environment. Returns how many cascades have to be rendered with
+    /// `begin_shadow_pass` before the main pass, none if shadows are disabled.
+    pub fn fit_shadow_cascades(&mut self, perspective: Mat4, view: Mat4) -> usize {
+        if !self.environment.shadows {
+            self.shadow_cascades = None;
+            return 0;
+        }
 
-#[derive(Debug)]
-pub struct UniformBuffer {
-    buffer: wgpu::Buffer,
-    bind_group: wgpu::BindGroup,
-}
+        let cascades = ShadowCascades::fit(&self.environment, perspective, view);
+        for (cascade, view_projection) in self
+            .graphics
+            .shadow_cascades
+            .iter()
+            .zip(&cascades.view_projections)
+        {
+            self.graphics.queue.write_buffer(
+                &cascade.buffer,
+                0,
+                bytemuck::cast_slice(&view_projection.to_cols_array()),
+            );
+        }
+        self.shadow_cascades = Some(cascades);
 
-pub struct Frame<'gfx> {
-    graphics: &'gfx GraphicsContext,
-    _current_texture: wgpu::SurfaceTexture,
-    current_texture_view: wgpu::TextureView,
-    encoder: wgpu::CommandEncoder,
-}
+        GraphicsContext::SHADOW_CASCADES
+    }
 
-impl<'gfx> Frame<'gfx> {
-    pub fn begin_render_pass<'frame>(&'frame mut self) -> Pass<'gfx, 'frame> {
-        let pass = self.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: None,
-            color_attachments: &[wgpu::RenderPassColorAttachment {
-                view: &self.current_texture_view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3,
-                        a: 1.0,
-                    }),
-                    store: true,
-                },
-            }],
+    pub fn begin_shadow_pass<'frame>(&'frame mut self, cascade: usize) -> ShadowPass<'gfx, 'frame> {
+        let cascade = &self.graphics.shadow_cascades[cascade];
+        let mut pass = self.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &self.graphics.depth_view,
+                view: &cascade.view,
                 depth_ops: Some(wgpu::Operations {
                     load: wgpu::LoadOp::Clear(1.0),
-                    store: false,
+                    store: true,
                 }),
                 stencil_ops: None,
             }),
         });
 
-        Pass {
+        pass.set_pipeline(&self.graphics.shadow_pipeline);
+        pass.set_bind_group(1, &cascade.bind_group, &[]);
+
+        ShadowPass {
             graphics: self.graphics,
+            uniforms: &mut self.uniforms,
             pass,
         }
     }
 
     pub fn submit(self) {
-        self.graphics.queue.submit(Some(self.encoder.finish()));
+        let chunk_size = self.uniforms.chunk_size();
+        for (index, data) in self.uniforms.data.chunks(chunk_size).enumerate() {
+            let chunk = self.graphics.get_uniform_chunk(index);
+            self.graphics.queue.write_buffer(&chunk.buffer, 0, data);
+        }
+
+        self.graphics.queue.write_buffer(
+            &self.graphics.debug_lines.vertex_buffer,
+            0,
+            bytemuck::cast_slice(&self.debug_vertices),
+        );
+
+        let bindings = &self.graphics.environment;
+        let mut data = vec![0; bindings.stride * self.pass_views.len()];
+        for (view, chunk) in self.pass_views.iter().zip(data.chunks_mut(bindings.stride)) {
+            let uniforms =
+                EnvironmentUniforms::new(&self.environment, self.shadow_cascades.as_ref(), *view);
+            chunk[..std::mem::size_of::<EnvironmentUniforms>()]
+                .copy_from_slice(bytemuck::bytes_of(&uniforms));
+        }
+        if !data.is_empty() {
+            self.graphics.queue.write_buffer(&bindings.buffer, 0, &data);
+        }
+
+        self.graphics.queue.write_buffer(
+            &self.graphics.tone_map.buffer,
+            0,
+            bytemuck::cast_slice(&[ToneMappingUniforms::new(&self.environment)]),
+        );
+
+        let mut encoder = self.encoder;
+        let bloom = &self.environment.bloom;
+        if bloom.enabled {
+            self.graphics.queue.write_buffer(
+                &self.graphics.bloom.buffer,
+                0,
+                bytemuck::cast_slice(&[BloomUniforms::new(bloom)]),
+            );
+            self.graphics.render_bloom(&mut encoder);
+        }
+
+        let post_effects = &self.environment.post_effects;
+        let post_effects =
+            &post_effects[..post_effects.len().min(GraphicsContext::MAX_POST_EFFECTS)];
+        let tone_map_target = if post_effects.is_empty() {
+            &self.current_texture_view
+        } else {
+            &self.graphics.screen_targets.post_targets[0]
+        };
+        draw_fullscreen(
+            &mut encoder,
+            "Tone Mapping Pass",
+            tone_map_target,
+            &self.graphics.tone_map.pipeline,
+            &self.graphics.screen_targets.tone_map_bind_group,
+        );
+        self.graphics
+            .render_post_effects(&mut encoder, post_effects, &self.current_texture_view);
+
+        self.graphics.queue.submit(Some(encoder.finish()));
+        self.graphics.frame_stats.set(self.stats);
+    }
+}
+
+/// What the render passes of a frame sent to the GPU, to see how well draws are batched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub draws: u32,
+    pub pipeline_changes: u32,
+    /// texture and environment bind groups, the uniforms are bound for every draw
+    pub bind_group_changes: u32,
+    /// index, vertex and instance buffers
+    pub buffer_changes: u32,
+    /// nodes skipped because they were out of view
+    pub culled: u32,
+}
+
+/// A draw submitted to a pass, kept until the pass ends.
+struct QueuedDraw<'frame> {
+    mesh: &'frame Mesh,
+    diffuse: &'frame Texture,
+    normal: &'frame Texture,
+    instances: &'frame InstanceBuffer,
+    features: ShaderFeatures,
+    custom: Option<&'frame CustomShader>,
+    /// chunk and dynamic offset of the uniforms
+    uniforms: (usize, u32),
+    /// dynamic offset of the parameters of a custom material, in the same chunk
+    parameters: Option<u32>,
+    /// view space distance from the camera
+    depth: f32,
+}
+
+impl QueuedDraw<'_> {
+    /// Draws with equal keys share their state.
+    fn state_key(&self) -> [usize; 6] {
+        [
+            self.features.index(),
+            self.custom.map_or(0, |it| it as *const _ as usize),
+            self.diffuse.bind_group() as *const _ as usize,
+            self.normal.bind_group() as *const _ as usize,
+            self.mesh.vertex() as *const _ as usize,
+            &*self.instances.buffer as *const _ as usize,
+        ]
+    }
+}
+
+struct QueuedLines {
+    /// chunk and dynamic offset of the uniforms
+    uniforms: (usize, u32),
+    /// in the frame's debug vertices
+    vertices: Range<u32>,
+    depth_test: bool,
+}
+
+/// The state last set on a pass, to skip setting it again.
+#[derive(Default)]
+struct BoundState {
+    pipeline: Option<*const wgpu::RenderPipeline>,
+    diffuse: Option<*const wgpu::BindGroup>,
+    normal: Option<*const wgpu::BindGroup>,
+    mesh: Option<*const wgpu::Buffer>,
+    instances: Option<*const wgpu::Buffer>,
+}
+
+/// Records `it` as bound, returns false if it already was.
+fn bind<T>(bound: &mut Option<*const T>, it: &T) -> bool {
+    let it = it as *const T;
+    if *bound == Some(it) {
+        return false;
     }
+    *bound = Some(it);
+    true
 }
 
-pub struct Pass<'gfx, 'frame> {
+/// Draws are queued and sent when the pass is dropped. Opaque draws are sorted so
+/// the ones sharing textures and meshes go together, and transparent draws are
+/// drawn after them from back to front.
+///
+/// With ambient occlusion enabled, the opaque draws of passes into the frame are
+/// rendered in a prepass first. Everything in a pass is drawn from the camera it
+/// was started with.
+pub struct Pass<'gfx: 'frame, 'frame> {
     graphics: &'gfx GraphicsContext,
-    pass: wgpu::RenderPass<'frame>,
+    description: PassDescription<'frame>,
+    encoder: &'frame mut wgpu::CommandEncoder,
+    uniforms: &'frame mut UniformArena,
+    stats: &'frame mut RenderStats,
+    debug_vertices: &'frame mut Vec<DebugVertex>,
+    environment: &'frame Environment,
+    /// in place of the context's, for passes into the frame and into render targets
+    environment_bind_groups: Option<&'frame [Rc<wgpu::BindGroup>; 2]>,
+    /// of the pass's uniforms in the environment buffer
+    environment_offset: u32,
+    perspective: Mat4,
+    view: Mat4,
+    queue: Vec<QueuedDraw<'frame>>,
+    /// drawn after everything else
+    debug_queue: Vec<QueuedLines>,
 }
 
 impl<'gfx: 'frame, 'frame> Pass<'gfx, 'frame> {
-    pub fn render_mesh(
+    /// Projection and view matrices of the camera of the pass, multiplied.
+    pub fn view_projection(&self) -> Mat4 {
+        self.perspective * self.view
+    }
+
+    pub fn render_mesh(&mut self, mesh: &'frame Mesh, material: &'frame Material, model: Mat4) {
+        let instances = self.graphics.get_identity_instances();
+        self.render_mesh_instanced(mesh, material, model, instances);
+    }
+
+    /// Draws every instance in `instances` with a single draw call.
+    pub fn render_mesh_instanced(
         &mut self,
         mesh: &'frame Mesh,
-        uniform_buffer: &'frame UniformBuffer,
         material: &'frame Material,
-        perspective: Mat4,
-        view: Mat4,
         model: Mat4,
+        instances: &'frame InstanceBuffer,
     ) {
-        let diffuse = material.diffuse.as_ref();
-        let normal = material.normal.as_ref();
+        let custom = material.shader.as_ref().map(|it| &*it.shader);
+        let (diffuse, normal) = match custom {
+            Some(custom) => (custom.textures[0].as_ref(), custom.textures[1].as_ref()),
+            None => (material.diffuse.as_ref(), material.normal.as_ref()),
+        };
+        let modelview = self.view * model;
 
         let uniforms = Uniforms {
-            mvp: (perspective * view * model).to_cols_array(),
-            transpose_inverse_modelview: (view * model).inverse().transpose().to_cols_array(),
-            light_direction: [-1.0, 0.4, 0.9f32, 0.0],
+            mvp: (self.perspective * modelview).to_cols_array(),
+            transpose_inverse_modelview: modelview.inverse().transpose().to_cols_array(),
+            model: model.to_cols_array(),
             base_diffuse_color: material.base_diffuse_color,
-            has_diffuse_texture: if diffuse.is_some() { 1 } else { 0 },
-            has_normal_texture: if normal.is_some() { 1 } else { 0 },
-            shaded: if material.shaded { 1 } else { 0 },
+            diffuse_tex_coord: material.diffuse_tex_coord,
+            normal_tex_coord: material.normal_tex_coord,
+            _padding: [0; 2],
+        };
+        let (chunk, offset, parameters) = match &material.shader {
+            Some(shader) => {
+                let (chunk, offset, parameters) = self
+                    .uniforms
+                    .push_with_parameters(&uniforms, &shader.parameters);
+                (chunk, offset, Some(parameters))
+            }
+            None => {
+                let (chunk, offset) = self.uniforms.push(&uniforms);
+                (chunk, offset, None)
+            }
         };
-        self.graphics.queue.write_buffer(
-            &uniform_buffer.buffer,
-            0,
-            bytemuck::cast_slice(&[uniforms]),
-        );
-
-        let diffuse = diffuse.unwrap_or_else(|| self.graphics.get_default_texture());
-        let normal = normal.unwrap_or_else(|| self.graphics.get_default_texture());
 
-        self.pass.set_pipeline(&self.graphics.pipeline);
-        self.pass.set_bind_group(0, &uniform_buffer.bind_group, &[]);
-        self.pass.set_bind_group(1, diffuse.bind_group(), &[]);
-        self.pass.set_bind_group(2, normal.bind_group(), &[]);
-        self.pass
-            .set_index_buffer(mesh.index().slice(..), wgpu::IndexFormat::Uint16);
-        self.pass.set_vertex_buffer(0, mesh.vertex().slice(..));
-        self.pass.draw_indexed(0..mesh.index_count as u32, 0, 0..1);
+        self.queue.push(QueuedDraw {
+            mesh,
+            diffuse: diffuse.unwrap_or_else(|| self.graphics.get_default_texture()),
+            normal: normal.unwrap_or_else(|| self.graphics.get_default_texture()),
+            instances,
+            features: material.shader_features(),
+            custom,
+            uniforms: (chunk, offset),
+            parameters,
+            // the camera looks down -z
+            depth: -modelview.w_axis.z,
+        });
     }
 
-    pub fn render_billboard(
-        &mut self,
-        uniform_buffer: &'frame UniformBuffer,
-        material: &'frame Material,
-        perspective: Mat4,
-        view: Mat4,
-        position: Vec3,
-        camera_position: Vec3,
-    ) {
+    /// Draws the diffuse texture of `material` on a quad at `position`, turned
+    /// towards the camera.
+    pub fn render_billboard(&mut self, material: &'frame Material, position: Vec3) {
         let mesh = self.graphics.get_quad_mesh();
+        let camera_position = self.view.inverse().transform_point3(Vec3::ZERO);
         let texture = material.diffuse.as_ref().unwrap();
 
         let w = texture.width as f32;
@@ -610,6 +3914,320 @@ impl<'gfx: 'frame, 'frame> Pass<'gfx, 'frame> {
         };
         let model = Mat4::from_translation(position) * rotation * Mat4::from_scale(scale);
 
-        self.render_mesh(&mesh, uniform_buffer, material, perspective, view, model);
+        self.render_mesh(mesh, material, model);
+    }
+
+    /// Draws the shapes in `debug` over everything else in the pass.
+    pub fn render_debug(&mut self, debug: &DebugDraw) {
+        // the debug shader only reads the first matrix
+        let uniforms = Uniforms {
+            mvp: self.view_projection().to_cols_array(),
+            ..Zeroable::zeroed()
+        };
+        let uniforms = self.uniforms.push(&uniforms);
+
+        for depth_test in [true, false] {
+            let start = self.debug_vertices.len();
+            let lines = debug
+                .lines()
+                .iter()
+                .filter(|it| it.depth_test == depth_test);
+            for line in lines {
+                if self.debug_vertices.len() + 2 > GraphicsContext::MAX_DEBUG_VERTICES {
+                    break;
+                }
+                for position in [line.start, line.end] {
+                    self.debug_vertices.push(DebugVertex {
+                        position: position.into(),
+                        color: line.color,
+                    });
+                }
+            }
+
+            let end = self.debug_vertices.len();
+            if end > start {
+                self.debug_queue.push(QueuedLines {
+                    uniforms,
+                    vertices: start as u32..end as u32,
+                    depth_test,
+                });
+            }
+        }
+    }
+
+    /// Counts something that wasn't submitted because it was out of view.
+    pub fn record_culled(&mut self) {
+        self.stats.culled += 1;
+    }
+
+    fn flush(&mut self) {
+        let mut queue = std::mem::take(&mut self.queue);
+        queue.sort_by(
+            |a, b| match (a.features.transparent, b.features.transparent) {
+                (false, false) => a.state_key().cmp(&b.state_key()),
+                (true, true) => b.depth.partial_cmp(&a.depth).unwrap_or(Ordering::Equal),
+                (a, b) => a.cmp(&b),
+            },
+        );
+
+        let graphics = self.graphics;
+        let description = self.description;
+        let ambient_occlusion = &self.environment.ambient_occlusion;
+        let occluded = description.target.is_none() && ambient_occlusion.enabled;
+        if occluded {
+            graphics.queue.write_buffer(
+                &graphics.ambient_occlusion.buffer,
+                0,
+                bytemuck::cast_slice(&[AmbientOcclusionUniforms::new(
+                    ambient_occlusion,
+                    self.perspective,
+                )]),
+            );
+            graphics.render_ambient_occlusion(self.encoder, &queue);
+        }
+
+        // multisampled draws are resolved at the end of the pass, and everything
+        // rendered into the frame is tone mapped into its texture when it's submitted
+        let (color, msaa, depth, environment) = match description.target {
+            Some(target) => (
+                &target.color,
+                target.msaa.as_ref(),
+                &target.depth.view,
+                self.environment_bind_groups
+                    .map_or(&graphics.unoccluded_environment_bind_group, |it| &*it[1]),
+            ),
+            None => (
+                &graphics.screen_targets.hdr,
+                graphics.msaa_target.as_ref(),
+                &graphics.depth.view,
+                self.environment_bind_groups
+                    .map_or(&graphics.screen_targets.environment_bind_group, |it| {
+                        &*it[0]
+                    }),
+            ),
+        };
+
+        // the sky takes the place of the clear color
+        let settings = self.environment;
+        let skybox = match &settings.environment_map {
+            Some(map) if settings.skybox && description.clear_color.is_some() => {
+                let rotation = Mat4::from_mat3(Mat3::from_mat4(self.view));
+                let intensity = settings.environment_intensity;
+                // the skybox shader reads the first matrix and the color
+                let uniforms = Uniforms {
+                    mvp: (self.perspective * rotation).inverse().to_cols_array(),
+                    base_diffuse_color: [intensity, intensity, intensity, 1.0],
+                    ..Zeroable::zeroed()
+                };
+                Some((map, self.uniforms.push(&uniforms)))
+            }
+            _ => None,
+        };
+
+        let attachment = |color, msaa: Option<_>, load| {
+            let (view, resolve_target) = match msaa {
+                Some(msaa) => (msaa, Some(color)),
+                None => (color, None),
+            };
+            wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations { load, store: true },
+            }
+        };
+        let extra_load = match description.clear_color {
+            Some(_) => wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+            None => wgpu::LoadOp::Load,
+        };
+        let color_attachments: Vec<_> = std::iter::once(attachment(
+            color,
+            msaa,
+            match description.clear_color {
+                Some([r, g, b, a]) => wgpu::LoadOp::Clear(wgpu::Color {
+                    r: r as f64,
+                    g: g as f64,
+                    b: b as f64,
+                    a: a as f64,
+                }),
+                None => wgpu::LoadOp::Load,
+            },
+        ))
+        .chain(
+            description
+                .extra_targets()
+                .map(|it| attachment(&it.color, it.msaa.as_ref(), extra_load)),
+        )
+        .collect();
+        let extra_targets = color_attachments.len() - 1;
+        let mut pass = self.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &color_attachments,
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: match description.clear_depth {
+                        Some(depth) => wgpu::LoadOp::Clear(depth),
+                        None => wgpu::LoadOp::Load,
+                    },
+                    store: description.store_depth,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        let stats = &mut *self.stats;
+        let mut bound = BoundState::default();
+        if let Some((map, (chunk, offset))) = skybox {
+            let pipeline = graphics.get_skybox_pipeline(extra_targets);
+            bind(&mut bound.pipeline, pipeline);
+            pass.set_pipeline(pipeline);
+            stats.pipeline_changes += 1;
+            let chunk = graphics.get_uniform_chunk(chunk);
+            pass.set_bind_group(0, &chunk.bind_group, &[offset]);
+            pass.set_bind_group(1, &map.sky_bind_group, &[]);
+            stats.bind_group_changes += 1;
+            pass.draw(0..3, 0..1);
+            stats.draws += 1;
+        }
+        if !queue.is_empty() {
+            pass.set_bind_group(3, environment, &[self.environment_offset]);
+            stats.bind_group_changes += 1;
+        }
+        for draw in &queue {
+            let custom = draw
+                .custom
+                .zip(draw.parameters)
+                .filter(|_| !graphics.debug_view.replaces_materials());
+            let pipeline = match custom {
+                Some((custom, _)) => {
+                    graphics.get_custom_pipeline(custom, draw.features.transparent, extra_targets)
+                }
+                None => graphics.get_pipeline(draw.features, extra_targets),
+            };
+            if bind(&mut bound.pipeline, pipeline) {
+                pass.set_pipeline(pipeline);
+                stats.pipeline_changes += 1;
+            }
+
+            let (chunk, offset) = draw.uniforms;
+            let chunk = graphics.get_uniform_chunk(chunk);
+            match custom {
+                Some((_, parameters)) => {
+                    pass.set_bind_group(0, &chunk.material_bind_group, &[offset, parameters])
+                }
+                None => pass.set_bind_group(0, &chunk.bind_group, &[offset]),
+            }
+            if bind(&mut bound.diffuse, draw.diffuse.bind_group()) {
+                pass.set_bind_group(1, draw.diffuse.bind_group(), &[]);
+                stats.bind_group_changes += 1;
+            }
+            if bind(&mut bound.normal, draw.normal.bind_group()) {
+                pass.set_bind_group(2, draw.normal.bind_group(), &[]);
+                stats.bind_group_changes += 1;
+            }
+
+            if bind(&mut bound.mesh, draw.mesh.vertex()) {
+                pass.set_index_buffer(draw.mesh.index().slice(..), wgpu::IndexFormat::Uint16);
+                pass.set_vertex_buffer(0, draw.mesh.vertex().slice(..));
+                stats.buffer_changes += 2;
+            }
+            if bind(&mut bound.instances, &*draw.instances.buffer) {
+                pass.set_vertex_buffer(1, draw.instances.buffer.slice(..));
+                stats.buffer_changes += 1;
+            }
+
+            pass.draw_indexed(0..draw.mesh.index_count as u32, 0, 0..draw.instances.count);
+            stats.draws += 1;
+        }
+
+        if graphics.debug_view == DebugView::Wireframe && !queue.is_empty() {
+            // the textures stay bound, the wireframe shader doesn't read them
+            pass.set_pipeline(graphics.get_wireframe_pipeline(extra_targets));
+            stats.pipeline_changes += 1;
+            for draw in &queue {
+                let (chunk, offset) = draw.uniforms;
+                let chunk = graphics.get_uniform_chunk(chunk);
+                pass.set_bind_group(0, &chunk.bind_group, &[offset]);
+                if bind(&mut bound.mesh, draw.mesh.vertex()) {
+                    pass.set_index_buffer(draw.mesh.index().slice(..), wgpu::IndexFormat::Uint16);
+                    pass.set_vertex_buffer(0, draw.mesh.vertex().slice(..));
+                    stats.buffer_changes += 2;
+                }
+                if bind(&mut bound.instances, &*draw.instances.buffer) {
+                    pass.set_vertex_buffer(1, draw.instances.buffer.slice(..));
+                    stats.buffer_changes += 1;
+                }
+                pass.draw_indexed(0..draw.mesh.index_count as u32, 0, 0..draw.instances.count);
+                stats.draws += 1;
+            }
+            bound.pipeline = None;
+        }
+
+        for lines in std::mem::take(&mut self.debug_queue) {
+            let pipeline = graphics.get_debug_pipeline(lines.depth_test, extra_targets);
+            if bind(&mut bound.pipeline, pipeline) {
+                pass.set_pipeline(pipeline);
+                stats.pipeline_changes += 1;
+            }
+            let (chunk, offset) = lines.uniforms;
+            let chunk = graphics.get_uniform_chunk(chunk);
+            pass.set_bind_group(0, &chunk.bind_group, &[offset]);
+            let vertex_buffer = &graphics.debug_lines.vertex_buffer;
+            if bind(&mut bound.mesh, vertex_buffer) {
+                pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                stats.buffer_changes += 1;
+            }
+            pass.draw(lines.vertices, 0..1);
+            stats.draws += 1;
+        }
+    }
+}
+
+impl<'gfx: 'frame, 'frame> Drop for Pass<'gfx, 'frame> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Renders the depth of shadow casters from the sun, into one of the cascades.
+pub struct ShadowPass<'gfx, 'frame> {
+    graphics: &'gfx GraphicsContext,
+    uniforms: &'frame mut UniformArena,
+    pass: wgpu::RenderPass<'frame>,
+}
+
+impl<'gfx: 'frame, 'frame> ShadowPass<'gfx, 'frame> {
+    pub fn render_mesh(&mut self, mesh: &'frame Mesh, material: &'frame Material, model: Mat4) {
+        let instances = self.graphics.get_identity_instances();
+        self.render_mesh_instanced(mesh, material, model, instances);
+    }
+
+    /// Meshes whose material doesn't cast shadows are skipped.
+    pub fn render_mesh_instanced(
+        &mut self,
+        mesh: &'frame Mesh,
+        material: &'frame Material,
+        model: Mat4,
+        instances: &'frame InstanceBuffer,
+    ) {
+        if !material.cast_shadows {
+            return;
+        }
+
+        // the shadow shader only reads the model matrix
+        let uniforms = Uniforms {
+            model: model.to_cols_array(),
+            ..Zeroable::zeroed()
+        };
+        let (chunk, offset) = self.uniforms.push(&uniforms);
+        let chunk = self.graphics.get_uniform_chunk(chunk);
+
+        self.pass.set_bind_group(0, &chunk.bind_group, &[offset]);
+        self.pass
+            .set_index_buffer(mesh.index().slice(..), wgpu::IndexFormat::Uint16);
+        self.pass.set_vertex_buffer(0, mesh.vertex().slice(..));
+        self.pass.set_vertex_buffer(1, instances.buffer.slice(..));
+        self.pass
+            .draw_indexed(0..mesh.index_count as u32, 0, 0..instances.count);
     }
 }
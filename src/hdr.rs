@@ -0,0 +1,284 @@
+use std::path::Path;
+
+use crate::AyudeError;
+
+/// Linear colors, row by row from the top.
+pub(crate) struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl HdrImage {
+    /// Pixels as half precision floats, for 16 bit float textures.
+    pub fn to_f16_bytes(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flatten()
+            .flat_map(|it| f16_bits(*it).to_le_bytes())
+            .collect()
+    }
+}
+
+/// Reads a Radiance `.hdr` image, or any other format the image crate reads,
+/// whose colors are taken as sRGB.
+pub(crate) fn load(path: &Path) -> Result<HdrImage, AyudeError> {
+    let bytes = std::fs::read(path)?;
+    let is_hdr = path
+        .extension()
+        .is_some_and(|it| it.eq_ignore_ascii_case("hdr"));
+    if is_hdr {
+        return decode(&bytes);
+    }
+
+    let image = image::load_from_memory(&bytes)
+        .map_err(|e| AyudeError::InvalidImage(e.to_string()))?
+        .to_rgba8();
+    let to_linear = |it: u8| {
+        let it = it as f32 / 255.0;
+        if it <= 0.04045 {
+            it / 12.92
+        } else {
+            ((it + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Ok(HdrImage {
+        width: image.width(),
+        height: image.height(),
+        pixels: image
+            .pixels()
+            .map(|it| {
+                let [r, g, b, a] = it.0;
+                [to_linear(r), to_linear(g), to_linear(b), a as f32 / 255.0]
+            })
+            .collect(),
+    })
+}
+
+/// Decodes the RGBE pixels of a Radiance image, flat or with the run length
+/// encoding of each channel. Only the usual `-Y height +X width` orientation is
+/// supported, and not the old run length encoding.
+pub(crate) fn decode(bytes: &[u8]) -> Result<HdrImage, AyudeError> {
+    let invalid = |reason: &str| AyudeError::InvalidImage(format!("hdr: {}", reason));
+
+    let mut lines = bytes.split(|it| *it == b'\n');
+    let mut offset = 0;
+    // lines may end with \r\n too
+    let mut next_line = |missing: &str| {
+        let line = match lines.next() {
+            Some(line) => line,
+            None => return Err(invalid(missing)),
+        };
+        offset += line.len() + 1;
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        std::str::from_utf8(line).map_err(|_| invalid("the header isn't text"))
+    };
+
+    let magic = next_line("empty file")?;
+    if !magic.starts_with("#?") {
+        return Err(invalid("not a Radiance image"));
+    }
+    loop {
+        let line = next_line("unterminated header")?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid("only the RGBE format is supported"));
+            }
+        }
+    }
+    let resolution = next_line("missing resolution")?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (height.parse().ok(), width.parse().ok()),
+        _ => return Err(invalid("unsupported orientation")),
+    };
+    let (height, width): (usize, usize) = height
+        .zip(width)
+        .ok_or_else(|| invalid("invalid resolution"))?;
+
+    let mut data = &bytes[offset.min(bytes.len())..];
+    // a run takes 2 bytes for up to 127 texels of a channel, so there can't be
+    // more than 16 pixels for each byte
+    let count = width
+        .checked_mul(height)
+        .filter(|it| *it > 0)
+        .ok_or_else(|| invalid("invalid resolution"))?;
+    if count / 16 > data.len() {
+        return Err(invalid("truncated pixels"));
+    }
+
+    let mut take = |count: usize| {
+        if data.len() < count {
+            return Err(invalid("truncated pixels"));
+        }
+        let (taken, rest) = data.split_at(count);
+        data = rest;
+        Ok(taken)
+    };
+
+    let mut pixels = Vec::with_capacity(count);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        let start = take(4.min(width * 4))?;
+        let encoded = (8..0x8000).contains(&width)
+            && start[0] == 2
+            && start[1] == 2
+            && (start[2] as usize) << 8 | start[3] as usize == width;
+        if encoded {
+            // each channel is stored separately, in runs and literal spans
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = take(1)?[0] as usize;
+                    let (count, run) = match count > 128 {
+                        true => (count - 128, true),
+                        false => (count, false),
+                    };
+                    if count == 0 || x + count > width {
+                        return Err(invalid("invalid run length"));
+                    }
+                    if run {
+                        let value = take(1)?[0];
+                        for pixel in &mut scanline[x..x + count] {
+                            pixel[channel] = value;
+                        }
+                    } else {
+                        for (pixel, value) in scanline[x..x + count].iter_mut().zip(take(count)?) {
+                            pixel[channel] = *value;
+                        }
+                    }
+                    x += count;
+                }
+            }
+        } else {
+            let rest = take(width * 4 - start.len())?;
+            for (pixel, rgbe) in scanline
+                .iter_mut()
+                .zip(start.chunks_exact(4).chain(rest.chunks_exact(4)))
+            {
+                pixel.copy_from_slice(rgbe);
+            }
+        }
+
+        pixels.extend(scanline.iter().map(|&[r, g, b, e]| {
+            if e == 0 {
+                return [0.0, 0.0, 0.0, 1.0];
+            }
+            // the mantissas are 8 bit fractions
+            let scale = 2f32.powi(e as i32 - 128 - 8);
+            [r as f32 * scale, g as f32 * scale, b as f32 * scale, 1.0]
+        }));
+    }
+
+    Ok(HdrImage {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+/// Bits of the half precision float closest to `value`, rounding towards zero.
+/// Values too large for it become the largest finite one, so bright spots don't
+/// turn into infinities when they are filtered.
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent >= 31 {
+        sign | 0x7bff
+    } else if exponent <= 0 {
+        // subnormal, with the implicit leading bit made explicit
+        if exponent < -10 {
+            return sign;
+        }
+        sign | ((mantissa | 0x80_0000) >> (14 - exponent)) as u16
+    } else {
+        sign | (exponent as u16) << 10 | (mantissa >> 13) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(header: &str, pixels: &[u8]) -> Vec<u8> {
+        [header.as_bytes(), pixels].concat()
+    }
+
+    #[test]
+    fn decodes_flat_pixels() {
+        let bytes = image(
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n",
+            &[128, 64, 0, 129, 7, 7, 7, 0],
+        );
+        let image = decode(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels, [[1.0, 0.5, 0.0, 1.0], [0.0, 0.0, 0.0, 1.0]]);
+    }
+
+    #[test]
+    fn decodes_runs_and_literal_spans() {
+        #[rustfmt::skip]
+        let pixels = [
+            2, 2, 0, 8,
+            // red in a single run
+            128 + 8, 128,
+            // green in two runs
+            128 + 3, 64, 128 + 5, 32,
+            // blue in a literal span and a run
+            3, 0, 64, 128, 128 + 5, 0,
+            // exponent in a literal span
+            8, 129, 129, 129, 129, 129, 129, 129, 129,
+        ];
+        let bytes = image("#?RADIANCE\n\n-Y 1 +X 8\n", &pixels);
+        let image = decode(&bytes).unwrap();
+        assert_eq!(image.pixels[0], [1.0, 0.5, 0.0, 1.0]);
+        assert_eq!(image.pixels[2], [1.0, 0.5, 1.0, 1.0]);
+        assert_eq!(image.pixels[7], [1.0, 0.25, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn accepts_crlf_lines() {
+        let bytes = image(
+            "#?RADIANCE\r\nFORMAT=32-bit_rle_rgbe\r\n\r\n-Y 1 +X 1\r\n",
+            &[128, 128, 128, 129],
+        );
+        assert_eq!(decode(&bytes).unwrap().pixels, [[1.0; 4]]);
+    }
+
+    #[test]
+    fn rejects_invalid_images() {
+        let invalid = |header: &str, pixels: &[u8]| decode(&image(header, pixels)).is_err();
+        assert!(decode(b"#?RADIANCE\n\xff\n\n-Y 1 +X 1\n\0\0\0\0").is_err());
+        assert!(invalid("#?RADIANCE\n\n-Y 100000 +X 100000\n", &[0; 64]));
+        assert!(invalid("#?RADIANCE\n\n-Y 2 +X 1\n", &[0; 4]));
+        assert!(invalid(
+            "#?RADIANCE\n\n-Y 1 +X 8\n",
+            &[2, 2, 0, 8, 128 + 9, 0]
+        ));
+        assert!(invalid("#?RADIANCE\n\n+Y 1 +X 1\n", &[0; 4]));
+    }
+
+    #[test]
+    fn converts_to_half_floats() {
+        assert_eq!(f16_bits(0.0), 0);
+        assert_eq!(f16_bits(1.0), 0x3c00);
+        assert_eq!(f16_bits(0.5), 0x3800);
+        assert_eq!(f16_bits(-2.0), 0xc000);
+        assert_eq!(f16_bits(65504.0), 0x7bff);
+        // the smallest normal and subnormal numbers
+        assert_eq!(f16_bits(2f32.powi(-14)), 0x0400);
+        assert_eq!(f16_bits(2f32.powi(-24)), 0x0001);
+        assert_eq!(f16_bits(2f32.powi(-30)), 0);
+        assert_eq!(f16_bits(1e6), 0x7bff);
+        assert_eq!(f16_bits(f32::INFINITY), 0x7bff);
+        assert_eq!(f16_bits(f32::NAN) & 0x7fff, 0x7e00);
+    }
+}
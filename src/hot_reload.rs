@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::asset_cache::AssetCache;
+use crate::graphics::GraphicsContext;
+use crate::import_gltf::{self, ImportGltfError};
+use crate::Scene;
+
+/// Watches the files imported scenes were read from, and imports them again
+/// when any of them changes on disk.
+pub struct AssetReloader {
+    /// source file of a scene -> its dependencies and when they were last modified
+    watched: HashMap<PathBuf, Vec<(PathBuf, Option<SystemTime>)>>,
+    interval: Duration,
+    last_poll: Instant,
+}
+
+impl AssetReloader {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            watched: HashMap::new(),
+            interval,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Starts watching the files `scene` was imported from.
+    pub fn watch(&mut self, scene: &Scene, cache: &AssetCache) {
+        let source = match &scene.source {
+            Some(source) => source,
+            None => return,
+        };
+        let files = cache
+            .dependencies(source)
+            .iter()
+            .map(|file| (file.clone(), modified(file)))
+            .collect();
+        self.watched.insert(source.clone(), files);
+    }
+
+    /// Returns the sources with a dependency that changed since the last poll.
+    /// Files are checked at most once per interval.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < self.interval {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = vec![];
+        for (source, files) in &mut self.watched {
+            let mut source_changed = false;
+            for (file, last_modified) in files.iter_mut() {
+                let current = modified(file);
+                if current != *last_modified {
+                    *last_modified = current;
+                    source_changed = true;
+                }
+            }
+            if source_changed {
+                changed.push(source.clone());
+            }
+        }
+        changed
+    }
+
+    /// Imports the changed sources again and swaps the result into every scene
+    /// that was imported from them. A failed import leaves the scenes untouched
+    /// and is returned instead.
+    pub fn reload_changed(
+        &mut self,
+        scenes: &mut [&mut Scene],
+        graphics: &GraphicsContext,
+        cache: &mut AssetCache,
+    ) -> Vec<(PathBuf, ImportGltfError)> {
+        let mut errors = vec![];
+        for source in self.poll() {
+            cache.evict_source(&source);
+
+            let reloaded =
+                match import_gltf::import_default_scene(&source.to_string_lossy(), graphics, cache)
+                {
+                    Ok(it) => it,
+                    Err(e) => {
+                        errors.push((source, e));
+                        continue;
+                    }
+                };
+
+            let targets = scenes
+                .iter_mut()
+                .filter(|it| it.source.as_deref() == Some(&source));
+            for scene in targets {
+                scene.reload_from(reloaded.clone());
+            }
+
+            // the new import may depend on different files
+            self.watch(&reloaded, cache);
+        }
+        errors
+    }
+}
+
+fn modified(file: &Path) -> Option<SystemTime> {
+    std::fs::metadata(file).and_then(|it| it.modified()).ok()
+}
@@ -1,91 +1,223 @@
-use std::{borrow::Cow, convert::TryInto, iter::repeat};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    convert::TryInto,
+    iter::repeat,
+    path::{Path, PathBuf},
+};
 
-use glam::Mat4;
+use glam::{Mat4, Quat, Vec3};
 use image::{DynamicImage, EncodableLayout, ImageError, ImageFormat};
-use smallvec::SmallVec;
 
 use crate::{
-    graphics::{
-        GraphicsContext, Material, Mesh, Texture, TextureDescription, UniformBuffer, Vertex,
+    asset_cache::{AssetCache, AssetKey},
+    bounds::Aabb,
+    cooked::{
+        self, CookedMaterial, CookedMesh, CookedNode, CookedPrimitive, CookedScene, CookedSkin,
+        CookedTexture,
     },
+    graphics::{GraphicsContext, Material, Mesh, Texture, TextureDescription, Vertex},
+    meshopt::{self, MeshoptError},
     transform::Transform,
-    Node, Scene, Skin,
+    Node, NodeIndex, NodeIndices, Scene, Skin,
 };
 
 pub fn import_default_scene(
     file_name: &str,
     graphics: &GraphicsContext,
+    cache: &mut AssetCache,
 ) -> Result<Scene, ImportGltfError> {
-    let gltf = gltf::Gltf::open(file_name)?;
-    let base_path = file_name[0..file_name.rfind("/").unwrap()].to_string();
+    import(file_name, graphics, cache, None).map(|it| it.0)
+}
+
+/// Imports the scene and also records everything that was uploaded, so it can be
+/// written to a cooked file.
+pub(crate) fn import_default_scene_and_cook(
+    file_name: &str,
+    graphics: &GraphicsContext,
+    cache: &mut AssetCache,
+) -> Result<(Scene, CookedScene<'static>), ImportGltfError> {
+    let (scene, cook) = import(file_name, graphics, cache, Some(CookedScene::default()))?;
+    Ok((scene, cook.unwrap_or_default()))
+}
+
+fn import(
+    file_name: &str,
+    graphics: &GraphicsContext,
+    cache: &mut AssetCache,
+    cook: Option<CookedScene<'static>>,
+) -> Result<(Scene, Option<CookedScene<'static>>), ImportGltfError> {
+    let bytes = std::fs::read(file_name)?;
+    let gltf = gltf::Gltf::from_slice(&bytes)?;
+
+    // the gltf crate drops extensions it doesn't know about, so those are read
+    // from the raw json
+    let json: serde_json::Value = if bytes.starts_with(b"glTF") {
+        serde_json::from_slice(&gltf::binary::Glb::from_slice(&bytes)?.json)?
+    } else {
+        serde_json::from_slice(&bytes)?
+    };
+    let instancing = match json["nodes"].as_array() {
+        Some(nodes) => nodes.iter().map(GpuInstancing::from_json).collect(),
+        None => vec![],
+    };
+    let compressed_views = match json["bufferViews"].as_array() {
+        Some(views) => views.iter().map(CompressedView::from_json).collect(),
+        None => vec![],
+    };
+    let fallback_buffers = match json["buffers"].as_array() {
+        Some(buffers) => buffers
+            .iter()
+            .map(|it| it["extensions"]["EXT_meshopt_compression"]["fallback"] == true)
+            .collect(),
+        None => vec![],
+    };
+
+    let base_path = Path::new(file_name)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let source = std::fs::canonicalize(file_name).unwrap_or_else(|_| PathBuf::from(file_name));
     let mut importer = Importer {
         blob: gltf.blob,
         buffers: vec![],
-        images: vec![],
-        textures: vec![None; gltf.document.textures().count()],
-        materials: vec![None; gltf.document.materials().count()],
-        meshes: vec![None; gltf.document.meshes().count()],
+        images: vec![None; gltf.document.images().count()],
+        instancing,
+        compressed_views,
+        fallback_buffers,
         base_path,
+        dependencies: vec![source.clone()],
+        source,
+        cook,
+        cache,
         graphics,
     };
 
-    importer.import_default_scene(gltf.document)
+    let scene = importer.import_default_scene(gltf.document)?;
+    Ok((scene, importer.cook))
 }
-struct Importer<'gfx> {
-    base_path: String,
-    blob: Option<Vec<u8>>,
 
-    buffers: Vec<Vec<u8>>,
-    images: Vec<(Vec<u8>, u32, u32, wgpu::TextureFormat)>,
+/// Accessor indices of the `EXT_mesh_gpu_instancing` attributes of a node.
+struct GpuInstancing {
+    translation: Option<usize>,
+    rotation: Option<usize>,
+    scale: Option<usize>,
+}
 
-    textures: Vec<Option<Texture>>,
-    materials: Vec<Option<Material>>,
-    meshes: Vec<Option<Vec<(Mesh, Material)>>>,
+impl GpuInstancing {
+    fn from_json(node: &serde_json::Value) -> Option<Self> {
+        let attributes = &node["extensions"]["EXT_mesh_gpu_instancing"]["attributes"];
+        if !attributes.is_object() {
+            return None;
+        }
+        let accessor = |name| attributes[name].as_u64().map(|it| it as usize);
+        Some(Self {
+            translation: accessor("TRANSLATION"),
+            rotation: accessor("ROTATION"),
+            scale: accessor("SCALE"),
+        })
+    }
+}
 
-    graphics: &'gfx GraphicsContext,
+/// A buffer view with `EXT_meshopt_compression`, its compressed data lives in
+/// another buffer and is decoded into the range of the view.
+struct CompressedView {
+    buffer: usize,
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: usize,
+    count: usize,
+    mode: meshopt::Mode,
+    filter: meshopt::Filter,
 }
 
-impl<'gfx> Importer<'gfx> {
+impl CompressedView {
+    fn from_json(view: &serde_json::Value) -> Option<Self> {
+        let ext = &view["extensions"]["EXT_meshopt_compression"];
+        let field = |name| ext[name].as_u64().map(|it| it as usize);
+        let mode = match ext["mode"].as_str()? {
+            "ATTRIBUTES" => meshopt::Mode::Attributes,
+            "TRIANGLES" => meshopt::Mode::Triangles,
+            "INDICES" => meshopt::Mode::Indices,
+            _ => return None,
+        };
+        let filter = match ext["filter"].as_str().unwrap_or("NONE") {
+            "OCTAHEDRAL" => meshopt::Filter::Octahedral,
+            "QUATERNION" => meshopt::Filter::Quaternion,
+            "EXPONENTIAL" => meshopt::Filter::Exponential,
+            _ => meshopt::Filter::None,
+        };
+        Some(Self {
+            buffer: field("buffer")?,
+            byte_offset: field("byteOffset").unwrap_or(0),
+            byte_length: field("byteLength")?,
+            byte_stride: field("byteStride")?,
+            count: field("count")?,
+            mode,
+            filter,
+        })
+    }
+}
+
+/// Rgba bytes, width, height and format of a decoded image.
+type ImportedImage = (Vec<u8>, u32, u32, wgpu::TextureFormat);
+
+struct Importer<'a> {
+    base_path: PathBuf,
+    source: PathBuf,
+    /// files read during the import, so they can be watched for changes
+    dependencies: Vec<PathBuf>,
+    blob: Option<Vec<u8>>,
+
+    buffers: Vec<Vec<u8>>,
+    images: Vec<Option<ImportedImage>>,
+    instancing: Vec<Option<GpuInstancing>>,
+    compressed_views: Vec<Option<CompressedView>>,
+    fallback_buffers: Vec<bool>,
+
+    /// only set when the import is being cooked
+    cook: Option<CookedScene<'static>>,
+    cache: &'a mut AssetCache,
+    graphics: &'a GraphicsContext,
+}
+
+impl<'a> Importer<'a> {
     fn import_default_scene(&mut self, document: gltf::Document) -> Result<Scene, ImportGltfError> {
         // check if document has default scene
         let scene = document
             .default_scene()
-            .expect("gltf document should have default scene");
+            .ok_or(ImportGltfError::DefaultSceneNotFound)?;
 
-        // pre-import buffers and images
+        // pre-import buffers, images are decoded on demand
         for buffer in document.buffers() {
             let b = self.import_gltf_buffer(buffer)?;
             self.buffers.push(b);
         }
 
-        for image in document.images() {
-            self.images.push(self.import_gltf_image(image)?);
+        for view in document.views() {
+            self.decompress_gltf_view(view)?;
         }
 
         let mut nodes = vec![];
+        let mut cooked_nodes = vec![];
 
         let root_nodes = scene
             .nodes()
-            .map(|it| map_node_to_u16_index(&it))
-            .collect::<Result<SmallVec<[u16; 4]>, ImportGltfError>>()?;
+            .map(|it| map_node_to_index(&it))
+            .collect::<Result<NodeIndices, ImportGltfError>>()?;
 
-        let mut node_stack: Vec<(gltf::Node, Option<u16>)> =
+        let mut node_stack: Vec<(gltf::Node, Option<NodeIndex>)> =
             scene.nodes().zip(repeat(None)).collect();
 
-        loop {
-            let (node, parent) = match node_stack.pop() {
-                Some(it) => it,
-                None => break,
-            };
-
-            let node_index = map_node_to_u16_index(&node)?;
+        while let Some((node, parent)) = node_stack.pop() {
+            let node_index = map_node_to_index(&node)?;
 
             node_stack.extend(node.children().zip(repeat(Some(node_index))));
 
             let children = node
                 .children()
-                .map(|it| map_node_to_u16_index(&it))
-                .collect::<Result<SmallVec<[u16; 4]>, ImportGltfError>>()?;
+                .map(|it| map_node_to_index(&it))
+                .collect::<Result<NodeIndices, ImportGltfError>>()?;
 
             let transform = Transform::from(Mat4::from_cols_array_2d(&node.transform().matrix()));
 
@@ -94,14 +226,19 @@ impl<'gfx> Importer<'gfx> {
                 None => vec![],
             };
 
+            let instances = match node.mesh() {
+                Some(_) => self.import_gpu_instancing(&document, &node)?,
+                None => vec![],
+            };
+
             let skin = match node.skin() {
                 Some(skin) => {
                     let joints = skin
                         .joints()
-                        .map(|it| map_node_to_u16_index(&it))
-                        .collect::<Result<SmallVec<[u16; 4]>, ImportGltfError>>()?;
+                        .map(|it| map_node_to_index(&it))
+                        .collect::<Result<NodeIndices, ImportGltfError>>()?;
 
-                    let skeleton = match skin.skeleton().map(|it| map_node_to_u16_index(&it)) {
+                    let skeleton = match skin.skeleton().map(|it| map_node_to_index(&it)) {
                         Some(Ok(it)) => Ok(Some(it)),
                         Some(Err(e)) => Err(e),
                         None => Ok(None),
@@ -110,21 +247,56 @@ impl<'gfx> Importer<'gfx> {
                     let inverse_bind_matrices = skin
                         .reader(|buffer| self.buffers.get(buffer.index()).map(Vec::as_slice))
                         .read_inverse_bind_matrices()
-                        .map(|it| {
-                            it.map(|mat| Transform::from(Mat4::from_cols_array_2d(&mat)))
-                                .collect()
-                        })
-                        .unwrap_or_else(|| vec![Transform::from(Mat4::IDENTITY); joints.len()]);
+                        .map(|it| it.map(|mat| Mat4::from_cols_array_2d(&mat)).collect())
+                        .unwrap_or_else(|| vec![Mat4::IDENTITY; joints.len()]);
 
                     Some(Skin {
                         joints,
                         skeleton,
-                        inverse_bind_matrices,
+                        inverse_bind_matrices: inverse_bind_matrices
+                            .into_iter()
+                            .map(Transform::from)
+                            .collect(),
                     })
                 }
                 None => None,
             };
 
+            if let Some(cook) = &mut self.cook {
+                let mesh = node
+                    .mesh()
+                    .map(|it| cook.mesh_indices[&AssetKey::gltf(&self.source, it.index())]);
+                cooked_nodes.push((
+                    node_index,
+                    CookedNode {
+                        parent: parent.map(|it| usize::from(it) as u32),
+                        children: children.iter().map(|it| usize::from(it) as u32).collect(),
+                        transform: transform.mat4().to_cols_array(),
+                        mesh,
+                        instances: instances.iter().map(Mat4::to_cols_array).collect(),
+                        skin: skin.as_ref().map(|skin| CookedSkin {
+                            joints: skin
+                                .joints
+                                .iter()
+                                .map(|it| usize::from(it) as u32)
+                                .collect(),
+                            skeleton: skin.skeleton.map(|it| usize::from(it) as u32),
+                            inverse_bind_matrices: skin
+                                .inverse_bind_matrices
+                                .iter()
+                                .map(|it| it.mat4().to_cols_array())
+                                .collect(),
+                        }),
+                        name: node.name().map(str::to_string),
+                    },
+                ));
+            }
+
+            let instances = match instances.is_empty() {
+                true => None,
+                false => Some(self.graphics.create_instance_buffer(&instances)),
+            };
+
             nodes.push((
                 node_index,
                 Node {
@@ -132,26 +304,119 @@ impl<'gfx> Importer<'gfx> {
                     children,
                     transform,
                     meshes,
+                    instances,
                     skin,
                     name: node.name().map(str::to_string),
                 },
             ));
         }
 
-        nodes.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        nodes.sort_unstable_by_key(|it| it.0);
 
         let nodes = nodes.into_iter().map(|it| it.1).collect();
 
         let transform = Transform::from(Mat4::IDENTITY);
 
+        if let Some(cook) = &mut self.cook {
+            cooked_nodes.sort_unstable_by_key(|it| it.0);
+            cook.nodes = cooked_nodes.into_iter().map(|it| it.1).collect();
+            cook.root_nodes = root_nodes.iter().map(|it| usize::from(it) as u32).collect();
+            cook.source = self.source.clone();
+            cook.dependencies = self
+                .dependencies
+                .iter()
+                .map(|it| (it.clone(), cooked::file_modified(it)))
+                .collect();
+        }
+
+        let dependencies = std::mem::take(&mut self.dependencies);
+        self.cache.add_dependencies(&self.source, dependencies);
+
         Ok(Scene {
             transform,
             nodes,
             root_nodes,
+            source: Some(self.source.clone()),
         })
     }
 
+    fn import_gpu_instancing(
+        &self,
+        document: &gltf::Document,
+        node: &gltf::Node,
+    ) -> Result<Vec<Mat4>, ImportGltfError> {
+        let instancing = match self.instancing.get(node.index()) {
+            Some(Some(it)) => it,
+            _ => return Ok(vec![]),
+        };
+
+        let get_buffer_data =
+            |buffer: gltf::Buffer| self.buffers.get(buffer.index()).map(Vec::as_slice);
+        let accessor = |index: usize| {
+            document
+                .accessors()
+                .nth(index)
+                .ok_or(ImportGltfError::UnknownAccessorIndex(index))
+        };
+
+        let translations = match instancing.translation {
+            Some(index) => gltf::accessor::Iter::<[f32; 3]>::new(accessor(index)?, get_buffer_data)
+                .ok_or(ImportGltfError::UnreadableAccessor(index))?
+                .map(Vec3::from)
+                .collect(),
+            None => vec![],
+        };
+
+        let scales = match instancing.scale {
+            Some(index) => gltf::accessor::Iter::<[f32; 3]>::new(accessor(index)?, get_buffer_data)
+                .ok_or(ImportGltfError::UnreadableAccessor(index))?
+                .map(Vec3::from)
+                .collect(),
+            None => vec![],
+        };
+
+        // rotations can also be stored as normalized integers
+        let rotations = match instancing.rotation {
+            Some(index) => {
+                use gltf::{accessor::DataType, accessor::Iter, animation::util::Rotations};
+                let accessor = accessor(index)?;
+                let rotations = match accessor.data_type() {
+                    DataType::I8 => Iter::new(accessor, get_buffer_data).map(Rotations::I8),
+                    DataType::U8 => Iter::new(accessor, get_buffer_data).map(Rotations::U8),
+                    DataType::I16 => Iter::new(accessor, get_buffer_data).map(Rotations::I16),
+                    DataType::U16 => Iter::new(accessor, get_buffer_data).map(Rotations::U16),
+                    DataType::F32 => Iter::new(accessor, get_buffer_data).map(Rotations::F32),
+                    DataType::U32 => None,
+                };
+                rotations
+                    .ok_or(ImportGltfError::UnreadableAccessor(index))?
+                    .into_f32()
+                    .map(Quat::from_array)
+                    .collect()
+            }
+            None => vec![],
+        };
+
+        let count = translations.len().max(rotations.len()).max(scales.len());
+        let transforms = (0..count)
+            .map(|i| {
+                Mat4::from_scale_rotation_translation(
+                    scales.get(i).copied().unwrap_or(Vec3::ONE),
+                    rotations.get(i).copied().unwrap_or(Quat::IDENTITY),
+                    translations.get(i).copied().unwrap_or(Vec3::ZERO),
+                )
+            })
+            .collect();
+
+        Ok(transforms)
+    }
+
     fn import_gltf_buffer(&mut self, buffer: gltf::Buffer) -> Result<Vec<u8>, ImportGltfError> {
+        // fallback buffers have no data, compressed views are decoded into them
+        if self.fallback_buffers.get(buffer.index()) == Some(&true) {
+            return Ok(vec![0; buffer.length()]);
+        }
+
         match buffer.source() {
             gltf::buffer::Source::Bin => {
                 self.blob.take().ok_or(ImportGltfError::BinSectionNotFound)
@@ -160,23 +425,76 @@ impl<'gfx> Importer<'gfx> {
                 if uri.starts_with("data:") {
                     Ok(data_uri_to_bytes_and_type(uri)?.0)
                 } else {
-                    Ok(std::fs::read(format!("{}/{}", self.base_path, uri))?)
+                    let path = self.base_path.join(uri);
+                    let bytes = std::fs::read(&path)?;
+                    self.dependencies
+                        .push(std::fs::canonicalize(&path).unwrap_or(path));
+                    Ok(bytes)
                 }
             }
         }
     }
 
-    // result is (rgba bytes, width, height, format)
-    fn import_gltf_image(
-        &self,
-        image: gltf::Image,
-    ) -> Result<(Vec<u8>, u32, u32, wgpu::TextureFormat), ImportGltfError> {
+    fn decompress_gltf_view(&mut self, view: gltf::buffer::View) -> Result<(), ImportGltfError> {
+        let compressed = match self.compressed_views.get(view.index()) {
+            Some(Some(it)) => it,
+            _ => return Ok(()),
+        };
+
+        let source_index = compressed.buffer;
+        let from = compressed.byte_offset;
+        let to = from + compressed.byte_length;
+        let data = self
+            .buffers
+            .get(source_index)
+            .ok_or(ImportGltfError::UnknownBufferIndex(source_index))?
+            .get(from..to)
+            .ok_or(ImportGltfError::BufferRangeOutOfBounds(
+                source_index,
+                from,
+                to,
+            ))?;
+
+        let decoded = meshopt::decode(
+            compressed.mode,
+            compressed.filter,
+            compressed.count,
+            compressed.byte_stride,
+            data,
+        )
+        .map_err(|e| ImportGltfError::MeshoptDecodingFailed(view.index(), e))?;
+        if decoded.len() != view.length() {
+            return Err(ImportGltfError::MeshoptLengthMismatch(
+                view.index(),
+                decoded.len(),
+                view.length(),
+            ));
+        }
+
+        let target_index = view.buffer().index();
+        let from = view.offset();
+        let to = from + decoded.len();
+        self.buffers
+            .get_mut(target_index)
+            .ok_or(ImportGltfError::UnknownBufferIndex(target_index))?
+            .get_mut(from..to)
+            .ok_or(ImportGltfError::BufferRangeOutOfBounds(
+                target_index,
+                from,
+                to,
+            ))?
+            .copy_from_slice(&decoded);
+
+        Ok(())
+    }
+
+    fn import_gltf_image(&self, image: gltf::Image) -> Result<ImportedImage, ImportGltfError> {
         let (data, mime_type) = match image.source() {
             gltf::image::Source::Uri { uri, mime_type } => {
                 let (data, parsed_mt) = if uri.starts_with("data:") {
                     data_uri_to_bytes_and_type(uri)?
                 } else {
-                    let bytes = std::fs::read(&format!("{}/{}", self.base_path, uri))?;
+                    let bytes = std::fs::read(self.base_path.join(uri))?;
                     let format = if uri.ends_with(".png") {
                         "image/png"
                     } else if uri.ends_with(".jpg") || uri.ends_with(".jpeg") {
@@ -243,113 +561,195 @@ impl<'gfx> Importer<'gfx> {
         }
     }
 
-    fn import_gltf_texture(&mut self, texture: gltf::Texture) -> Result<Texture, ImportGltfError> {
-        let texture_index = texture.index();
-        if let Some(tex) = self
-            .textures
-            .get(texture_index)
-            .ok_or(ImportGltfError::UnknownTextureIndex(texture_index))?
-        {
-            return Ok(tex.clone());
+    fn import_gltf_texture(
+        &mut self,
+        texture: gltf::Texture,
+    ) -> Result<(Texture, AssetKey), ImportGltfError> {
+        let key = self.texture_key(&texture);
+        if self.is_cached(|cook| &cook.texture_indices, &key) {
+            if let Some(tex) = self.cache.texture(&key) {
+                return Ok((tex, key));
+            }
         }
 
         let image_index = texture.source().index();
-        let (data, width, height, format) = &self
+        if self
             .images
             .get(image_index)
-            .ok_or(ImportGltfError::UnknownImageIndex(image_index))?;
+            .ok_or(ImportGltfError::UnknownImageIndex(image_index))?
+            .is_none()
+        {
+            self.images[image_index] = Some(self.import_gltf_image(texture.source())?);
+        }
+        let (data, width, height, format) = self.images[image_index].as_ref().unwrap();
 
         let sampler = texture.sampler();
 
-        let mut desc = TextureDescription::new(data, *width, *height, *format)
-            .wrap_s(match sampler.wrap_s() {
-                gltf::texture::WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
-                gltf::texture::WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
-                gltf::texture::WrappingMode::Repeat => wgpu::AddressMode::Repeat,
-            })
-            .wrap_t(match sampler.wrap_s() {
-                gltf::texture::WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
-                gltf::texture::WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
-                gltf::texture::WrappingMode::Repeat => wgpu::AddressMode::Repeat,
-            });
+        let wrap_s = map_wrapping_mode(sampler.wrap_s());
+        let wrap_t = map_wrapping_mode(sampler.wrap_t());
+        let min_filter = match sampler.min_filter() {
+            // textures have no mipmaps, so mipmap filters fall back to the base filter
+            Some(gltf::texture::MinFilter::Nearest)
+            | Some(gltf::texture::MinFilter::NearestMipmapNearest)
+            | Some(gltf::texture::MinFilter::NearestMipmapLinear) => wgpu::FilterMode::Nearest,
+            _ => wgpu::FilterMode::Linear,
+        };
+        let mag_filter = match sampler.mag_filter() {
+            Some(gltf::texture::MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+            _ => wgpu::FilterMode::Linear,
+        };
 
-        if let Some(min_filter) = sampler.min_filter() {
-            desc = desc.min_filter(match min_filter {
-                gltf::texture::MinFilter::Nearest => wgpu::FilterMode::Nearest,
-                gltf::texture::MinFilter::Linear => wgpu::FilterMode::Linear,
-                _ => unimplemented!(),
+        let desc = TextureDescription::new(data, *width, *height, *format)
+            .wrap_s(wrap_s)
+            .wrap_t(wrap_t)
+            .min_filter(min_filter)
+            .mag_filter(mag_filter);
+
+        if let Some(cook) = &mut self.cook {
+            cook.texture_indices
+                .insert(key.clone(), cook.textures.len() as u32);
+            cook.textures.push(CookedTexture {
+                key: key.clone(),
+                width: *width,
+                height: *height,
+                wrap_s,
+                wrap_t,
+                min_filter,
+                mag_filter,
+                texels: Cow::Owned(data.clone()),
             });
         }
 
-        if let Some(mag_filter) = sampler.mag_filter() {
-            desc = desc.mag_filter(match mag_filter {
-                gltf::texture::MagFilter::Nearest => wgpu::FilterMode::Nearest,
-                gltf::texture::MagFilter::Linear => wgpu::FilterMode::Linear,
-            });
+        let texture = self.graphics.create_texture(&desc);
+        self.cache.insert_texture(key.clone(), texture.clone());
+        Ok((texture, key))
+    }
+
+    /// When cooking, assets must be imported once so they can be recorded, even if
+    /// they were cached by an earlier import.
+    fn is_cached(
+        &self,
+        recorded: impl for<'c> Fn(&'c CookedScene<'static>) -> &'c HashMap<AssetKey, u32>,
+        key: &AssetKey,
+    ) -> bool {
+        match &self.cook {
+            Some(cook) => recorded(cook).contains_key(key),
+            None => true,
         }
+    }
 
-        let texture = self.graphics.create_texture(&desc);
-        Ok(texture)
+    // textures that point to an image file outside of the document are keyed by
+    // the file path, so they are shared between documents that use the same file
+    fn texture_key(&mut self, texture: &gltf::Texture) -> AssetKey {
+        match texture.source().source() {
+            gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+                let path = self.base_path.join(uri);
+                let path = std::fs::canonicalize(&path).unwrap_or(path);
+                self.dependencies.push(path.clone());
+                // the sampler's gl enums all fit in 16 bits, packing them keeps the
+                // key the same across builds, which cooked files rely on
+                let sampler = texture.sampler();
+                let sampler = [
+                    sampler.wrap_s().as_gl_enum(),
+                    sampler.wrap_t().as_gl_enum(),
+                    sampler.min_filter().map_or(0, |it| it.as_gl_enum()),
+                    sampler.mag_filter().map_or(0, |it| it.as_gl_enum()),
+                ]
+                .iter()
+                .fold(0, |packed, it| (packed << 16) | (*it as u64 & 0xffff));
+                AssetKey::File(path, sampler)
+            }
+            _ => AssetKey::gltf(&self.source, texture.index()),
+        }
     }
 
     fn import_gltf_material(
         &mut self,
         material: gltf::Material,
     ) -> Result<Material, ImportGltfError> {
-        if let Some(index) = material.index() {
-            if let Some(mat) = self
-                .materials
-                .get(index)
-                .ok_or(ImportGltfError::UnknownMaterialIndex(index))?
-            {
-                return Ok(mat.clone());
+        // the default material has no index and is not cached
+        let key = material.index().map(|it| AssetKey::gltf(&self.source, it));
+        if let Some(key) = &key {
+            if self.is_cached(|cook| &cook.material_indices, key) {
+                if let Some(mat) = self.cache.material(key) {
+                    return Ok(mat);
+                }
             }
         }
 
-        let normal = match material.normal_texture().as_ref() {
-            Some(info) => Some(self.import_gltf_texture(info.texture())?),
-            None => None,
+        let (normal, normal_tex_coord) = match material.normal_texture().as_ref() {
+            Some(info) => (
+                Some(self.import_gltf_texture(info.texture())?),
+                info.tex_coord(),
+            ),
+            None => (None, 0),
         };
-        let diffuse = match material
+        let (diffuse, diffuse_tex_coord) = match material
             .pbr_metallic_roughness()
             .base_color_texture()
             .as_ref()
         {
-            Some(info) => Some(self.import_gltf_texture(info.texture())?),
-            None => None,
+            Some(info) => (
+                Some(self.import_gltf_texture(info.texture())?),
+                info.tex_coord(),
+            ),
+            None => (None, 0),
         };
         let base_diffuse_color = material.pbr_metallic_roughness().base_color_factor();
-        Ok(Material {
-            normal,
-            diffuse,
+        let transparent = material.alpha_mode() == gltf::material::AlphaMode::Blend;
+
+        if let Some(cook) = &mut self.cook {
+            let texture_index = |it: &Option<(Texture, AssetKey)>| {
+                it.as_ref().map(|(_, key)| cook.texture_indices[key])
+            };
+            let cooked = CookedMaterial {
+                key: key.clone(),
+                diffuse: texture_index(&diffuse),
+                normal: texture_index(&normal),
+                base_diffuse_color,
+                shaded: true,
+                diffuse_tex_coord,
+                normal_tex_coord,
+                transparent,
+            };
+            if let Some(key) = &key {
+                cook.material_indices
+                    .insert(key.clone(), cook.materials.len() as u32);
+            }
+            cook.materials.push(cooked);
+        }
+
+        let material = Material {
+            normal: normal.map(|it| it.0),
+            diffuse: diffuse.map(|it| it.0),
             base_diffuse_color,
             shaded: true,
-        })
+            diffuse_tex_coord,
+            normal_tex_coord,
+            cast_shadows: true,
+            receive_shadows: true,
+            transparent,
+            shader: None,
+        };
+        if let Some(key) = key {
+            self.cache.insert_material(key, material.clone());
+        }
+        Ok(material)
     }
 
     fn import_gltf_mesh(
         &mut self,
         mesh: gltf::Mesh,
-    ) -> Result<Vec<(Mesh, UniformBuffer, Material)>, ImportGltfError> {
-        let mesh_index = mesh.index();
-        if let Some(m) = self
-            .meshes
-            .get(mesh_index)
-            .ok_or(ImportGltfError::UnknownMeshIndex(mesh_index))?
-        {
-            return Ok(m
-                .iter()
-                .map(|(mesh, mat)| {
-                    (
-                        mesh.clone(),
-                        self.graphics.create_uniform_buffer(),
-                        mat.clone(),
-                    )
-                })
-                .collect());
+    ) -> Result<Vec<(Mesh, Material)>, ImportGltfError> {
+        let key = AssetKey::gltf(&self.source, mesh.index());
+        if self.is_cached(|cook| &cook.mesh_indices, &key) {
+            if let Some(m) = self.cache.mesh(&key) {
+                return Ok(m);
+            }
         }
 
         let mut primitives = vec![];
+        let mut cooked_primitives = vec![];
         for primitive in mesh.primitives() {
             let reader =
                 primitive.reader(|buffer| self.buffers.get(buffer.index()).map(Vec::as_slice));
@@ -381,6 +781,10 @@ impl<'gfx> Importer<'gfx> {
                 ))?
                 .into_f32();
 
+            // second uv set and vertex colors are optional
+            let mut tex_coords_1 = reader.read_tex_coords(1).map(|it| it.into_f32());
+            let mut colors = reader.read_colors(0).map(|it| it.into_rgba_f32());
+
             let mut vertices: Vec<Vertex> = Vec::with_capacity(positions.len());
             for _ in 0..positions.len() {
                 let p = positions.next().unwrap();
@@ -390,10 +794,20 @@ impl<'gfx> Importer<'gfx> {
                     let val = tex_coords.next().unwrap();
                     [val[0], val[1]]
                 };
+                let tex_coord_1 = match tex_coords_1.as_mut().and_then(Iterator::next) {
+                    Some(val) => [val[0], val[1]],
+                    None => tex_coord,
+                };
+                let color = colors
+                    .as_mut()
+                    .and_then(Iterator::next)
+                    .unwrap_or([1.0, 1.0, 1.0, 1.0]);
                 let vertex = Vertex {
                     position,
                     normal,
                     tex_coord,
+                    tex_coord_1,
+                    color,
                 };
                 vertices.push(vertex);
             }
@@ -411,12 +825,40 @@ impl<'gfx> Importer<'gfx> {
 
             let material = self.import_gltf_material(primitive.material())?;
 
-            let mesh = self.graphics.create_mesh(&vertices, &indices);
-            let ub = self.graphics.create_uniform_buffer();
+            // glTF requires the bounds of positions in their accessor
+            let bounds = primitive.bounding_box();
+            let bounds = Aabb::new(bounds.min.into(), bounds.max.into());
+            let mesh = self
+                .graphics
+                .create_mesh_with_bounds(&vertices, &indices, bounds);
+
+            primitives.push((mesh, material));
+
+            if let Some(cook) = &self.cook {
+                // the default material is recorded every time it's used
+                let material = match primitive.material().index() {
+                    Some(index) => cook.material_indices[&AssetKey::gltf(&self.source, index)],
+                    None => cook.materials.len() as u32 - 1,
+                };
+                cooked_primitives.push(CookedPrimitive {
+                    vertices: Cow::Owned(vertices),
+                    indices: Cow::Owned(indices),
+                    material,
+                });
+            }
+        }
 
-            primitives.push((mesh, ub, material.clone()));
+        if let Some(cook) = &mut self.cook {
+            cook.mesh_indices
+                .insert(key.clone(), cook.meshes.len() as u32);
+            cook.meshes.push(CookedMesh {
+                key: key.clone(),
+                primitives: cooked_primitives,
+            });
         }
 
+        self.cache.insert_mesh(key, primitives.clone());
+
         Ok(primitives)
     }
 }
@@ -427,7 +869,15 @@ fn data_uri_to_bytes_and_type(uri: &str) -> Result<(Vec<u8>, &str), base64::Deco
     Ok((bytes, mt))
 }
 
-fn map_node_to_u16_index(node: &gltf::Node) -> Result<u16, ImportGltfError> {
+fn map_wrapping_mode(mode: gltf::texture::WrappingMode) -> wgpu::AddressMode {
+    match mode {
+        gltf::texture::WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        gltf::texture::WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        gltf::texture::WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    }
+}
+
+fn map_node_to_index(node: &gltf::Node) -> Result<NodeIndex, ImportGltfError> {
     node.index()
         .try_into()
         .map_err(|_| ImportGltfError::NodeIndexOutOfRange(node.index()))
@@ -441,10 +891,14 @@ pub enum ImportGltfError {
     Base64Error(#[from] base64::DecodeError),
     #[error("error while loading source gltf: {0}")]
     GltfError(#[from] gltf::Error),
+    #[error("error while reading gltf json: {0}")]
+    JsonError(#[from] serde_json::Error),
     #[error("image loading failed for file '{0}': {1}")]
     ImageLoadingFailed(String, ImageError),
     #[error("unknown image format '{0:?}' for image {1}")]
     UnknownImageFormat(String, usize),
+    #[error("gltf document has no default scene")]
+    DefaultSceneNotFound,
     #[error("binary section of gltf not found")]
     BinSectionNotFound,
     #[error(
@@ -455,6 +909,14 @@ pub enum ImportGltfError {
     UnknownBufferIndex(usize),
     #[error("buffer {0} has a view with range ({1}..{2}) that is out of bounds")]
     BufferRangeOutOfBounds(usize, usize, usize),
+    #[error("meshopt decoding failed for buffer view {0}: {1}")]
+    MeshoptDecodingFailed(usize, MeshoptError),
+    #[error("meshopt data for buffer view {0} decodes to {1} bytes instead of {2}")]
+    MeshoptLengthMismatch(usize, usize, usize),
+    #[error("unknown accessor index {0}")]
+    UnknownAccessorIndex(usize),
+    #[error("accessor {0} could not be read")]
+    UnreadableAccessor(usize),
     #[error("unknown image index {0}")]
     UnknownImageIndex(usize),
     #[error("unknown material index {0}")]
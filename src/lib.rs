@@ -3,102 +3,250 @@ pub use error::AyudeError;
 
 pub mod graphics;
 
+pub mod asset_cache;
+pub use asset_cache::AssetCache;
+
 pub mod catalog;
+use bounds::{Aabb, Frustum};
 pub use catalog::Catalog;
 use glam::Mat4;
 
-use graphics::GraphicsContext;
 use smallvec::SmallVec;
+use std::path::PathBuf;
 use transform::Transform;
 
+mod ambient_occlusion;
+mod bloom;
+pub mod bounds;
 pub mod camera;
+pub mod cooked;
+pub mod debug_draw;
+mod environment_map;
+mod hdr;
+pub mod hot_reload;
 pub mod imgui;
 pub mod import_gltf;
+mod meshopt;
+mod mmap;
+mod post_process;
+mod preprocess;
+mod shadow;
 pub mod transform;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Scene {
     pub nodes: Vec<Node>,
-    pub root_nodes: SmallVec<[u16; 4]>,
+    pub root_nodes: NodeIndices,
     pub transform: Transform,
+    /// File the scene was imported from.
+    pub source: Option<PathBuf>,
 }
 
 impl Scene {
     pub fn render<'scene: 'pass, 'pass>(
         &'scene self,
         pass: &'pass mut graphics::Pass<'scene, 'scene>,
-        perspective: Mat4,
-        view: Mat4,
     ) {
-        let base_transform = &self.transform;
+        let frustum = Frustum::from_view_projection(pass.view_projection());
         for node in &self.nodes {
             if node.meshes.is_empty() {
                 continue;
             }
 
-            let transform = {
-                let mut current = node;
-                let mut transform = node.transform.mat4().clone();
-                'transform: loop {
-                    current = match current.parent {
-                        Some(index) => &self.nodes[usize::from(index)],
-                        None => break 'transform,
-                    };
+            let model = self.model_transform(node);
+            if let Some(bounds) = self.node_bounds_with(node, model) {
+                if !frustum.intersects(&bounds) {
+                    pass.record_culled();
+                    continue;
+                }
+            }
 
-                    transform = transform * current.transform.mat4();
+            for (mesh, material) in &node.meshes {
+                match &node.instances {
+                    Some(instances) => pass.render_mesh_instanced(mesh, material, model, instances),
+                    None => pass.render_mesh(mesh, material, model),
                 }
-                Transform::from(transform)
-            };
+            }
+        }
+    }
 
-            for (mesh, ub, material) in &node.meshes {
-                let base_transform = base_transform.mat4();
-                let mesh_transform = transform.mat4();
-                let model = mesh_transform * base_transform;
+    /// Renders the meshes whose materials cast shadows into a shadow cascade.
+    pub fn render_shadows<'scene: 'pass, 'pass>(
+        &'scene self,
+        pass: &'pass mut graphics::ShadowPass<'scene, 'scene>,
+    ) {
+        for node in &self.nodes {
+            if node.meshes.is_empty() {
+                continue;
+            }
 
-                pass.render_mesh(mesh, ub, material, perspective, view, model);
+            let model = self.model_transform(node);
+            for (mesh, material) in &node.meshes {
+                match &node.instances {
+                    Some(instances) => pass.render_mesh_instanced(mesh, material, model, instances),
+                    None => pass.render_mesh(mesh, material, model),
+                }
             }
         }
     }
 
-    pub fn duplicate(&self, graphics: &GraphicsContext) -> Self {
-        Self {
-            nodes: self.nodes.iter().map(|it| it.duplicate(graphics)).collect(),
-            root_nodes: self.root_nodes.clone(),
-            transform: self.transform.clone(),
+    /// World space bounds of the meshes of a node. `None` when it has no meshes, or
+    /// when it's instanced, since the instance transforms aren't kept.
+    pub fn node_bounds(&self, node: &Node) -> Option<Aabb> {
+        self.node_bounds_with(node, self.model_transform(node))
+    }
+
+    fn node_bounds_with(&self, node: &Node, model: Mat4) -> Option<Aabb> {
+        if node.instances.is_some() {
+            return None;
         }
+        let bounds = node
+            .meshes
+            .iter()
+            .map(|(mesh, _)| mesh.bounds)
+            .reduce(|a, b| a.union(&b))?;
+        Some(bounds.transform(model))
+    }
+
+    fn model_transform(&self, node: &Node) -> Mat4 {
+        let mut current = node;
+        let mut transform = node.transform.mat4();
+        'transform: loop {
+            current = match current.parent {
+                Some(index) => &self.nodes[usize::from(index)],
+                None => break 'transform,
+            };
+
+            transform *= current.transform.mat4();
+        }
+        transform * self.transform.mat4()
+    }
+
+    /// Replaces the contents of the scene with `other`, a new import of the same
+    /// file, keeping the transform of the scene and of the nodes both of them have.
+    pub fn reload_from(&mut self, mut other: Scene) {
+        for (node, previous) in other.nodes.iter_mut().zip(&self.nodes) {
+            node.transform = previous.transform.clone();
+        }
+        other.transform = self.transform.clone();
+        *self = other;
     }
 }
 
-#[derive(Debug)]
-pub struct Node {
-    pub parent: Option<u16>,
-    pub children: SmallVec<[u16; 4]>,
-    pub transform: Transform,
-    pub meshes: Vec<(graphics::Mesh, graphics::UniformBuffer, graphics::Material)>,
-    pub skin: Option<Skin>,
-    pub name: Option<String>,
+/// Index of a node in `Scene::nodes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeIndex(u32);
+
+/// List of node indices. While every index fits in a `u16`, as they do in
+/// scenes under 65536 nodes, they are stored as one, and the first few inline.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct NodeIndices(NodeIndexStorage);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum NodeIndexStorage {
+    Compact(SmallVec<[u16; 8]>),
+    Wide(Vec<u32>),
 }
 
-impl Node {
-    pub fn duplicate(&self, graphics: &GraphicsContext) -> Self {
-        Self {
-            parent: self.parent.clone(),
-            children: self.children.clone(),
-            transform: self.transform.clone(),
-            meshes: self
-                .meshes
-                .iter()
-                .map(|(mesh, _, mat)| (mesh.clone(), graphics.create_uniform_buffer(), mat.clone()))
-                .collect(),
-            skin: self.skin.clone(),
-            name: self.name.clone(),
+impl Default for NodeIndexStorage {
+    fn default() -> Self {
+        Self::Compact(SmallVec::new())
+    }
+}
+
+impl NodeIndices {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            NodeIndexStorage::Compact(it) => it.len(),
+            NodeIndexStorage::Wide(it) => it.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<NodeIndex> {
+        match &self.0 {
+            NodeIndexStorage::Compact(it) => it.get(index).map(|it| NodeIndex(*it as u32)),
+            NodeIndexStorage::Wide(it) => it.get(index).copied().map(NodeIndex),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+        (0..self.len()).map(move |it| self.get(it).unwrap())
+    }
+
+    /// Adds `index` at the end, switching every index to `u32` storage when it
+    /// doesn't fit in a `u16`.
+    pub fn push(&mut self, index: NodeIndex) {
+        match &mut self.0 {
+            NodeIndexStorage::Compact(compact) => match u16::try_from(index.0) {
+                Ok(it) => compact.push(it),
+                Err(_) => {
+                    let mut wide: Vec<u32> = compact.iter().map(|it| *it as u32).collect();
+                    wide.push(index.0);
+                    self.0 = NodeIndexStorage::Wide(wide);
+                }
+            },
+            NodeIndexStorage::Wide(wide) => wide.push(index.0),
         }
     }
 }
 
+impl FromIterator<NodeIndex> for NodeIndices {
+    fn from_iter<T: IntoIterator<Item = NodeIndex>>(iter: T) -> Self {
+        let mut indices = Self::new();
+        iter.into_iter().for_each(|it| indices.push(it));
+        indices
+    }
+}
+
+impl From<NodeIndex> for usize {
+    fn from(index: NodeIndex) -> Self {
+        index.0 as usize
+    }
+}
+
+impl std::fmt::Display for NodeIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<u32> for NodeIndex {
+    fn from(index: u32) -> Self {
+        Self(index)
+    }
+}
+
+impl TryFrom<usize> for NodeIndex {
+    type Error = std::num::TryFromIntError;
+
+    fn try_from(index: usize) -> Result<Self, Self::Error> {
+        u32::try_from(index).map(Self)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub parent: Option<NodeIndex>,
+    pub children: NodeIndices,
+    pub transform: Transform,
+    pub meshes: Vec<(graphics::Mesh, graphics::Material)>,
+    /// When present, every mesh of the node is drawn once per instance transform.
+    pub instances: Option<graphics::InstanceBuffer>,
+    pub skin: Option<Skin>,
+    pub name: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Skin {
-    pub joints: SmallVec<[u16; 4]>,
+    pub joints: NodeIndices,
     pub inverse_bind_matrices: Vec<Transform>,
-    pub skeleton: Option<u16>,
+    pub skeleton: Option<NodeIndex>,
 }
@@ -0,0 +1,701 @@
+// decoders for the meshoptimizer codecs used by EXT_meshopt_compression
+// format https://github.com/KhronosGroup/glTF/tree/main/extensions/2.0/Vendor/EXT_meshopt_compression
+// reference implementation https://github.com/zeux/meshoptimizer/blob/master/src/vertexcodec.cpp
+// reference implementation https://github.com/zeux/meshoptimizer/blob/master/src/indexcodec.cpp
+// reference implementation https://github.com/zeux/meshoptimizer/blob/master/src/vertexfilter.cpp
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Attributes,
+    Triangles,
+    Indices,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    None,
+    Octahedral,
+    Quaternion,
+    Exponential,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MeshoptError {
+    #[error("unsupported header byte {0:#x}")]
+    UnsupportedHeader(u8),
+    #[error("compressed data ended before it was fully decoded")]
+    UnexpectedEnd,
+    #[error("compressed data has {0} trailing bytes")]
+    TrailingData(usize),
+    #[error("byte stride {0} is not valid for this mode")]
+    InvalidStride(usize),
+    #[error("element count {0} is not valid for this mode")]
+    InvalidCount(usize),
+}
+
+/// Decodes `count` elements of `stride` bytes each and applies `filter` to them.
+pub fn decode(
+    mode: Mode,
+    filter: Filter,
+    count: usize,
+    stride: usize,
+    data: &[u8],
+) -> Result<Vec<u8>, MeshoptError> {
+    let mut out = vec![0u8; count * stride];
+    match mode {
+        Mode::Attributes => decode_vertex_buffer(&mut out, count, stride, data)?,
+        Mode::Triangles => decode_index_buffer(&mut out, count, stride, data)?,
+        Mode::Indices => decode_index_sequence(&mut out, count, stride, data)?,
+    }
+    match filter {
+        Filter::None => {}
+        Filter::Octahedral => match stride {
+            4 => filter_octahedral_8(&mut out),
+            8 => filter_octahedral_16(&mut out),
+            _ => return Err(MeshoptError::InvalidStride(stride)),
+        },
+        Filter::Quaternion => match stride {
+            8 => filter_quaternion(&mut out),
+            _ => return Err(MeshoptError::InvalidStride(stride)),
+        },
+        Filter::Exponential => match stride % 4 {
+            0 => filter_exponential(&mut out),
+            _ => return Err(MeshoptError::InvalidStride(stride)),
+        },
+    }
+    Ok(out)
+}
+
+const VERTEX_HEADER: u8 = 0xa0;
+const VERTEX_BLOCK_SIZE_BYTES: usize = 8192;
+const VERTEX_BLOCK_MAX_SIZE: usize = 256;
+const BYTE_GROUP_SIZE: usize = 16;
+const BYTE_GROUP_DECODE_LIMIT: usize = 24;
+const TAIL_MAX_SIZE: usize = 32;
+
+fn decode_vertex_buffer(
+    out: &mut [u8],
+    count: usize,
+    stride: usize,
+    data: &[u8],
+) -> Result<(), MeshoptError> {
+    if stride == 0 || stride > 256 || !stride.is_multiple_of(4) {
+        return Err(MeshoptError::InvalidStride(stride));
+    }
+    if data.len() < 1 + stride {
+        return Err(MeshoptError::UnexpectedEnd);
+    }
+    if data[0] != VERTEX_HEADER {
+        return Err(MeshoptError::UnsupportedHeader(data[0]));
+    }
+
+    // the first vertex is encoded against the last bytes of the data
+    let mut last_vertex = [0u8; 256];
+    last_vertex[..stride].copy_from_slice(&data[data.len() - stride..]);
+
+    let block_size =
+        ((VERTEX_BLOCK_SIZE_BYTES / stride) & !(BYTE_GROUP_SIZE - 1)).min(VERTEX_BLOCK_MAX_SIZE);
+
+    let mut position = 1;
+    let mut vertex_offset = 0;
+    while vertex_offset < count {
+        let block_count = block_size.min(count - vertex_offset);
+        let block = &mut out[vertex_offset * stride..(vertex_offset + block_count) * stride];
+        position =
+            decode_vertex_block(data, position, block, block_count, stride, &mut last_vertex)?;
+        vertex_offset += block_count;
+    }
+
+    let tail_size = stride.max(TAIL_MAX_SIZE);
+    match (data.len() - position).cmp(&tail_size) {
+        std::cmp::Ordering::Less => Err(MeshoptError::UnexpectedEnd),
+        std::cmp::Ordering::Equal => Ok(()),
+        std::cmp::Ordering::Greater => Err(MeshoptError::TrailingData(
+            data.len() - position - tail_size,
+        )),
+    }
+}
+
+fn decode_vertex_block(
+    data: &[u8],
+    mut position: usize,
+    out: &mut [u8],
+    count: usize,
+    stride: usize,
+    last_vertex: &mut [u8; 256],
+) -> Result<usize, MeshoptError> {
+    let mut deltas = [0u8; VERTEX_BLOCK_MAX_SIZE];
+    let count_aligned = (count + BYTE_GROUP_SIZE - 1) & !(BYTE_GROUP_SIZE - 1);
+
+    // every byte of the vertex is stored as its own stream of deltas
+    for k in 0..stride {
+        position = decode_bytes(data, position, &mut deltas[..count_aligned])?;
+
+        let mut previous = last_vertex[k];
+        for i in 0..count {
+            let value = unzigzag8(deltas[i]).wrapping_add(previous);
+            out[i * stride + k] = value;
+            previous = value;
+        }
+    }
+
+    last_vertex[..stride].copy_from_slice(&out[(count - 1) * stride..count * stride]);
+    Ok(position)
+}
+
+fn decode_bytes(data: &[u8], position: usize, out: &mut [u8]) -> Result<usize, MeshoptError> {
+    // two bits per group tell how many bits each byte in the group uses
+    let header_size = (out.len() / BYTE_GROUP_SIZE).div_ceil(4);
+    if data.len() - position < header_size {
+        return Err(MeshoptError::UnexpectedEnd);
+    }
+    let header = &data[position..position + header_size];
+    let mut position = position + header_size;
+
+    for (group, out) in out.chunks_exact_mut(BYTE_GROUP_SIZE).enumerate() {
+        if data.len() - position < BYTE_GROUP_DECODE_LIMIT {
+            return Err(MeshoptError::UnexpectedEnd);
+        }
+        let bits_log2 = (header[group / 4] >> ((group % 4) * 2)) & 3;
+        position = decode_bytes_group(data, position, out, bits_log2);
+    }
+
+    Ok(position)
+}
+
+fn decode_bytes_group(data: &[u8], position: usize, out: &mut [u8], bits_log2: u8) -> usize {
+    match bits_log2 {
+        0 => {
+            out.fill(0);
+            position
+        }
+        1 => decode_bits_group(data, position, out, 2),
+        2 => decode_bits_group(data, position, out, 4),
+        _ => {
+            out.copy_from_slice(&data[position..position + BYTE_GROUP_SIZE]);
+            position + BYTE_GROUP_SIZE
+        }
+    }
+}
+
+// values are packed most significant bits first, a value with every bit set means
+// the real byte is stored after the packed values
+fn decode_bits_group(data: &[u8], position: usize, out: &mut [u8], bits: usize) -> usize {
+    let packed_size = BYTE_GROUP_SIZE * bits / 8;
+    let packed = &data[position..position + packed_size];
+    let mut extra = position + packed_size;
+    let sentinel = (1u8 << bits) - 1;
+
+    for (i, out) in out.iter_mut().enumerate() {
+        let bit_offset = i * bits;
+        let shift = 8 - bits - bit_offset % 8;
+        let value = (packed[bit_offset / 8] >> shift) & sentinel;
+        if value == sentinel {
+            *out = data[extra];
+            extra += 1;
+        } else {
+            *out = value;
+        }
+    }
+
+    extra
+}
+
+fn unzigzag8(v: u8) -> u8 {
+    (0u8.wrapping_sub(v & 1)) ^ (v >> 1)
+}
+
+const INDEX_HEADER: u8 = 0xe0;
+const SEQUENCE_HEADER: u8 = 0xd0;
+const CODE_AUX_TABLE_SIZE: usize = 16;
+
+fn write_index(out: &mut [u8], i: usize, stride: usize, index: u32) {
+    match stride {
+        2 => out[i * 2..i * 2 + 2].copy_from_slice(&(index as u16).to_le_bytes()),
+        _ => out[i * 4..i * 4 + 4].copy_from_slice(&index.to_le_bytes()),
+    }
+}
+
+fn decode_vbyte(data: &[u8], position: &mut usize) -> u32 {
+    let lead = data[*position];
+    *position += 1;
+    if lead < 128 {
+        return lead as u32;
+    }
+
+    let mut result = (lead & 127) as u32;
+    let mut shift = 7;
+    for _ in 0..4 {
+        let group = data[*position];
+        *position += 1;
+        result |= ((group & 127) as u32) << shift;
+        shift += 7;
+        if group < 128 {
+            break;
+        }
+    }
+    result
+}
+
+fn decode_index(data: &[u8], position: &mut usize, last: u32) -> u32 {
+    let v = decode_vbyte(data, position);
+    let delta = (v >> 1) ^ 0u32.wrapping_sub(v & 1);
+    last.wrapping_add(delta)
+}
+
+struct Fifos {
+    edges: [[u32; 2]; 16],
+    edges_offset: usize,
+    vertices: [u32; 16],
+    vertices_offset: usize,
+}
+
+impl Fifos {
+    fn edge(&self, fe: usize) -> (u32, u32) {
+        let [a, b] = self.edges[self.edges_offset.wrapping_sub(1 + fe) & 15];
+        (a, b)
+    }
+
+    // `fe` here is the value as stored in the stream, where 0 means "not in the fifo"
+    fn vertex(&self, fe: usize) -> u32 {
+        self.vertices[self.vertices_offset.wrapping_sub(fe) & 15]
+    }
+
+    fn push_edge(&mut self, a: u32, b: u32) {
+        self.edges[self.edges_offset & 15] = [a, b];
+        self.edges_offset += 1;
+    }
+
+    fn push_vertex(&mut self, v: u32, condition: bool) {
+        self.vertices[self.vertices_offset & 15] = v;
+        self.vertices_offset += condition as usize;
+    }
+}
+
+fn decode_index_buffer(
+    out: &mut [u8],
+    count: usize,
+    stride: usize,
+    data: &[u8],
+) -> Result<(), MeshoptError> {
+    if stride != 2 && stride != 4 {
+        return Err(MeshoptError::InvalidStride(stride));
+    }
+    if !count.is_multiple_of(3) {
+        return Err(MeshoptError::InvalidCount(count));
+    }
+    if data.len() < 1 + count / 3 + CODE_AUX_TABLE_SIZE {
+        return Err(MeshoptError::UnexpectedEnd);
+    }
+    let version = match data[0] {
+        header if header & 0xf0 == INDEX_HEADER && header & 0x0f <= 1 => header & 0x0f,
+        header => return Err(MeshoptError::UnsupportedHeader(header)),
+    };
+
+    let mut fifos = Fifos {
+        edges: [[u32::MAX; 2]; 16],
+        edges_offset: 0,
+        vertices: [u32::MAX; 16],
+        vertices_offset: 0,
+    };
+    let mut next = 0u32;
+    let mut last = 0u32;
+    let fec_max = if version >= 1 { 13 } else { 15 };
+
+    // one code byte per triangle, followed by the free indices, followed by the
+    // table used to decode the most common aux codes
+    let codes = &data[1..1 + count / 3];
+    let mut position = 1 + count / 3;
+    let safe_end = data.len() - CODE_AUX_TABLE_SIZE;
+    let code_aux_table = &data[safe_end..];
+
+    // at most 16 bytes are read for a triangle, so reads past `safe_end` can only
+    // run into the table and never out of bounds
+    for (i, &code_tri) in (0..count).step_by(3).zip(codes) {
+        if position > safe_end {
+            return Err(MeshoptError::UnexpectedEnd);
+        }
+
+        let (a, b, c) = if code_tri < 0xf0 {
+            // triangle shares an edge with a recent triangle
+            let (a, b) = fifos.edge((code_tri >> 4) as usize);
+            let fec = (code_tri & 15) as usize;
+
+            if fec < fec_max {
+                let c = if fec == 0 {
+                    next
+                } else {
+                    fifos.vertex(fec + 1)
+                };
+                next += (fec == 0) as u32;
+                fifos.push_vertex(c, fec == 0);
+                (a, b, c)
+            } else {
+                // 13 and 14 are -1 and +1 from the last free index
+                let c = match fec {
+                    13 => last.wrapping_sub(1),
+                    14 => last.wrapping_add(1),
+                    _ => decode_index(data, &mut position, last),
+                };
+                last = c;
+                fifos.push_vertex(c, true);
+                (a, b, c)
+            }
+        } else if code_tri < 0xfe {
+            // no shared edge, aux code comes from the table
+            let code_aux = code_aux_table[(code_tri & 15) as usize];
+            let feb = (code_aux >> 4) as usize;
+            let fec = (code_aux & 15) as usize;
+
+            let a = next;
+            next += 1;
+            let b = if feb == 0 { next } else { fifos.vertex(feb) };
+            next += (feb == 0) as u32;
+            let c = if fec == 0 { next } else { fifos.vertex(fec) };
+            next += (fec == 0) as u32;
+
+            fifos.push_vertex(a, true);
+            fifos.push_vertex(b, feb == 0);
+            fifos.push_vertex(c, fec == 0);
+            fifos.push_edge(b, a);
+            (a, b, c)
+        } else {
+            // no shared edge, aux code is stored in the data
+            let code_aux = data[position];
+            position += 1;
+            let fea = if code_tri == 0xfe { 0 } else { 15 };
+            let feb = (code_aux >> 4) as usize;
+            let fec = (code_aux & 15) as usize;
+
+            if code_aux == 0 {
+                next = 0;
+            }
+
+            let mut take_next = || {
+                next += 1;
+                next - 1
+            };
+            let mut a = if fea == 0 { take_next() } else { 0 };
+            let mut b = if feb == 0 {
+                take_next()
+            } else {
+                fifos.vertex(feb)
+            };
+            let mut c = if fec == 0 {
+                take_next()
+            } else {
+                fifos.vertex(fec)
+            };
+
+            if fea == 15 {
+                a = decode_index(data, &mut position, last);
+                last = a;
+            }
+            if feb == 15 {
+                b = decode_index(data, &mut position, last);
+                last = b;
+            }
+            if fec == 15 {
+                c = decode_index(data, &mut position, last);
+                last = c;
+            }
+
+            fifos.push_vertex(a, true);
+            fifos.push_vertex(b, feb == 0 || feb == 15);
+            fifos.push_vertex(c, fec == 0 || fec == 15);
+            fifos.push_edge(b, a);
+            (a, b, c)
+        };
+
+        fifos.push_edge(c, b);
+        fifos.push_edge(a, c);
+
+        write_index(out, i, stride, a);
+        write_index(out, i + 1, stride, b);
+        write_index(out, i + 2, stride, c);
+    }
+
+    if position != safe_end {
+        return Err(MeshoptError::TrailingData(
+            safe_end.saturating_sub(position),
+        ));
+    }
+    Ok(())
+}
+
+fn decode_index_sequence(
+    out: &mut [u8],
+    count: usize,
+    stride: usize,
+    data: &[u8],
+) -> Result<(), MeshoptError> {
+    if stride != 2 && stride != 4 {
+        return Err(MeshoptError::InvalidStride(stride));
+    }
+    if data.len() < 1 + count + 4 {
+        return Err(MeshoptError::UnexpectedEnd);
+    }
+    match data[0] {
+        header if header & 0xf0 == SEQUENCE_HEADER && header & 0x0f <= 1 => {}
+        header => return Err(MeshoptError::UnsupportedHeader(header)),
+    }
+
+    // every index is a delta against one of two baselines, the 4 byte tail makes
+    // sure a 5 byte varint can always be read
+    let safe_end = data.len() - 4;
+    let mut position = 1;
+    let mut last = [0u32; 2];
+    for i in 0..count {
+        if position >= safe_end {
+            return Err(MeshoptError::UnexpectedEnd);
+        }
+
+        let v = decode_vbyte(data, &mut position);
+        let baseline = (v & 1) as usize;
+        let v = v >> 1;
+        let delta = (v >> 1) ^ 0u32.wrapping_sub(v & 1);
+        let index = last[baseline].wrapping_add(delta);
+        last[baseline] = index;
+
+        write_index(out, i, stride, index);
+    }
+
+    if position != safe_end {
+        return Err(MeshoptError::TrailingData(
+            safe_end.saturating_sub(position),
+        ));
+    }
+    Ok(())
+}
+
+fn round_to_int(v: f32) -> i32 {
+    (v + if v >= 0.0 { 0.5 } else { -0.5 }) as i32
+}
+
+// x and y are stored, z is reconstructed from the octahedral encoding
+fn octahedral(x: f32, y: f32, z: f32, max: f32) -> (i32, i32, i32) {
+    let z = z - x.abs() - y.abs();
+    let t = z.min(0.0);
+    let x = x + if x >= 0.0 { t } else { -t };
+    let y = y + if y >= 0.0 { t } else { -t };
+
+    let scale = max / (x * x + y * y + z * z).sqrt();
+    (
+        round_to_int(x * scale),
+        round_to_int(y * scale),
+        round_to_int(z * scale),
+    )
+}
+
+fn filter_octahedral_8(data: &mut [u8]) {
+    for v in data.chunks_exact_mut(4) {
+        let (x, y, z) = octahedral(
+            v[0] as i8 as f32,
+            v[1] as i8 as f32,
+            v[2] as i8 as f32,
+            127.0,
+        );
+        v[0] = x as i8 as u8;
+        v[1] = y as i8 as u8;
+        v[2] = z as i8 as u8;
+    }
+}
+
+fn read_i16(data: &[u8], i: usize) -> i16 {
+    i16::from_le_bytes([data[i * 2], data[i * 2 + 1]])
+}
+
+fn write_i16(data: &mut [u8], i: usize, value: i32) {
+    data[i * 2..i * 2 + 2].copy_from_slice(&(value as i16).to_le_bytes());
+}
+
+fn filter_octahedral_16(data: &mut [u8]) {
+    for v in data.chunks_exact_mut(8) {
+        let (x, y, z) = octahedral(
+            read_i16(v, 0) as f32,
+            read_i16(v, 1) as f32,
+            read_i16(v, 2) as f32,
+            32767.0,
+        );
+        write_i16(v, 0, x);
+        write_i16(v, 1, y);
+        write_i16(v, 2, z);
+    }
+}
+
+// three smallest components are stored, the index of the largest one is in the
+// two low bits of the fourth component and the rest of it stores the scale
+fn filter_quaternion(data: &mut [u8]) {
+    let scale = 1.0 / 2f32.sqrt();
+    for q in data.chunks_exact_mut(8) {
+        let packed = read_i16(q, 3) as i32;
+        let s = scale / (packed | 3) as f32;
+
+        let x = read_i16(q, 0) as f32 * s;
+        let y = read_i16(q, 1) as f32 * s;
+        let z = read_i16(q, 2) as f32 * s;
+        let w = (1.0 - x * x - y * y - z * z).max(0.0).sqrt();
+
+        let largest = (packed & 3) as usize;
+        write_i16(q, (largest + 1) & 3, round_to_int(x * 32767.0));
+        write_i16(q, (largest + 2) & 3, round_to_int(y * 32767.0));
+        write_i16(q, (largest + 3) & 3, round_to_int(z * 32767.0));
+        write_i16(q, largest, round_to_int(w * 32767.0));
+    }
+}
+
+// 24 bit signed mantissa and 8 bit signed exponent
+fn filter_exponential(data: &mut [u8]) {
+    for v in data.chunks_exact_mut(4) {
+        let packed = u32::from_le_bytes([v[0], v[1], v[2], v[3]]);
+        let mantissa = ((packed << 8) as i32) >> 8;
+        let exponent = (packed as i32) >> 24;
+        let value = f32::from_bits(((exponent + 127) as u32) << 23) * mantissa as f32;
+        v.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+// vectors from https://github.com/zeux/meshoptimizer/blob/master/demo/tests.cpp
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16s(data: &[u8]) -> Vec<u16> {
+        data.chunks_exact(2)
+            .map(|it| u16::from_le_bytes([it[0], it[1]]))
+            .collect()
+    }
+
+    fn u32s(data: &[u8]) -> Vec<u32> {
+        data.chunks_exact(4)
+            .map(|it| u32::from_le_bytes([it[0], it[1], it[2], it[3]]))
+            .collect()
+    }
+
+    fn bytes16(values: &[u16]) -> Vec<u8> {
+        values.iter().flat_map(|it| it.to_le_bytes()).collect()
+    }
+
+    fn bytes32(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|it| it.to_le_bytes()).collect()
+    }
+
+    const INDEX_BUFFER: [u32; 12] = [0, 1, 2, 2, 1, 3, 4, 6, 5, 7, 8, 9];
+
+    #[rustfmt::skip]
+    const INDEX_DATA_V0: [u8; 27] = [
+        0xe0, 0xf0, 0x10, 0xfe, 0xff, 0xf0, 0x0c, 0xff, 0x02, 0x02, 0x02, 0x00, 0x76, 0x87, 0x56, 0x67,
+        0x78, 0xa9, 0x86, 0x65, 0x89, 0x68, 0x98, 0x01, 0x69, 0x00, 0x00,
+    ];
+
+    #[rustfmt::skip]
+    const INDEX_SEQUENCE_V1: [u8; 13] = [
+        0xd1, 0x00, 0x04, 0xcd, 0x01, 0x04, 0x07, 0x98, 0x1f, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    // position (3 x u16), normal (2 x u8) and uv (2 x u16) of a quad
+    #[rustfmt::skip]
+    const VERTEX_BUFFER: [u16; 24] = [
+        0, 0, 0, 0, 0, 0,
+        300, 0, 0, 0, 500, 0,
+        0, 300, 0, 0, 0, 500,
+        300, 300, 0, 0, 500, 500,
+    ];
+
+    #[rustfmt::skip]
+    const VERTEX_DATA_V0: [u8; 85] = [
+        0xa0, 0x01, 0x3f, 0x00, 0x00, 0x00, 0x58, 0x57, 0x58, 0x01, 0x26, 0x00, 0x00, 0x00, 0x01,
+        0x0c, 0x00, 0x00, 0x00, 0x58, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        0x3f, 0x00, 0x00, 0x00, 0x17, 0x18, 0x17, 0x01, 0x26, 0x00, 0x00, 0x00, 0x01, 0x0c, 0x00,
+        0x00, 0x00, 0x17, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn vertex_codec_v0() {
+        let out = decode(Mode::Attributes, Filter::None, 4, 12, &VERTEX_DATA_V0).unwrap();
+        assert_eq!(out, bytes16(&VERTEX_BUFFER));
+    }
+
+    #[test]
+    fn index_codec_v0() {
+        let out = decode(Mode::Triangles, Filter::None, 12, 4, &INDEX_DATA_V0).unwrap();
+        assert_eq!(u32s(&out), INDEX_BUFFER);
+        let out = decode(Mode::Triangles, Filter::None, 12, 2, &INDEX_DATA_V0).unwrap();
+        assert_eq!(u16s(&out), INDEX_BUFFER.map(|it| it as u16));
+    }
+
+    #[test]
+    fn index_sequence_codec_v1() {
+        let out = decode(Mode::Indices, Filter::None, 6, 4, &INDEX_SEQUENCE_V1).unwrap();
+        assert_eq!(u32s(&out), [0, 1, 51, 2, 49, 1000]);
+    }
+
+    #[test]
+    fn truncated_data_is_rejected() {
+        let truncated = &VERTEX_DATA_V0[..VERTEX_DATA_V0.len() - 1];
+        assert!(decode(Mode::Attributes, Filter::None, 4, 12, truncated).is_err());
+        let truncated = &INDEX_DATA_V0[..INDEX_DATA_V0.len() - 1];
+        assert!(decode(Mode::Triangles, Filter::None, 12, 4, truncated).is_err());
+        let truncated = &INDEX_SEQUENCE_V1[..INDEX_SEQUENCE_V1.len() - 1];
+        assert!(decode(Mode::Indices, Filter::None, 6, 4, truncated).is_err());
+    }
+
+    #[test]
+    fn octahedral_filter_8() {
+        let mut data = [
+            0, 1, 127, 0, 0, 187, 127, 1, 255, 1, 127, 0, 14, 130, 127, 1,
+        ];
+        filter_octahedral_8(&mut data);
+        assert_eq!(
+            data,
+            [0, 1, 127, 0, 0, 159, 82, 1, 255, 1, 127, 0, 1, 130, 241, 1]
+        );
+    }
+
+    #[test]
+    fn octahedral_filter_16() {
+        #[rustfmt::skip]
+        let mut data = bytes16(&[
+            0, 1, 2047, 0,
+            0, 1870, 2047, 1,
+            2017, 1, 2047, 0,
+            14, 1300, 2047, 1,
+        ]);
+        filter_octahedral_16(&mut data);
+        #[rustfmt::skip]
+        assert_eq!(u16s(&data), [
+            0, 16, 32767, 0,
+            0, 32621, 3088, 1,
+            32764, 16, 471, 0,
+            307, 28541, 16093, 1,
+        ]);
+    }
+
+    #[test]
+    fn quaternion_filter() {
+        #[rustfmt::skip]
+        let mut data = bytes16(&[
+            0, 1, 0, 0x7fc,
+            0, 1870, 0, 0x7fd,
+            2017, 1, 0, 0x7fe,
+            14, 1300, 0, 0x7ff,
+        ]);
+        filter_quaternion(&mut data);
+        #[rustfmt::skip]
+        assert_eq!(u16s(&data), [
+            32767, 0, 11, 0,
+            0, 25013, 0, 21166,
+            11, 0, 23504, 22830,
+            158, 14715, 0, 29277,
+        ]);
+    }
+
+    #[test]
+    fn exponential_filter() {
+        let mut data = bytes32(&[0, 0xff000003, 0x02fffff7, 0xfe7fffff]);
+        filter_exponential(&mut data);
+        assert_eq!(u32s(&data), [0, 0x3fc00000, 0xc2100000, 0x49fffffe]);
+    }
+}
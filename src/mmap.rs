@@ -0,0 +1,147 @@
+use std::fs::File;
+use std::ops::Deref;
+use std::path::Path;
+
+/// Read-only view of a whole file. It is memory mapped where we know how to do
+/// that, and read into memory elsewhere.
+pub(crate) struct Mmap {
+    inner: Inner,
+}
+
+impl Mmap {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(Self {
+                inner: Inner::Empty,
+            });
+        }
+        Ok(Self {
+            inner: Inner::map(&file, len)?,
+        })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.inner {
+            Inner::Empty => &[],
+            #[cfg(any(unix, windows))]
+            // SAFETY: the mapping is `len` readable bytes that live until `self` is
+            // dropped. Cooked files, the only ones mapped, are replaced by renaming a
+            // new file over them and never written in place, so the bytes stay put.
+            Inner::Mapped { ptr, len } => unsafe { std::slice::from_raw_parts(*ptr, *len) },
+            #[cfg(not(any(unix, windows)))]
+            Inner::Read(bytes) => bytes,
+        }
+    }
+}
+
+enum Inner {
+    Empty,
+    #[cfg(any(unix, windows))]
+    Mapped {
+        ptr: *const u8,
+        len: usize,
+    },
+    #[cfg(not(any(unix, windows)))]
+    Read(Vec<u8>),
+}
+
+#[cfg(unix)]
+impl Inner {
+    fn map(file: &File, len: usize) -> std::io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: a new private read-only mapping of an open file, which doesn't
+        // alias any memory Rust knows about. The result is checked below.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self::Mapped {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+}
+
+#[cfg(windows)]
+impl Inner {
+    fn map(file: &File, len: usize) -> std::io::Result<Self> {
+        use std::os::windows::io::AsRawHandle;
+        use winapi::um::{handleapi, memoryapi, winnt};
+
+        // SAFETY: the handle is that of an open file, and both results are checked
+        // before they are used. The view is a new read-only region of memory.
+        unsafe {
+            let mapping = memoryapi::CreateFileMappingW(
+                file.as_raw_handle() as _,
+                std::ptr::null_mut(),
+                winnt::PAGE_READONLY,
+                0,
+                0,
+                std::ptr::null(),
+            );
+            if mapping.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            // the view keeps the mapping alive
+            let ptr = memoryapi::MapViewOfFile(mapping, memoryapi::FILE_MAP_READ, 0, 0, len);
+            handleapi::CloseHandle(mapping);
+            if ptr.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            Ok(Self::Mapped {
+                ptr: ptr as *const u8,
+                len,
+            })
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+impl Inner {
+    fn map(mut file: &File, len: usize) -> std::io::Result<Self> {
+        use std::io::Read;
+
+        let mut bytes = Vec::with_capacity(len);
+        file.read_to_end(&mut bytes)?;
+        Ok(Self::Read(bytes))
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Self::Mapped { ptr, len } = self {
+            // SAFETY: `ptr` and `len` are those of the mapping, which is unmapped only
+            // here, and no slices of it outlive `self`.
+            unsafe {
+                libc::munmap(*ptr as *mut _, *len);
+            }
+        }
+        #[cfg(windows)]
+        if let Self::Mapped { ptr, .. } = self {
+            // SAFETY: `ptr` is the start of the view, which is unmapped only here, and
+            // no slices of it outlive `self`.
+            unsafe {
+                winapi::um::memoryapi::UnmapViewOfFile(*ptr as *const _);
+            }
+        }
+    }
+}
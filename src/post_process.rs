@@ -0,0 +1,213 @@
+use std::borrow::Cow;
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::graphics::GraphicsContext;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub(crate) struct PostEffectUniforms {
+    pub values: [f32; 4],
+}
+
+/// The built in post processing passes, and what custom ones are created with.
+pub(crate) struct PostProcessPipelines {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub lut_bind_group_layout: wgpu::BindGroupLayout,
+    /// for the passes that only read the source
+    pub pipeline_layout: wgpu::PipelineLayout,
+    /// uniforms of every pass in a frame, `stride` apart
+    pub buffer: wgpu::Buffer,
+    pub stride: usize,
+    pub sampler: wgpu::Sampler,
+    /// format of the color target
+    pub format: wgpu::TextureFormat,
+    pub fxaa: wgpu::RenderPipeline,
+    pub vignette: wgpu::RenderPipeline,
+    pub color_grading: wgpu::RenderPipeline,
+    pub gamma_contrast: wgpu::RenderPipeline,
+}
+
+impl PostProcessPipelines {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<
+                            PostEffectUniforms,
+                        >() as _),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let lut_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let lut_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout, &lut_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as usize;
+        let stride = std::mem::size_of::<PostEffectUniforms>().next_multiple_of(alignment);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Processing Buffer"),
+            size: (stride * GraphicsContext::MAX_POST_EFFECTS) as _,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Processing Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Owned(format!(
+                "{}{}",
+                GraphicsContext::POST_SHADER_PRELUDE,
+                include_str!("shader/post_effects.wgsl")
+            ))),
+        });
+
+        Self {
+            fxaa: Self::create_pipeline(device, format, &pipeline_layout, &shader, "fs_fxaa"),
+            vignette: Self::create_pipeline(
+                device,
+                format,
+                &pipeline_layout,
+                &shader,
+                "fs_vignette",
+            ),
+            color_grading: Self::create_pipeline(
+                device,
+                format,
+                &lut_pipeline_layout,
+                &shader,
+                "fs_color_grading",
+            ),
+            gamma_contrast: Self::create_pipeline(
+                device,
+                format,
+                &pipeline_layout,
+                &shader,
+                "fs_gamma_contrast",
+            ),
+            bind_group_layout,
+            lut_bind_group_layout,
+            pipeline_layout,
+            buffer,
+            stride,
+            sampler,
+            format,
+        }
+    }
+
+    pub fn create_pipeline(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        entry_point: &str,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Post Processing Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point,
+                targets: &[format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    pub fn bind_group(&self, device: &wgpu::Device, source: &wgpu::TextureView) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &self.buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(std::mem::size_of::<PostEffectUniforms>() as _),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+}
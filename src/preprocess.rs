@@ -0,0 +1,150 @@
+//! `#ifdef`, `#ifndef`, `#else` and `#endif` for WGSL, which has no preprocessor.
+
+use crate::AyudeError;
+
+/// An `#ifdef` or `#ifndef` that wasn't closed yet.
+struct Block {
+    /// whether the lines of the current branch are kept
+    kept: bool,
+    /// whether the block itself is in a kept branch
+    outer: bool,
+    in_else: bool,
+    /// of the directive that opened it, from 1
+    line: usize,
+}
+
+/// Keeps the lines of `source` whose conditions hold with `defines`. Directives
+/// and removed lines are left empty, so errors point at the original lines.
+/// Names that aren't in `defines` are undefined.
+pub(crate) fn preprocess(source: &str, defines: &[&str]) -> Result<String, AyudeError> {
+    let invalid =
+        |line: usize, reason: &str| AyudeError::InvalidShader(format!("line {}: {}", line, reason));
+
+    let mut blocks: Vec<Block> = vec![];
+    let mut output = String::with_capacity(source.len());
+
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        let mut words = line.split_whitespace();
+        let enabled = blocks.iter().all(|it| it.kept);
+        match (words.next(), words.next()) {
+            (Some(directive @ ("#ifdef" | "#ifndef")), name) => {
+                let name = name.ok_or_else(|| invalid(number, "expected a name"))?;
+                let defined = defines.contains(&name);
+                blocks.push(Block {
+                    kept: enabled && defined == (directive == "#ifdef"),
+                    outer: enabled,
+                    in_else: false,
+                    line: number,
+                });
+            }
+            (Some("#else"), _) => {
+                let block = blocks
+                    .last_mut()
+                    .ok_or_else(|| invalid(number, "#else without #ifdef"))?;
+                if block.in_else {
+                    return Err(invalid(number, "second #else in the same block"));
+                }
+                block.kept = block.outer && !block.kept;
+                block.in_else = true;
+            }
+            (Some("#endif"), _) => {
+                blocks
+                    .pop()
+                    .ok_or_else(|| invalid(number, "#endif without #ifdef"))?;
+            }
+            _ if enabled => output.push_str(line),
+            _ => {}
+        }
+        output.push('\n');
+    }
+
+    match blocks.last() {
+        Some(block) => Err(invalid(block.line, "missing #endif")),
+        None => Ok(output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kept(source: &str, defines: &[&str]) -> Vec<String> {
+        preprocess(source, defines)
+            .unwrap()
+            .lines()
+            .filter(|it| !it.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn keeps_lines_of_defined_names() {
+        let source = "a\n#ifdef X\nb\n#endif\n#ifndef X\nc\n#endif\nd";
+        assert_eq!(kept(source, &["X"]), ["a", "b", "d"]);
+        assert_eq!(kept(source, &[]), ["a", "c", "d"]);
+    }
+
+    #[test]
+    fn leaves_directives_as_empty_lines() {
+        let source = "#ifdef X\na\n#else\nb\n#endif\nc";
+        assert_eq!(preprocess(source, &["X"]).unwrap(), "\na\n\n\n\nc\n");
+    }
+
+    #[test]
+    fn else_branches() {
+        let source = "#ifdef X\na\n#else\nb\n#endif";
+        assert_eq!(kept(source, &["X"]), ["a"]);
+        assert_eq!(kept(source, &[]), ["b"]);
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let source =
+            "#ifdef X\n#ifdef Y\na\n#else\nb\n#endif\nc\n#else\n#ifdef Y\nd\n#endif\ne\n#endif";
+        assert_eq!(kept(source, &["X", "Y"]), ["a", "c"]);
+        assert_eq!(kept(source, &["X"]), ["b", "c"]);
+        assert_eq!(kept(source, &["Y"]), ["d", "e"]);
+        assert_eq!(kept(source, &[]), ["e"]);
+    }
+
+    #[test]
+    fn unknown_names_are_undefined() {
+        let source = "#ifdef UNKNOWN\na\n#else\nb\n#endif";
+        assert_eq!(kept(source, &["X"]), ["b"]);
+    }
+
+    #[test]
+    fn unbalanced_directives_name_the_line() {
+        let error = |source| preprocess(source, &[]).unwrap_err().to_string();
+        assert!(error("a\n#else\n").contains("line 2"));
+        assert!(error("a\nb\n#endif\n").contains("line 3"));
+        assert!(error("#ifdef X\n#else\n#else\n#endif").contains("line 3"));
+        assert!(error("a\n#ifdef X\nb\n").contains("line 2"));
+        assert!(error("#ifdef\n#endif").contains("line 1"));
+    }
+
+    // every built-in shader, so directives added to one that isn't preprocessed yet
+    // are caught too
+    #[test]
+    fn built_in_shaders_are_balanced() {
+        let shaders = [
+            include_str!("shader/bloom.wgsl"),
+            include_str!("shader/debug.wgsl"),
+            include_str!("shader/environment_map.wgsl"),
+            include_str!("shader/instance.wgsl"),
+            include_str!("shader/material.wgsl"),
+            include_str!("shader/post.wgsl"),
+            include_str!("shader/post_effects.wgsl"),
+            include_str!("shader/prepass.wgsl"),
+            include_str!("shader/shader.wgsl"),
+            include_str!("shader/shadow.wgsl"),
+            include_str!("shader/skybox.wgsl"),
+            include_str!("shader/ssao.wgsl"),
+            include_str!("shader/tonemap.wgsl"),
+        ];
+        for shader in shaders {
+            preprocess(shader, &[]).unwrap();
+        }
+    }
+}
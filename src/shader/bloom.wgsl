@@ -0,0 +1,98 @@
+// The down and up sampling filters are from "Next Generation Post Processing in
+// Call of Duty: Advanced Warfare" (Jorge Jimenez, 2014).
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+struct Bloom {
+    threshold: f32;
+    // width of the soft transition around the threshold
+    knee: f32;
+    radius: f32;
+};
+[[group(0), binding(0)]]
+var<uniform> bloom: Bloom;
+[[group(0), binding(1)]]
+var source: texture_2d<f32>;
+[[group(0), binding(2)]]
+var source_sampler: sampler;
+
+// a triangle covering the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << u32(1)) & u32(2)), f32(index & u32(2)));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn sample_source(uv: vec2<f32>) -> vec3<f32> {
+    return textureSample(source, source_sampler, uv).rgb;
+}
+
+// 13 taps, weighted as overlapping 2x2 boxes so bright pixels don't flicker
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let x = texel.x;
+    let y = texel.y;
+
+    let a = sample_source(uv + vec2<f32>(-2.0 * x, 2.0 * y));
+    let b = sample_source(uv + vec2<f32>(0.0, 2.0 * y));
+    let c = sample_source(uv + vec2<f32>(2.0 * x, 2.0 * y));
+    let d = sample_source(uv + vec2<f32>(-2.0 * x, 0.0));
+    let e = sample_source(uv);
+    let f = sample_source(uv + vec2<f32>(2.0 * x, 0.0));
+    let g = sample_source(uv + vec2<f32>(-2.0 * x, -2.0 * y));
+    let h = sample_source(uv + vec2<f32>(0.0, -2.0 * y));
+    let i = sample_source(uv + vec2<f32>(2.0 * x, -2.0 * y));
+    let j = sample_source(uv + vec2<f32>(-x, y));
+    let k = sample_source(uv + vec2<f32>(x, y));
+    let l = sample_source(uv + vec2<f32>(-x, -y));
+    let m = sample_source(uv + vec2<f32>(x, -y));
+
+    return e * 0.125
+        + (a + c + g + i) * 0.03125
+        + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+}
+
+[[stage(fragment)]]
+fn fs_prefilter(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = downsample(in.uv);
+
+    // only what is brighter than the threshold glows, with a soft transition
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    soft = soft * soft / (4.0 * bloom.knee + 0.0001);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 0.0001);
+
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_downsample(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+// 3x3 tent filter, blended additively over the larger level
+[[stage(fragment)]]
+fn fs_upsample(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let texel = bloom.radius / vec2<f32>(textureDimensions(source));
+    let x = texel.x;
+    let y = texel.y;
+
+    var color = sample_source(in.uv) * 4.0;
+    color = color + (sample_source(in.uv + vec2<f32>(0.0, y))
+        + sample_source(in.uv + vec2<f32>(0.0, -y))
+        + sample_source(in.uv + vec2<f32>(x, 0.0))
+        + sample_source(in.uv + vec2<f32>(-x, 0.0))) * 2.0;
+    color = color + sample_source(in.uv + vec2<f32>(x, y))
+        + sample_source(in.uv + vec2<f32>(-x, y))
+        + sample_source(in.uv + vec2<f32>(x, -y))
+        + sample_source(in.uv + vec2<f32>(-x, -y));
+
+    return vec4<f32>(color / 16.0, 1.0);
+}
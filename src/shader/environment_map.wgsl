@@ -0,0 +1,194 @@
+// Precomputes the lighting of an environment map, one face of a cube at a time,
+// and the lookup table of the specular BRDF shared by every environment map.
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+struct Uniforms {
+    // +x, -x, +y, -y, +z, -z
+    face: u32;
+    // of the prefiltered mip being rendered
+    roughness: f32;
+    // texels along each side of the source cube
+    source_size: f32;
+    sample_count: u32;
+};
+[[group(0), binding(0)]]
+var<uniform> uniforms: Uniforms;
+
+[[group(1), binding(0)]]
+var source_sampler: sampler;
+// equirectangular
+[[group(1), binding(1)]]
+var source_image: texture_2d<f32>;
+[[group(1), binding(2)]]
+var source_cube: texture_cube<f32>;
+
+let PI: f32 = 3.14159265359;
+
+// a triangle covering the whole target
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << u32(1)) & u32(2)), f32(index & u32(2)));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// direction from the center of the cube through `uv` of the face
+fn cube_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let s = uv.x * 2.0 - 1.0;
+    let t = uv.y * 2.0 - 1.0;
+    var direction: vec3<f32>;
+    switch (i32(face)) {
+        case 0: { direction = vec3<f32>(1.0, -t, -s); }
+        case 1: { direction = vec3<f32>(-1.0, -t, s); }
+        case 2: { direction = vec3<f32>(s, 1.0, t); }
+        case 3: { direction = vec3<f32>(s, -1.0, -t); }
+        case 4: { direction = vec3<f32>(s, -t, 1.0); }
+        default: { direction = vec3<f32>(-s, -t, -1.0); }
+    }
+    return normalize(direction);
+}
+
+// any two directions perpendicular to `normal` and each other
+fn tangent_frame(normal: vec3<f32>) -> mat3x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(normal.y) > 0.999) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return mat3x3<f32>(tangent, bitangent, normal);
+}
+
+[[stage(fragment)]]
+fn fs_from_image(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let direction = cube_direction(uniforms.face, in.uv);
+    let uv = vec2<f32>(
+        0.5 + atan2(direction.z, direction.x) / (2.0 * PI),
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+    // the wrap around in u would pick the smallest mip otherwise
+    return vec4<f32>(textureSampleLevel(source_image, source_sampler, uv, 0.0).rgb, 1.0);
+}
+
+// the source is a single mip, twice the size, so filtering averages 2x2 texels
+[[stage(fragment)]]
+fn fs_downsample(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let direction = cube_direction(uniforms.face, in.uv);
+    return textureSampleLevel(source_cube, source_sampler, direction, 0.0);
+}
+
+// cosine weighted sum of the light around the normal
+[[stage(fragment)]]
+fn fs_irradiance(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let frame = tangent_frame(cube_direction(uniforms.face, in.uv));
+    // a mip around the size of the steps, so the sum doesn't alias
+    let level = max(log2(uniforms.source_size / 32.0), 0.0);
+
+    let steps = i32(uniforms.sample_count);
+    var sum = vec3<f32>(0.0);
+    for (var i: i32 = 0; i < steps * 4; i = i + 1) {
+        for (var j: i32 = 0; j < steps; j = j + 1) {
+            let phi = (f32(i) + 0.5) / f32(steps * 4) * 2.0 * PI;
+            let theta = (f32(j) + 0.5) / f32(steps) * 0.5 * PI;
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let light = textureSampleLevel(source_cube, source_sampler, frame * local, level).rgb;
+            sum = sum + light * cos(theta) * sin(theta);
+        }
+    }
+    return vec4<f32>(PI * sum / f32(steps * steps * 4), 1.0);
+}
+
+// a low discrepancy sequence of points in the unit square
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    var bits = i;
+    bits = (bits << u32(16)) | (bits >> u32(16));
+    bits = ((bits & u32(0x55555555)) << u32(1)) | ((bits >> u32(1)) & u32(0x55555555));
+    bits = ((bits & u32(0x33333333)) << u32(2)) | ((bits >> u32(2)) & u32(0x33333333));
+    bits = ((bits & u32(0x0F0F0F0F)) << u32(4)) | ((bits >> u32(4)) & u32(0x0F0F0F0F));
+    bits = ((bits & u32(0x00FF00FF)) << u32(8)) | ((bits >> u32(8)) & u32(0x00FF00FF));
+    return vec2<f32>(f32(i) / f32(count), f32(bits) * 2.3283064365386963e-10);
+}
+
+// half vector around +z, distributed like the GGX normal distribution
+fn importance_sample_ggx(xi: vec2<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let d = n_dot_h * n_dot_h * (a * a - 1.0) + 1.0;
+    return a * a / (PI * d * d);
+}
+
+// reflections of a surface with the uniform's roughness, looking at it straight on
+[[stage(fragment)]]
+fn fs_prefilter(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let normal = cube_direction(uniforms.face, in.uv);
+    let frame = tangent_frame(normal);
+    let texel_solid_angle = 4.0 * PI / (6.0 * uniforms.source_size * uniforms.source_size);
+
+    var sum = vec3<f32>(0.0);
+    var weight: f32 = 0.0;
+    for (var i: u32 = u32(0); i < uniforms.sample_count; i = i + u32(1)) {
+        let half_vector = frame * importance_sample_ggx(hammersley(i, uniforms.sample_count), uniforms.roughness);
+        let light = normalize(2.0 * dot(normal, half_vector) * half_vector - normal);
+        let n_dot_l = dot(normal, light);
+        if (n_dot_l > 0.0) {
+            // samples covering more than a texel read a smaller mip, so they don't alias
+            let n_dot_h = max(dot(normal, half_vector), 0.0);
+            let pdf = distribution_ggx(n_dot_h, uniforms.roughness) / 4.0 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(uniforms.sample_count) * pdf);
+            var level: f32 = 0.0;
+            if (uniforms.roughness > 0.0) {
+                level = max(0.5 * log2(sample_solid_angle / texel_solid_angle), 0.0);
+            }
+            sum = sum + textureSampleLevel(source_cube, source_sampler, light, level).rgb * n_dot_l;
+            weight = weight + n_dot_l;
+        }
+    }
+    return vec4<f32>(sum / max(weight, 0.0001), 1.0);
+}
+
+fn geometry_schlick_ggx(n_dot_v: f32, roughness: f32) -> f32 {
+    // the remapping of k for image based lighting
+    let k = roughness * roughness / 2.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+// scale and bias of the fresnel reflectance at normal incidence, by the cosine of
+// the view angle in u and the roughness in v
+[[stage(fragment)]]
+fn fs_brdf(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let n_dot_v = max(in.uv.x, 0.001);
+    let roughness = in.uv.y;
+    let view = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    let count = u32(256);
+    var scale: f32 = 0.0;
+    var bias: f32 = 0.0;
+    for (var i: u32 = u32(0); i < count; i = i + u32(1)) {
+        let half_vector = importance_sample_ggx(hammersley(i, count), roughness);
+        let light = normalize(2.0 * dot(view, half_vector) * half_vector - view);
+        let n_dot_l = max(light.z, 0.0);
+        let n_dot_h = max(half_vector.z, 0.0);
+        let v_dot_h = max(dot(view, half_vector), 0.0);
+        if (n_dot_l > 0.0) {
+            let geometry = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            let visibility = geometry * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale = scale + (1.0 - fresnel) * visibility;
+            bias = bias + fresnel * visibility;
+        }
+    }
+    return vec4<f32>(scale / f32(count), bias / f32(count), 0.0, 1.0);
+}
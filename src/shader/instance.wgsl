@@ -0,0 +1,12 @@
+// Shared by the shaders that draw instanced meshes, which start with it.
+
+// the instance's inverse transpose, scaled by its determinant, transforms
+// normals so they stay perpendicular to surfaces under non uniform scales
+fn transform_instance_normal(instance: mat4x4<f32>, normal: vec3<f32>) -> vec3<f32> {
+    let x = instance[0].xyz;
+    let y = instance[1].xyz;
+    let z = instance[2].xyz;
+    let cofactor = mat3x3<f32>(cross(y, z), cross(z, x), cross(x, y));
+    // mirroring instances would flip the normal otherwise
+    return normalize(cofactor * normal) * sign(dot(x, cross(y, z)));
+}
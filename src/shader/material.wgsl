@@ -0,0 +1,78 @@
+// Declarations every custom material shader starts with. The parameters and the
+// textures come from the material's description.
+
+struct VertexOutput {
+    [[location(0)]] tex_coord: vec2<f32>;
+    [[location(1)]] normal: vec3<f32>;
+    [[location(2)]] norpos: vec3<f32>;
+    [[location(3)]] tex_coord_1: vec2<f32>;
+    [[location(4)]] color: vec4<f32>;
+    [[location(5)]] world_position: vec3<f32>;
+    [[builtin(position)]] position: vec4<f32>;
+};
+
+struct MaterialParameters {
+MATERIAL_PARAMETERS
+};
+
+struct Uniforms {
+    mvp: mat4x4<f32>;
+    transpose_inverse_modelview: mat4x4<f32>;
+    model: mat4x4<f32>;
+    base_diffuse_color: vec4<f32>;
+    diffuse_tex_coord: u32;
+    normal_tex_coord: u32;
+    _padding: vec2<u32>;
+};
+[[group(0), binding(0)]]
+var<uniform> uniforms: Uniforms;
+[[group(0), binding(1)]]
+var<uniform> material: MaterialParameters;
+
+struct Environment {
+    sun_direction: vec4<f32>;
+    sun_color: vec4<f32>;
+    ambient_color: vec4<f32>;
+    has_shadows: u32;
+    has_ambient_occlusion: u32;
+    cascade_view_projections: array<mat4x4<f32>, 4>;
+    cascade_splits: vec4<f32>;
+    has_environment_map: u32;
+    environment_intensity: f32;
+    // of the pass's camera
+    view_sun_direction: vec4<f32>;
+    view: mat4x4<f32>;
+};
+[[group(3), binding(0)]]
+var<uniform> environment: Environment;
+
+MATERIAL_TEXTURES
+
+#ifdef STANDARD_VERTEX
+[[stage(vertex)]]
+fn vs_main(
+    [[location(0)]] position: vec4<f32>,
+    [[location(1)]] normal: vec3<f32>,
+    [[location(2)]] tex_coord: vec2<f32>,
+    [[location(3)]] tex_coord_1: vec2<f32>,
+    [[location(4)]] color: vec4<f32>,
+    [[location(5)]] instance_0: vec4<f32>,
+    [[location(6)]] instance_1: vec4<f32>,
+    [[location(7)]] instance_2: vec4<f32>,
+    [[location(8)]] instance_3: vec4<f32>,
+) -> VertexOutput {
+    let instance = mat4x4<f32>(instance_0, instance_1, instance_2, instance_3);
+    let instance_normal = transform_instance_normal(instance, normal);
+
+    var out: VertexOutput;
+    out.normal = (uniforms.transpose_inverse_modelview * vec4<f32>(instance_normal, 0.0)).xyz;
+    out.position = uniforms.mvp * instance * position;
+    out.world_position = (uniforms.model * instance * position).xyz;
+    out.norpos = out.position.xyz / out.position.w;
+    out.tex_coord = tex_coord;
+    out.tex_coord_1 = tex_coord_1;
+    out.color = color;
+    return out;
+}
+#endif
+
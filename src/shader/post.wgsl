@@ -0,0 +1,30 @@
+// Shared by every post processing pass, custom passes are appended to it and
+// define `fs_main`.
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+struct PostEffect {
+    // what they mean depends on the effect
+    values: vec4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> effect: PostEffect;
+// output of the pass before, tone mapped
+[[group(0), binding(1)]]
+var source: texture_2d<f32>;
+[[group(0), binding(2)]]
+var source_sampler: sampler;
+
+// a triangle covering the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << u32(1)) & u32(2)), f32(index & u32(2)));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
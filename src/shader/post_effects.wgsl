@@ -0,0 +1,90 @@
+// The built in post processing passes, appended to post.wgsl.
+
+[[group(1), binding(0)]]
+var lut: texture_3d<f32>;
+[[group(1), binding(1)]]
+var lut_sampler: sampler;
+
+fn sample_source(uv: vec2<f32>) -> vec3<f32> {
+    return textureSample(source, source_sampler, uv).rgb;
+}
+
+// the source holds linear colors, edits like contrast are done on encoded ones
+fn encode_gamma(color: vec3<f32>) -> vec3<f32> {
+    return pow(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(1.0 / 2.2));
+}
+
+fn decode_gamma(color: vec3<f32>) -> vec3<f32> {
+    return pow(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(2.2));
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(encode_gamma(color), vec3<f32>(0.299, 0.587, 0.114));
+}
+
+// FXAA in the style of the console version by Timothy Lottes, the edge direction
+// comes from the corners and the pixel is blurred along it
+[[stage(fragment)]]
+fn fs_fxaa(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    let center = textureSample(source, source_sampler, in.uv);
+    let luma_center = luma(center.rgb);
+    let luma_nw = luma(sample_source(in.uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = luma(sample_source(in.uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = luma(sample_source(in.uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = luma(sample_source(in.uv + vec2<f32>(1.0, 1.0) * texel));
+    let luma_min = min(luma_center, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_center, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var direction = vec2<f32>(
+        (luma_sw + luma_se) - (luma_nw + luma_ne),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * 0.125, 1.0 / 128.0);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + reduce);
+    direction = clamp(direction * scale, vec2<f32>(-8.0), vec2<f32>(8.0)) * texel;
+
+    let inner = 0.5 * (sample_source(in.uv + direction * (1.0 / 3.0 - 0.5))
+        + sample_source(in.uv + direction * (2.0 / 3.0 - 0.5)));
+    let outer = inner * 0.5 + 0.25 * (sample_source(in.uv - direction * 0.5)
+        + sample_source(in.uv + direction * 0.5));
+
+    // the wider blur crossed another edge
+    let luma_outer = luma(outer);
+    if (luma_outer < luma_min || luma_outer > luma_max) {
+        return vec4<f32>(inner, center.a);
+    }
+    return vec4<f32>(outer, center.a);
+}
+
+// x is the intensity, y how smooth the falloff is
+[[stage(fragment)]]
+fn fs_vignette(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    // 0 at the center, 1 at the corners
+    let distance = length(in.uv - 0.5) * 1.4142135;
+    let smoothness = max(effect.values.y, 0.001);
+    let shade = 1.0 - effect.values.x * smoothStep(1.0 - smoothness, 1.0, distance);
+    return vec4<f32>(color.rgb * shade, color.a);
+}
+
+// x is the intensity, the lookup table is indexed with gamma encoded colors
+[[stage(fragment)]]
+fn fs_color_grading(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    let size = f32(textureDimensions(lut).x);
+    // the centers of the first and last texels are the ends of the range
+    let coordinates = encode_gamma(color.rgb) * (size - 1.0) / size + 0.5 / size;
+    let graded = textureSample(lut, lut_sampler, coordinates).rgb;
+    return vec4<f32>(mix(color.rgb, graded, effect.values.x), color.a);
+}
+
+// x is the gamma, y the contrast
+[[stage(fragment)]]
+fn fs_gamma_contrast(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    // contrast pivots around middle grey
+    var encoded = (encode_gamma(color.rgb) - 0.5) * effect.values.y + 0.5;
+    encoded = pow(clamp(encoded, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(1.0 / max(effect.values.x, 0.001)));
+    return vec4<f32>(decode_gamma(encoded), color.a);
+}
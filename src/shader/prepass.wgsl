@@ -0,0 +1,36 @@
+// only the start of the mesh uniforms in shader.wgsl, up to the normal matrix
+struct Uniforms {
+    mvp: mat4x4<f32>;
+    transpose_inverse_modelview: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> uniforms: Uniforms;
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] normal: vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    [[location(0)]] position: vec4<f32>,
+    [[location(1)]] normal: vec3<f32>,
+    [[location(5)]] instance_0: vec4<f32>,
+    [[location(6)]] instance_1: vec4<f32>,
+    [[location(7)]] instance_2: vec4<f32>,
+    [[location(8)]] instance_3: vec4<f32>,
+) -> VertexOutput {
+    let instance = mat4x4<f32>(instance_0, instance_1, instance_2, instance_3);
+    let instance_normal = transform_instance_normal(instance, normal);
+
+    var out: VertexOutput;
+    out.position = uniforms.mvp * instance * position;
+    out.normal = (uniforms.transpose_inverse_modelview * vec4<f32>(instance_normal, 0.0)).xyz;
+    return out;
+}
+
+// view space normals
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(normalize(in.normal), 1.0);
+}
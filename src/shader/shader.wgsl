@@ -1,33 +1,84 @@
+// Compiled once for each combination of `ShaderFeatures` drawn with, which are
+// defined for `#ifdef`.
+
 struct VertexOutput {
     [[location(0)]] tex_coord: vec2<f32>;
     [[location(1)]] normal: vec3<f32>;
     [[location(2)]] norpos: vec3<f32>;
+    [[location(3)]] tex_coord_1: vec2<f32>;
+    [[location(4)]] color: vec4<f32>;
+    [[location(5)]] world_position: vec3<f32>;
     [[builtin(position)]] position: vec4<f32>;
 };
 
 struct Uniforms {
     mvp: mat4x4<f32>;
     transpose_inverse_modelview: mat4x4<f32>;
-    light_direction: vec4<f32>;
+    model: mat4x4<f32>;
     base_diffuse_color: vec4<f32>;
-    has_diffuse_texture: u32;
-    has_normal_texture: u32;
-    shaded: u32;
+    diffuse_tex_coord: u32;
+    normal_tex_coord: u32;
 };
 [[group(0), binding(0)]]
 var<uniform> uniforms: Uniforms;
 
+struct Environment {
+    sun_direction: vec4<f32>;
+    sun_color: vec4<f32>;
+    ambient_color: vec4<f32>;
+    has_shadows: u32;
+    has_ambient_occlusion: u32;
+    cascade_view_projections: array<mat4x4<f32>, 4>;
+    cascade_splits: vec4<f32>;
+    has_environment_map: u32;
+    environment_intensity: f32;
+    // of the pass's camera
+    view_sun_direction: vec4<f32>;
+    view: mat4x4<f32>;
+};
+[[group(3), binding(0)]]
+var<uniform> environment: Environment;
+[[group(3), binding(1)]]
+var shadow_map: texture_depth_2d_array;
+[[group(3), binding(2)]]
+var shadow_sampler: sampler_comparison;
+[[group(3), binding(3)]]
+var ambient_occlusion: texture_2d<f32>;
+// light around each direction of the environment map, diffuse and reflected by
+// rougher surfaces in each mip
+[[group(3), binding(4)]]
+var irradiance_map: texture_cube<f32>;
+[[group(3), binding(5)]]
+var prefiltered_map: texture_cube<f32>;
+// scale and bias of the specular reflectance, by angle and roughness
+[[group(3), binding(6)]]
+var brdf_lut: texture_2d<f32>;
+[[group(3), binding(7)]]
+var environment_sampler: sampler;
+
 [[stage(vertex)]]
 fn vs_main(
     [[location(0)]] position: vec4<f32>,
     [[location(1)]] normal: vec3<f32>,
     [[location(2)]] tex_coord: vec2<f32>,
+    [[location(3)]] tex_coord_1: vec2<f32>,
+    [[location(4)]] color: vec4<f32>,
+    [[location(5)]] instance_0: vec4<f32>,
+    [[location(6)]] instance_1: vec4<f32>,
+    [[location(7)]] instance_2: vec4<f32>,
+    [[location(8)]] instance_3: vec4<f32>,
 ) -> VertexOutput {
+    let instance = mat4x4<f32>(instance_0, instance_1, instance_2, instance_3);
+    let instance_normal = transform_instance_normal(instance, normal);
+
     var out: VertexOutput;
-    out.normal = (uniforms.transpose_inverse_modelview * vec4<f32>(normal, 0.0)).xyz;
-    out.position = uniforms.mvp * position;
+    out.normal = (uniforms.transpose_inverse_modelview * vec4<f32>(instance_normal, 0.0)).xyz;
+    out.position = uniforms.mvp * instance * position;
+    out.world_position = (uniforms.model * instance * position).xyz;
     out.norpos = out.position.xyz / out.position.w;
     out.tex_coord = tex_coord;
+    out.tex_coord_1 = tex_coord_1;
+    out.color = color;
     return out;
 }
 
@@ -56,42 +107,192 @@ var normal_texture: texture_2d<f32>;
 [[group(2), binding(1)]]
 var normal_sampler: sampler;
 
-[[stage(fragment)]]
-fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    if (uniforms.shaded == u32(0)) {
-        var diffuse_color: vec3<f32>;
-        if (uniforms.has_diffuse_texture > u32(0)) {
-            diffuse_color = textureSample(diffuse_texture, diffuse_sampler, in.tex_coord).rgb;
-        } else {
-            diffuse_color = uniforms.base_diffuse_color.rgb;
+// 1 where the sun is visible and 0 where it's occluded, filtered with a 3x3 kernel
+fn sun_visibility(world_position: vec3<f32>) -> f32 {
+    if (environment.has_shadows == u32(0)) {
+        return 1.0;
+    }
+
+    let view_depth = -(environment.view * vec4<f32>(world_position, 1.0)).z;
+    var cascade: i32 = 0;
+    loop {
+        if (cascade >= 4 || view_depth <= environment.cascade_splits[cascade]) {
+            break;
         }
-        return vec4<f32>(diffuse_color, 1.0);
-    } else {
-    
-        var real_normal: vec3<f32>;
-        if (uniforms.has_normal_texture > u32(0)) {
-            real_normal = textureSample(normal_texture, normal_sampler, in.tex_coord).rgb;
-        } else {
-            real_normal = in.normal;
+        cascade = cascade + 1;
+    }
+    if (cascade >= 4) {
+        return 1.0;
+    }
+
+    let light_position = environment.cascade_view_projections[cascade] * vec4<f32>(world_position, 1.0);
+    let ndc = light_position.xyz / light_position.w;
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+    var visibility: f32 = 0.0;
+    for (var x: i32 = -1; x <= 1; x = x + 1) {
+        for (var y: i32 = -1; y <= 1; y = y + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            visibility = visibility + textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, cascade, ndc.z);
         }
+    }
+    return visibility / 9.0;
+}
 
-        let diffuse = max(dot(normalize(real_normal), normalize(uniforms.light_direction.xyz)), 0.0);
+fn select_tex_coord(in: VertexOutput, set: u32) -> vec2<f32> {
+    if (set == u32(1)) {
+        return in.tex_coord_1;
+    }
+    return in.tex_coord;
+}
 
-        let camera_dir = normalize(-in.norpos);
-        let half_direction = normalize(normalize(uniforms.light_direction.xyz) + camera_dir);
-        let tbn = cotangent_frame(in.normal, in.norpos, in.tex_coord);
-        let specular = pow(max(dot(half_direction, normalize(tbn * -(real_normal * 2.0 - 1.0))), 0.0), 16.0);
+fn base_color(in: VertexOutput) -> vec4<f32> {
+#ifdef DIFFUSE_TEXTURE
+    let uv = select_tex_coord(in, uniforms.diffuse_tex_coord);
+    let diffuse_color = textureSample(diffuse_texture, diffuse_sampler, uv);
+#else
+    let diffuse_color = uniforms.base_diffuse_color;
+#endif
+    return diffuse_color * in.color;
+}
 
-        var diffuse_color: vec3<f32>;
-        if (uniforms.has_diffuse_texture > u32(0)) {
-            diffuse_color = textureSample(diffuse_texture, diffuse_sampler, in.tex_coord).rgb;
-        } else {
-            diffuse_color = uniforms.base_diffuse_color.rgb;
-        }
-        let ambient_color = diffuse_color * 0.1;
+// 1 where nothing nearby blocks the ambient light
+fn ambient_visibility(position: vec4<f32>) -> f32 {
+    if (environment.has_ambient_occlusion == u32(0)) {
+        return 1.0;
+    }
+    // passes into render targets read a single texel
+    let pixel = min(vec2<i32>(position.xy), textureDimensions(ambient_occlusion) - 1);
+    return textureLoad(ambient_occlusion, pixel, 0).r;
+}
+
+// surfaces reflect the environment map about as sharply as the highlights of the sun
+let AMBIENT_ROUGHNESS: f32 = 0.4;
 
-        let specular_color = vec3<f32>(1.0, 1.0, 1.0);
-        // return vec4<f32>(1.0, 0.0, 0.0, 1.0);
-        return vec4<f32>(ambient_color + diffuse * diffuse_color + specular * specular_color, 1.0);
+// light from around the surface, diffuse and reflected
+fn ambient_light(in: VertexOutput, diffuse_color: vec3<f32>) -> vec3<f32> {
+    if (environment.has_environment_map == u32(0)) {
+        return diffuse_color * environment.ambient_color.rgb;
     }
-}
\ No newline at end of file
+
+    // the environment map is in world space, and the view matrix is a rotation and
+    // a translation, so its transpose undoes the rotation
+    let view = environment.view;
+    let rotation = transpose(mat3x3<f32>(view[0].xyz, view[1].xyz, view[2].xyz));
+    let normal = normalize(rotation * in.normal);
+    let camera_position = -(rotation * view[3].xyz);
+    let to_camera = normalize(camera_position - in.world_position);
+    let reflection = reflect(-to_camera, normal);
+
+    let irradiance = textureSampleLevel(irradiance_map, environment_sampler, normal, 0.0).rgb;
+    let level = AMBIENT_ROUGHNESS * f32(textureNumLevels(prefiltered_map) - 1);
+    let prefiltered = textureSampleLevel(prefiltered_map, environment_sampler, reflection, level).rgb;
+    let lut_uv = vec2<f32>(max(dot(normal, to_camera), 0.0), AMBIENT_ROUGHNESS);
+    let brdf = textureSampleLevel(brdf_lut, environment_sampler, lut_uv, 0.0).rg;
+    // dielectrics reflect 4% of the light looking at them straight on
+    let specular = prefiltered * (0.04 * brdf.x + brdf.y);
+    return (diffuse_color * irradiance + specular) * environment.environment_intensity;
+}
+
+// lit color of a surface with this base color
+fn shade(in: VertexOutput, base: vec4<f32>) -> vec3<f32> {
+#ifdef SHADED
+    let normal_uv = select_tex_coord(in, uniforms.normal_tex_coord);
+
+#ifdef NORMAL_TEXTURE
+    let real_normal = textureSample(normal_texture, normal_sampler, normal_uv).rgb;
+#else
+    let real_normal = in.normal;
+#endif
+
+    // normals are in view space
+    let sun_direction = environment.view_sun_direction.xyz;
+
+    let diffuse = max(dot(normalize(real_normal), sun_direction), 0.0);
+
+    let camera_dir = normalize(-in.norpos);
+    let half_direction = normalize(sun_direction + camera_dir);
+    let tbn = cotangent_frame(in.normal, in.norpos, normal_uv);
+    let specular = pow(max(dot(half_direction, normalize(tbn * -(real_normal * 2.0 - 1.0))), 0.0), 16.0);
+
+    let diffuse_color = base.rgb;
+    let ambient_color = ambient_light(in, diffuse_color) * ambient_visibility(in.position);
+
+#ifdef RECEIVE_SHADOWS
+    let sun_color = environment.sun_color.rgb * sun_visibility(in.world_position);
+#else
+    let sun_color = environment.sun_color.rgb;
+#endif
+    return ambient_color + (diffuse * diffuse_color + specular) * sun_color;
+#else
+    return base.rgb;
+#endif
+}
+
+#ifdef DEBUG_VIEW
+// what `DebugView` shows instead of the shaded color, exactly one of these is defined
+fn debug_color(in: VertexOutput) -> vec3<f32> {
+#ifdef DEBUG_WIREFRAME
+    return vec3<f32>(0.0, 1.0, 0.5);
+#endif
+#ifdef DEBUG_NORMALS
+    // the view matrix is a rotation and a translation, so its transpose undoes the rotation
+    let view = mat3x3<f32>(environment.view[0].xyz, environment.view[1].xyz, environment.view[2].xyz);
+    let world_normal = transpose(view) * normalize(in.normal);
+    return world_normal * 0.5 + 0.5;
+#endif
+#ifdef DEBUG_TEX_COORDS
+    let uv = select_tex_coord(in, uniforms.diffuse_tex_coord);
+    return vec3<f32>(fract(uv), 0.0);
+#endif
+#ifdef DEBUG_BASE_COLOR
+    return base_color(in).rgb;
+#endif
+#ifdef DEBUG_NORMAL_MAP
+#ifdef NORMAL_TEXTURE
+    let normal_uv = select_tex_coord(in, uniforms.normal_tex_coord);
+    return textureSample(normal_texture, normal_sampler, normal_uv).rgb;
+#else
+    // a flat normal map
+    return vec3<f32>(0.5, 0.5, 1.0);
+#endif
+#endif
+#ifdef DEBUG_LIGHTING
+    return shade(in, vec4<f32>(1.0));
+#endif
+#ifdef DEBUG_OVERDRAW
+    // added up for every draw that covers the pixel
+    return vec3<f32>(0.1, 0.04, 0.01);
+#endif
+#ifdef DEBUG_MIP_LEVEL
+#ifdef DIFFUSE_TEXTURE
+    let uv = select_tex_coord(in, uniforms.diffuse_tex_coord);
+    let texels = uv * vec2<f32>(textureDimensions(diffuse_texture));
+    let dx = dpdx(texels);
+    let dy = dpdy(texels);
+    let last_level = textureNumLevels(diffuse_texture) - 1;
+    let level = clamp(0.5 * log2(max(dot(dx, dx), dot(dy, dy))), 0.0, f32(last_level));
+    // textures without mips have a single level, which is shown as the first
+    let t = level / f32(max(last_level, 1));
+    return vec3<f32>(t, 1.0 - abs(t * 2.0 - 1.0), 1.0 - t);
+#else
+    return vec3<f32>(0.5);
+#endif
+#endif
+}
+#endif
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+#ifdef DEBUG_VIEW
+    return vec4<f32>(debug_color(in), 1.0);
+#else
+    let base = base_color(in);
+    // opaque pipelines don't write alpha
+    return vec4<f32>(shade(in, base), base.a);
+#endif
+}
@@ -0,0 +1,26 @@
+// only the start of the mesh uniforms in shader.wgsl, up to the model matrix
+struct Uniforms {
+    mvp: mat4x4<f32>;
+    transpose_inverse_modelview: mat4x4<f32>;
+    model: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> uniforms: Uniforms;
+
+struct Cascade {
+    view_projection: mat4x4<f32>;
+};
+[[group(1), binding(0)]]
+var<uniform> cascade: Cascade;
+
+[[stage(vertex)]]
+fn vs_main(
+    [[location(0)]] position: vec4<f32>,
+    [[location(5)]] instance_0: vec4<f32>,
+    [[location(6)]] instance_1: vec4<f32>,
+    [[location(7)]] instance_2: vec4<f32>,
+    [[location(8)]] instance_3: vec4<f32>,
+) -> [[builtin(position)]] vec4<f32> {
+    let instance = mat4x4<f32>(instance_0, instance_1, instance_2, instance_3);
+    return cascade.view_projection * uniforms.model * instance * position;
+}
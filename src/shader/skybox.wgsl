@@ -0,0 +1,41 @@
+// Draws an environment map behind everything in a pass.
+
+struct Uniforms {
+    // from clip space to a direction in world space, the view without its
+    // translation
+    inverse_view_projection: mat4x4<f32>;
+    transpose_inverse_modelview: mat4x4<f32>;
+    model: mat4x4<f32>;
+    // brightness of the sky in each channel
+    color: vec4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> uniforms: Uniforms;
+
+[[group(1), binding(0)]]
+var sky_sampler: sampler;
+[[group(1), binding(2)]]
+var sky: texture_cube<f32>;
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] clip: vec2<f32>;
+};
+
+// a triangle covering the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << u32(1)) & u32(2)), f32(index & u32(2)));
+    var out: VertexOutput;
+    out.clip = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    out.position = vec4<f32>(out.clip, 0.0, 1.0);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // any point along the ray through the pixel
+    let point = uniforms.inverse_view_projection * vec4<f32>(in.clip, 0.5, 1.0);
+    let direction = point.xyz / point.w;
+    return vec4<f32>(textureSample(sky, sky_sampler, direction).rgb * uniforms.color.rgb, 1.0);
+}
@@ -0,0 +1,105 @@
+// Hemisphere sampled ambient occlusion, from the depth and normals of the prepass.
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+};
+
+struct AmbientOcclusion {
+    projection: mat4x4<f32>;
+    inverse_projection: mat4x4<f32>;
+    radius: f32;
+    bias: f32;
+    sample_count: u32;
+    // points in the hemisphere around +z
+    kernel: array<vec4<f32>, 64>;
+};
+[[group(0), binding(0)]]
+var<uniform> ambient_occlusion: AmbientOcclusion;
+[[group(0), binding(1)]]
+var depth_texture: texture_depth_2d;
+[[group(0), binding(2)]]
+var normal_texture: texture_2d<f32>;
+
+// read by the blur, which is bound on its own
+[[group(0), binding(3)]]
+var occlusion_texture: texture_2d<f32>;
+
+// a triangle covering the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << u32(1)) & u32(2)), f32(index & u32(2)));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+fn view_position(pixel: vec2<i32>, size: vec2<f32>) -> vec3<f32> {
+    let depth = textureLoad(depth_texture, pixel, 0);
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let position = ambient_occlusion.inverse_projection * ndc;
+    return position.xyz / position.w;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let size = vec2<f32>(textureDimensions(depth_texture));
+    let pixel = vec2<i32>(in.position.xy);
+    // nothing was drawn here
+    if (textureLoad(depth_texture, pixel, 0) >= 1.0) {
+        return vec4<f32>(1.0);
+    }
+
+    let position = view_position(pixel, size);
+    let normal = normalize(textureLoad(normal_texture, pixel, 0).xyz);
+
+    // the kernel is rotated differently in each pixel of a 4x4 tile, the blur
+    // averages the tile back out
+    let tile = (pixel.x & 3) + (pixel.y & 3) * 4;
+    let angle = fract(f32(tile) * 0.618034) * 6.2831853;
+    let random = vec3<f32>(cos(angle), sin(angle), 0.0);
+    var tangent = random - normal * dot(random, normal);
+    if (dot(tangent, tangent) < 0.0001) {
+        tangent = vec3<f32>(0.0, 0.0, 1.0) - normal * normal.z;
+    }
+    tangent = normalize(tangent);
+    let tbn = mat3x3<f32>(tangent, cross(normal, tangent), normal);
+
+    var occlusion = 0.0;
+    let radius = ambient_occlusion.radius;
+    for (var i: u32 = u32(0); i < ambient_occlusion.sample_count; i = i + u32(1)) {
+        let sample_position = position + tbn * ambient_occlusion.kernel[i].xyz * radius;
+
+        let clip = ambient_occlusion.projection * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        let sample_pixel = clamp(vec2<i32>(uv * size), vec2<i32>(0), vec2<i32>(size) - 1);
+        let scene_position = view_position(sample_pixel, size);
+
+        // what is much closer to the camera than the radius doesn't occlude
+        let range = smoothStep(0.0, 1.0, radius / abs(position.z - scene_position.z));
+        if (scene_position.z >= sample_position.z + ambient_occlusion.bias) {
+            occlusion = occlusion + range;
+        }
+    }
+
+    let visibility = 1.0 - occlusion / f32(max(ambient_occlusion.sample_count, u32(1)));
+    return vec4<f32>(visibility, visibility, visibility, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_blur(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let size = textureDimensions(occlusion_texture);
+    let pixel = vec2<i32>(in.position.xy);
+
+    var sum = 0.0;
+    for (var x: i32 = -2; x < 2; x = x + 1) {
+        for (var y: i32 = -2; y < 2; y = y + 1) {
+            let offset = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            sum = sum + textureLoad(occlusion_texture, offset, 0).r;
+        }
+    }
+
+    let visibility = sum / 16.0;
+    return vec4<f32>(visibility, visibility, visibility, 1.0);
+}
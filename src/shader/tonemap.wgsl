@@ -0,0 +1,99 @@
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+};
+
+struct ToneMapping {
+    exposure: f32;
+    // 0 clamps, 1 is Reinhard, 2 is ACES, 3 is AgX
+    operator: u32;
+    // 0 when bloom is disabled
+    bloom_intensity: f32;
+};
+[[group(0), binding(0)]]
+var<uniform> tone_mapping: ToneMapping;
+[[group(0), binding(1)]]
+var hdr_target: texture_2d<f32>;
+[[group(0), binding(2)]]
+var bloom: texture_2d<f32>;
+[[group(0), binding(3)]]
+var bloom_sampler: sampler;
+
+// a triangle covering the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << u32(1)) & u32(2)), f32(index & u32(2)));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (color + 1.0);
+}
+
+// fit of the ACES filmic curve by Krzysztof Narkowicz
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// minimal AgX by Benjamin Wrensch, with the default look
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2
+        - 40.14 * x4 * x
+        + 31.96 * x4
+        - 6.868 * x2 * x
+        + 0.4298 * x2
+        + 0.1191 * x
+        - 0.00232;
+}
+
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var v = inset * color;
+    v = clamp(log2(max(v, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    v = (v - min_ev) / (max_ev - min_ev);
+    v = agx_contrast(v);
+    v = outset * v;
+    // the curve outputs gamma encoded colors, the target expects linear ones
+    return pow(max(v, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let hdr = textureLoad(hdr_target, vec2<i32>(in.position.xy), 0);
+    var color = hdr.rgb;
+    // the bloom texture is stale when it's disabled
+    if (tone_mapping.bloom_intensity > 0.0) {
+        let uv = in.position.xy / vec2<f32>(textureDimensions(hdr_target));
+        color = color + textureSample(bloom, bloom_sampler, uv).rgb * tone_mapping.bloom_intensity;
+    }
+    color = color * tone_mapping.exposure;
+
+    var mapped: vec3<f32>;
+    switch (tone_mapping.operator) {
+        case 1: { mapped = reinhard(color); }
+        case 2: { mapped = aces(color); }
+        case 3: { mapped = agx(color); }
+        default: { mapped = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)); }
+    }
+    return vec4<f32>(mapped, hdr.a);
+}
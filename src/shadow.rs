@@ -0,0 +1,90 @@
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+use crate::graphics::{Environment, GraphicsContext};
+
+pub(crate) struct ShadowCascade {
+    /// view projection matrix of the light
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    /// the layer of the shadow map this cascade renders to
+    pub view: wgpu::TextureView,
+}
+
+pub(crate) struct ShadowCascades {
+    pub view_projections: [Mat4; GraphicsContext::SHADOW_CASCADES],
+    pub splits: [f32; GraphicsContext::SHADOW_CASCADES],
+}
+
+impl ShadowCascades {
+    /// Splits the camera frustum up to the shadow distance into slices, and fits an
+    /// orthographic projection from the sun around each of them.
+    pub fn fit(environment: &Environment, perspective: Mat4, view: Mat4) -> Self {
+        const COUNT: usize = GraphicsContext::SHADOW_CASCADES;
+        // splits are a mix of uniform and logarithmic
+        const LOGARITHMIC_WEIGHT: f32 = 0.6;
+        const NEAR: f32 = 0.1;
+
+        let far = environment.shadow_distance.max(NEAR * 2.0);
+        let mut splits = [0.0; COUNT];
+        for (i, split) in splits.iter_mut().enumerate() {
+            let p = (i + 1) as f32 / COUNT as f32;
+            let uniform = NEAR + (far - NEAR) * p;
+            let logarithmic = NEAR * (far / NEAR).powf(p);
+            *split = uniform + (logarithmic - uniform) * LOGARITHMIC_WEIGHT;
+        }
+
+        // size of the frustum at a distance of 1, from the projection
+        let tan_half_fov = Vec2::new(1.0 / perspective.x_axis.x, 1.0 / perspective.y_axis.y);
+        let inverse_view = view.inverse();
+        let sun_direction = environment.sun_direction.normalize();
+        let up = if sun_direction.y.abs() > 0.99 {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        };
+        let texels = GraphicsContext::SHADOW_MAP_SIZE as f32 / 2.0;
+
+        let mut view_projections = [Mat4::IDENTITY; COUNT];
+        for (i, view_projection) in view_projections.iter_mut().enumerate() {
+            let start = if i == 0 { 0.0 } else { splits[i - 1] };
+            let end = splits[i];
+
+            let mut corners = [Vec3::ZERO; 8];
+            for (j, corner) in corners.iter_mut().enumerate() {
+                let distance = if j < 4 { start } else { end };
+                let x = if j & 1 == 0 { -1.0 } else { 1.0 };
+                let y = if j & 2 == 0 { -1.0 } else { 1.0 };
+                let xy = Vec2::new(x, y) * tan_half_fov * distance;
+                *corner = inverse_view.transform_point3(xy.extend(-distance));
+            }
+
+            // a bounding sphere keeps the projection the same size when the camera rotates
+            let center = corners.iter().fold(Vec3::ZERO, |sum, it| sum + *it) / 8.0;
+            let radius = corners
+                .iter()
+                .map(|it| it.distance(center))
+                .fold(0.0, f32::max);
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            // casters between the sun and the slice must fit in the depth range too
+            let eye = center + sun_direction * (radius + far);
+            let light_view = Mat4::look_at_rh(eye, center, up);
+            let projection =
+                Mat4::orthographic_rh(-radius, radius, -radius, radius, 0.0, 2.0 * radius + far);
+            let mut matrix = projection * light_view;
+
+            // moving in whole texels avoids shimmering edges when the camera moves
+            let origin = (matrix * Vec4::W).truncate().truncate() * texels;
+            let offset = (origin.round() - origin) / texels;
+            matrix.w_axis.x += offset.x;
+            matrix.w_axis.y += offset.y;
+
+            *view_projection = matrix;
+        }
+
+        Self {
+            view_projections,
+            splits,
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Renders every sample with the software adapter and compares the frame against
+//! the reference images in `tests/golden`. Failures write the rendered frame and
+//! a diff image to the target tmp directory.
+//!
+//! They are skipped when there is no adapter. Run with `AYUDE_BLESS=1` to write the
+//! current output as the new references.
+
+use std::path::{Path, PathBuf};
+
+use ayude::{camera::Camera, graphics::GraphicsContext, import_gltf, AssetCache, AyudeError};
+use glam::Vec3;
+use image::{Rgba, RgbaImage};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+/// Pixels whose perceptual color difference is above this (0 to 1) count as different.
+const COLOR_THRESHOLD: f32 = 0.1;
+/// Fraction of different pixels that is still accepted, for rasterization differences
+/// between adapters.
+const MAX_DIFFERENT_PIXELS: f32 = 0.005;
+
+#[test]
+fn knight() {
+    let camera = Camera::new(Vec3::new(0.0, 0.0, 37.0), std::f32::consts::PI, 0.0);
+    check_sample("knight", "samples/knight/knight.gltf", camera);
+}
+
+#[test]
+fn sphere() {
+    let camera = Camera::new(Vec3::new(0.0, 0.0, 5.0), std::f32::consts::PI, 0.0);
+    check_sample("sphere", "samples/sphere.gltf", camera);
+}
+
+#[test]
+fn big_generator() {
+    let camera = Camera::new(Vec3::new(0.0, 80.0, 700.0), std::f32::consts::PI, 0.0);
+    check_sample("big_generator", "samples/big_generator/scene.gltf", camera);
+}
+
+#[test]
+fn principito_y_el_aviador() {
+    let camera = Camera::new(Vec3::new(0.0, 0.0, 30.0), std::f32::consts::PI, 0.0);
+    check_sample(
+        "principito_y_el_aviador",
+        "samples/principito_y_el_aviador/scene.gltf",
+        camera,
+    );
+}
+
+#[test]
+fn physicstest() {
+    let camera = Camera::new(Vec3::new(0.0, 6.0, 30.0), std::f32::consts::PI, 0.0);
+    check_sample("physicstest", "samples/physicstest.gltf", camera);
+}
+
+fn check_sample(name: &str, file_name: &str, camera: Camera) {
+    let graphics = GraphicsContext::new_headless(WIDTH, HEIGHT, true);
+    let mut graphics = match pollster::block_on(graphics) {
+        Ok(it) => it,
+        Err(AyudeError::AdapterNotFound) => {
+            eprintln!("'{}' skipped, there is no adapter", name);
+            return;
+        }
+        Err(e) => panic!("'{}' can't be rendered: {}", name, e),
+    };
+
+    let actual = render_sample(&mut graphics, file_name, &camera);
+
+    let reference_path = Path::new("tests/golden").join(format!("{}.png", name));
+    if std::env::var_os("AYUDE_BLESS").is_some() {
+        std::fs::create_dir_all("tests/golden").unwrap();
+        actual.save(&reference_path).unwrap();
+        return;
+    }
+
+    let output_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&output_dir).unwrap();
+    let actual_path = output_dir.join(format!("{}.actual.png", name));
+
+    let reference = match image::open(&reference_path) {
+        Ok(it) => it.into_rgba8(),
+        Err(e) => {
+            actual.save(&actual_path).unwrap();
+            panic!(
+                "reference image '{}' could not be read ({}), run with AYUDE_BLESS=1 to create it",
+                reference_path.display(),
+                e
+            );
+        }
+    };
+
+    assert_eq!(
+        reference.dimensions(),
+        actual.dimensions(),
+        "reference image '{}' has the wrong size",
+        reference_path.display()
+    );
+
+    let (diff, different_pixels) = compare(&reference, &actual);
+    let different_fraction = different_pixels as f32 / (WIDTH * HEIGHT) as f32;
+    if different_fraction > MAX_DIFFERENT_PIXELS {
+        let diff_path = output_dir.join(format!("{}.diff.png", name));
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+        panic!(
+            "'{}' differs from its reference in {} pixels ({:.2}%), see '{}' and '{}'",
+            name,
+            different_pixels,
+            different_fraction * 100.0,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+fn render_sample(graphics: &mut GraphicsContext, file_name: &str, camera: &Camera) -> RgbaImage {
+    let mut assets = AssetCache::new();
+    let scene = import_gltf::import_default_scene(file_name, graphics, &mut assets).unwrap();
+
+    // same projection as the viewer
+    let perspective = glam::Mat4::perspective_rh_gl(
+        std::f32::consts::PI / 3.0,
+        WIDTH as f32 / HEIGHT as f32,
+        0.1,
+        1024.0,
+    );
+    let view = camera.view();
+
+    let mut frame = graphics.get_current_frame();
+    for cascade in 0..frame.fit_shadow_cascades(perspective, view) {
+        let mut pass = frame.begin_shadow_pass(cascade);
+        scene.render_shadows(&mut pass);
+    }
+    {
+        let mut pass = frame.begin_render_pass(perspective, view);
+        scene.render(&mut pass);
+    }
+    frame.submit();
+
+    graphics.read_target().unwrap()
+}
+
+/// Counts the pixels that differ perceptually, using the YIQ color distance from
+/// "Measuring perceived color difference using YIQ NTSC transmission color space in
+/// mobile applications" (Kotsarenko and Ramos, 2010). The diff image shows the
+/// reference faded out, with different pixels in red.
+fn compare(reference: &RgbaImage, actual: &RgbaImage) -> (RgbaImage, usize) {
+    // largest possible distance, between black and white
+    const MAX_DELTA: f32 = 35215.0;
+
+    let mut diff = RgbaImage::new(reference.width(), reference.height());
+    let mut different_pixels = 0;
+    for (x, y, expected) in reference.enumerate_pixels() {
+        let delta = color_delta(expected, actual.get_pixel(x, y));
+        if delta > MAX_DELTA * COLOR_THRESHOLD * COLOR_THRESHOLD {
+            different_pixels += 1;
+            diff.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        } else {
+            let [r, g, b, _] = expected.0.map(f32::from);
+            let gray = (255.0 - 0.1 * (255.0 - (r * 0.299 + g * 0.587 + b * 0.114))) as u8;
+            diff.put_pixel(x, y, Rgba([gray, gray, gray, 255]));
+        }
+    }
+    (diff, different_pixels)
+}
+
+fn color_delta(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    // colors are blended with white by their alpha first
+    let blend = |it: &Rgba<u8>| {
+        let [r, g, b, a] = it.0.map(f32::from);
+        let a = a / 255.0;
+        [
+            255.0 + (r - 255.0) * a,
+            255.0 + (g - 255.0) * a,
+            255.0 + (b - 255.0) * a,
+        ]
+    };
+    let [r1, g1, b1] = blend(a);
+    let [r2, g2, b2] = blend(b);
+
+    let y = |r: f32, g: f32, b: f32| r * 0.2988953 + g * 0.5866225 + b * 0.11448223;
+    let i = |r: f32, g: f32, b: f32| r * 0.59597799 - g * 0.2741761 - b * 0.3218019;
+    let q = |r: f32, g: f32, b: f32| r * 0.21147017 - g * 0.5226171 + b * 0.31114694;
+
+    let dy = y(r1, g1, b1) - y(r2, g2, b2);
+    let di = i(r1, g1, b1) - i(r2, g2, b2);
+    let dq = q(r1, g1, b1) - q(r2, g2, b2);
+    0.5053 * dy * dy + 0.299 * di * di + 0.1957 * dq * dq
+}